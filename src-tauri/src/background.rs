@@ -0,0 +1,69 @@
+//! Launch-at-login and background mode.
+//!
+//! Relay connections live in the main webview, so "staying connected" in
+//! background mode means hiding the main window on close instead of
+//! destroying it. The app then lives in the tray until "Open" or "Quit".
+//! When started at login the app passes [`BACKGROUND_ARG`] and starts hidden.
+
+use tauri::plugin::TauriPlugin;
+use tauri::{AppHandle, Manager, State, Window, WindowEvent, Wry};
+use tauri_plugin_autostart::{MacosLauncher, ManagerExt};
+
+use crate::error::{AppError, AppResult};
+use crate::settings::SettingsState;
+use crate::windows::MAIN_WINDOW;
+
+pub const BACKGROUND_ARG: &str = "--background";
+
+pub fn autostart_plugin() -> TauriPlugin<Wry> {
+    tauri_plugin_autostart::init(MacosLauncher::LaunchAgent, Some(vec![BACKGROUND_ARG]))
+}
+
+/// Whether this process was started at login and should stay in the tray.
+pub fn started_in_background() -> bool {
+    std::env::args().any(|arg| arg == BACKGROUND_ARG)
+}
+
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    let WindowEvent::CloseRequested { api, .. } = event else {
+        return;
+    };
+    if window.label() != MAIN_WINDOW {
+        return;
+    }
+    let background_mode = window
+        .try_state::<SettingsState>()
+        .is_some_and(|s| s.get().app.background_mode);
+    if background_mode {
+        api.prevent_close();
+        let _ = window.hide();
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn app_set_background_mode(
+    app: AppHandle,
+    enabled: bool,
+    settings: State<'_, SettingsState>,
+) -> AppResult<()> {
+    settings.update(&app, |s| s.app.background_mode = enabled)?;
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn app_set_launch_at_login(
+    app: AppHandle,
+    enabled: bool,
+    settings: State<'_, SettingsState>,
+) -> AppResult<()> {
+    let autolaunch = app.autolaunch();
+    if enabled {
+        autolaunch.enable().map_err(AppError::platform)?;
+    } else {
+        autolaunch.disable().map_err(AppError::platform)?;
+    }
+    settings.update(&app, |s| s.app.launch_at_login = enabled)?;
+    Ok(())
+}
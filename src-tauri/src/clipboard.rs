@@ -0,0 +1,48 @@
+//! Clipboard copies of secrets (nsec, fingerprints) that clear themselves.
+//!
+//! Only a hash of the copied value is kept, and the clipboard is only
+//! cleared if it still holds that value when the TTL expires, so anything
+//! the user copied in the meantime is left alone.
+
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use tauri::AppHandle;
+use tauri_plugin_clipboard_manager::ClipboardExt;
+
+use crate::error::{AppError, AppResult};
+
+const DEFAULT_TTL_SECS: u64 = 30;
+const MAX_TTL_SECS: u64 = 600;
+
+fn digest(value: &str) -> [u8; 32] {
+    Sha256::digest(value.as_bytes()).into()
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn clipboard_copy_secret(
+    app: AppHandle,
+    value: String,
+    ttl_secs: Option<u64>,
+) -> AppResult<()> {
+    let ttl = ttl_secs.unwrap_or(DEFAULT_TTL_SECS).clamp(1, MAX_TTL_SECS);
+    let copied = digest(&value);
+    app.clipboard()
+        .write_text(value)
+        .map_err(AppError::platform)?;
+
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(ttl)).await;
+        let unchanged = app
+            .clipboard()
+            .read_text()
+            .is_ok_and(|current| digest(&current) == copied);
+        if unchanged {
+            if let Err(e) = app.clipboard().clear() {
+                tracing::warn!("failed to clear copied secret: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! Error envelope returned by every command.
+//!
+//! Errors reach the frontend as `{ code, params }` so it can branch on the
+//! code (retry on `RelayTimeout`, re-prompt on `InvalidShortcut`, ...) and
+//! build a localized message from the params instead of showing backend
+//! strings. `params.message`, where present, is untranslated detail from the
+//! underlying library, meant for logs and bug reports.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Serialize;
+use specta::Type;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Type)]
+pub enum ErrorCode {
+    InvalidArgument,
+    InvalidCoordinates,
+    InvalidGeohash,
+    InvalidPrecision,
+    InvalidRelayUrl,
+    InvalidSettings,
+    InvalidShortcut,
+    RelayTimeout,
+    RelayUnreachable,
+    RelayInvalidResponse,
+    FileTooLarge,
+    NotAFile,
+    UnknownFileHandle,
+    SecureStoreUnavailable,
+    BluetoothUnavailable,
+    UnknownLink,
+    UnknownContact,
+    InvalidPeerId,
+    MalformedPacket,
+    PayloadTooLarge,
+    UnsupportedVersion,
+    /// No transport can reach the peer right now.
+    PeerUnreachable,
+    /// Too many links are still connecting; `params.max` says how many.
+    TooManyPendingLinks,
+    /// Not available on this OS or hardware; `params.feature` says what.
+    Unsupported,
+    Io,
+    /// The message history database failed or couldn't be opened.
+    Storage,
+    /// A Tauri or OS integration (window, tray, clipboard, ...) failed.
+    Platform,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct AppError {
+    pub code: ErrorCode,
+    pub params: BTreeMap<String, String>,
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    pub fn new(code: ErrorCode) -> Self {
+        Self {
+            code,
+            params: BTreeMap::new(),
+        }
+    }
+
+    pub fn with(mut self, key: &str, value: impl ToString) -> Self {
+        self.params.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn platform(err: impl fmt::Display) -> Self {
+        Self::new(ErrorCode::Platform).with("message", err)
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.code)?;
+        for (i, (key, value)) in self.params.iter().enumerate() {
+            let sep = if i == 0 { " (" } else { ", " };
+            write!(f, "{}{}={}", sep, key, value)?;
+        }
+        if !self.params.is_empty() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        Self::new(ErrorCode::Io).with("message", err)
+    }
+}
+
+impl From<tauri::Error> for AppError {
+    fn from(err: tauri::Error) -> Self {
+        Self::platform(err)
+    }
+}
@@ -0,0 +1,67 @@
+//! Single typed event stream from the backend to the webview.
+//!
+//! Every backend subsystem reports to the frontend through [`emit`], which
+//! sends a [`BackendEvent`] on the `bitchat://event` channel. Payloads are
+//! tagged by subsystem and then by event type:
+//!
+//! ```json
+//! { "subsystem": "app", "type": "panic_wipe_requested" }
+//! { "subsystem": "app", "type": "online_requested", "online": false }
+//! ```
+//!
+//! Subsystems add a variant here (with its own event enum) rather than
+//! emitting on ad-hoc channel names. Events go to every window unless sent
+//! with [`emit_to`].
+
+use serde::Serialize;
+use specta::Type;
+use tauri::{AppHandle, Emitter, EventTarget};
+
+use crate::favorites::FavoritesEvent;
+use crate::files::FileEvent;
+use crate::mesh::MeshEvent;
+use crate::settings::SettingsEvent;
+use crate::storage::contacts::ContactsEvent;
+use crate::transport::TransportEvent;
+
+pub const CHANNEL: &str = "bitchat://event";
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(tag = "subsystem", rename_all = "snake_case")]
+pub enum BackendEvent {
+    App(AppEvent),
+    Contacts(ContactsEvent),
+    Favorites(FavoritesEvent),
+    Files(FileEvent),
+    Mesh(MeshEvent),
+    Settings(SettingsEvent),
+    Transport(TransportEvent),
+}
+
+/// Requests from native UI (tray menu, shortcuts) that the frontend acts on.
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AppEvent {
+    /// The user asked to go online or offline.
+    OnlineRequested { online: bool },
+    /// The user asked to wipe all local data immediately.
+    PanicWipeRequested,
+    /// A message was submitted from the quick compose window.
+    ComposeSubmitted { target: String, content: String },
+}
+
+impl From<AppEvent> for BackendEvent {
+    fn from(event: AppEvent) -> Self {
+        BackendEvent::App(event)
+    }
+}
+
+pub fn emit(app: &AppHandle, event: impl Into<BackendEvent>) {
+    // Only fails if the webview is gone, in which case nobody is listening.
+    let _ = app.emit(CHANNEL, event.into());
+}
+
+/// Send to a single window, for events that must be handled exactly once.
+pub fn emit_to(app: &AppHandle, label: &str, event: impl Into<BackendEvent>) {
+    let _ = app.emit_to(EventTarget::webview_window(label), CHANNEL, event.into());
+}
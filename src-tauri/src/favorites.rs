@@ -0,0 +1,381 @@
+//! Favorite peers, and the Nostr keys that let us reach them off-mesh.
+//!
+//! Favorites are keyed by Noise static public key, which unlike the mesh
+//! peer ID survives restarts, and persisted as `favorites.json` in the app
+//! data directory. Favoriting a peer sends it a private message in the
+//! native apps' format, `[FAVORITED]:<npub>` or `[UNFAVORITED]:<npub>`,
+//! carrying our Nostr key. Once both sides have favorited each other the
+//! router may reach the peer over Nostr when it's out of range.
+//!
+//! Notifications travel like any other private message, so only the
+//! frontend can decrypt them. It hands them over with
+//! [`favorites_notification_received`] together with the Noise key the
+//! session authenticated, which is what they're recorded under. One that
+//! came over the mesh is dropped unless the sender announced that key.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{AppHandle, Manager, State};
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events::{self, BackendEvent};
+use crate::mesh::packet::PeerId;
+use crate::mesh::peers::PeerInfo;
+use crate::mesh::{self, now_ms};
+use crate::storage::contacts;
+use crate::transport::router::{Recipient, Router};
+
+pub const FILE_NAME: &str = "favorites.json";
+
+const FAVORITED: &str = "[FAVORITED]:";
+const UNFAVORITED: &str = "[UNFAVORITED]:";
+const BECH32_CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+pub struct Favorite {
+    /// Hex-encoded Noise static public key.
+    pub noise_public_key: String,
+    pub nickname: String,
+    /// The peer's Nostr public key, once they've shared it.
+    pub npub: Option<String>,
+    /// We've favorited them.
+    pub is_favorite: bool,
+    /// They've favorited us.
+    pub favorited_us: bool,
+    /// Mesh peer ID they were last seen with.
+    pub last_peer_id: Option<String>,
+    /// Milliseconds since the epoch.
+    pub updated_at: u64,
+}
+
+impl Favorite {
+    pub fn is_mutual(&self) -> bool {
+        self.is_favorite && self.favorited_us
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FavoritesEvent {
+    /// `favorite` changed. It is gone once neither side favorites the other.
+    Changed { favorite: Favorite },
+}
+
+impl From<FavoritesEvent> for BackendEvent {
+    fn from(event: FavoritesEvent) -> Self {
+        BackendEvent::Favorites(event)
+    }
+}
+
+pub struct FavoritesState {
+    path: PathBuf,
+    /// Noise public key (hex) -> favorite.
+    favorites: Mutex<BTreeMap<String, Favorite>>,
+}
+
+impl FavoritesState {
+    /// Load favorites from `path`, starting empty if the file is missing or
+    /// unreadable.
+    pub fn load(path: PathBuf) -> Self {
+        let favorites = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| match serde_json::from_str::<Vec<Favorite>>(&json) {
+                Ok(list) => Some(list),
+                Err(e) => {
+                    tracing::warn!("ignoring invalid favorites file: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default()
+            .into_iter()
+            .map(|f| (f.noise_public_key.clone(), f))
+            .collect();
+        Self {
+            path,
+            favorites: Mutex::new(favorites),
+        }
+    }
+
+    pub fn list(&self) -> Vec<Favorite> {
+        self.favorites.lock().unwrap().values().cloned().collect()
+    }
+
+    fn get(&self, noise_public_key: &str) -> Option<Favorite> {
+        self.favorites
+            .lock()
+            .unwrap()
+            .get(noise_public_key)
+            .cloned()
+    }
+
+    /// Apply `f` to the entry for `noise_public_key` (created if missing),
+    /// then persist and publish the result.
+    fn update(
+        &self,
+        app: &AppHandle,
+        noise_public_key: &str,
+        f: impl FnOnce(&mut Favorite),
+    ) -> AppResult<Favorite> {
+        let mut favorites = self.favorites.lock().unwrap();
+        let mut favorite = favorites
+            .get(noise_public_key)
+            .cloned()
+            .unwrap_or_else(|| Favorite {
+                noise_public_key: noise_public_key.to_string(),
+                ..Favorite::default()
+            });
+        f(&mut favorite);
+        favorite.updated_at = now_ms();
+        if favorite.is_favorite || favorite.favorited_us {
+            favorites.insert(noise_public_key.to_string(), favorite.clone());
+        } else {
+            favorites.remove(noise_public_key);
+        }
+        self.save(&favorites)?;
+        events::emit(
+            app,
+            FavoritesEvent::Changed {
+                favorite: favorite.clone(),
+            },
+        );
+        Ok(favorite)
+    }
+
+    fn save(&self, favorites: &BTreeMap<String, Favorite>) -> AppResult<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let list: Vec<&Favorite> = favorites.values().collect();
+        let json = serde_json::to_string_pretty(&list)
+            .map_err(|e| AppError::new(ErrorCode::Io).with("message", e))?;
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        Ok(fs::rename(&tmp, &self.path)?)
+    }
+}
+
+pub(crate) fn is_npub(s: &str) -> bool {
+    s.len() == 63 && s.starts_with("npub1") && s[5..].chars().all(|c| BECH32_CHARSET.contains(c))
+}
+
+/// The npub to message `recipient` at over Nostr, if it's a mutual
+/// favorite that has shared one.
+pub fn nostr_route(app: &AppHandle, recipient: &Recipient) -> Option<String> {
+    let state = app.state::<FavoritesState>();
+    let favorites = state.favorites.lock().unwrap();
+    route(&favorites, recipient)
+}
+
+fn route(favorites: &BTreeMap<String, Favorite>, recipient: &Recipient) -> Option<String> {
+    let favorite = match recipient {
+        Recipient::NoiseKey(key) => favorites.get(&hex::encode(key)),
+        Recipient::Peer(peer_id) => {
+            let peer_id = peer_id.to_string();
+            favorites
+                .values()
+                .find(|f| f.last_peer_id.as_deref() == Some(peer_id.as_str()))
+        }
+    }?;
+    favorite.npub.clone().filter(|_| favorite.is_mutual())
+}
+
+/// Whether `npub` is a mutual favorite's, i.e. one [`nostr_route`] may
+/// return.
+pub fn is_mutual_npub(app: &AppHandle, npub: &str) -> bool {
+    let state = app.state::<FavoritesState>();
+    let favorites = state.favorites.lock().unwrap();
+    favorites
+        .values()
+        .any(|f| f.is_mutual() && f.npub.as_deref() == Some(npub))
+}
+
+/// Re-apply favorite status to a peer that has just announced itself.
+pub fn peer_discovered(app: &AppHandle, peer: &PeerInfo) {
+    let Some(noise_public_key) = &peer.noise_public_key else {
+        return;
+    };
+    let state = app.state::<FavoritesState>();
+    let Some(favorite) = state.get(noise_public_key) else {
+        return;
+    };
+    if let Ok(peer_id) = peer.peer_id.parse::<PeerId>() {
+        mesh::set_favorite(app, peer_id, favorite.is_favorite);
+    }
+    if favorite.last_peer_id.as_ref() != Some(&peer.peer_id) || favorite.nickname != peer.nickname {
+        let result = state.update(app, noise_public_key, |f| {
+            f.nickname = peer.nickname.clone();
+            f.last_peer_id = Some(peer.peer_id.clone());
+        });
+        if let Err(e) = result {
+            tracing::warn!("can't save favorite: {}", e);
+        }
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn favorites_list(state: State<'_, FavoritesState>) -> Vec<Favorite> {
+    state.list()
+}
+
+/// Favorite or unfavorite `peer_id` (a mesh peer ID or Noise public key)
+/// and tell the peer, sharing our own `npub` with it.
+#[tauri::command]
+#[specta::specta]
+pub fn favorites_set(
+    app: AppHandle,
+    peer_id: String,
+    favorite: bool,
+    npub: String,
+    state: State<'_, FavoritesState>,
+    router: State<'_, Router>,
+) -> AppResult<Favorite> {
+    if !is_npub(&npub) {
+        return Err(AppError::new(ErrorCode::InvalidArgument).with("field", "npub"));
+    }
+    let recipient: Recipient = peer_id.parse()?;
+    let (noise_public_key, peer) = match recipient {
+        Recipient::NoiseKey(key) => {
+            let peer = mesh::find_peer(&app, &key).and_then(|id| mesh::peer(&app, &id));
+            (hex::encode(key), peer)
+        }
+        Recipient::Peer(id) => {
+            let peer = mesh::peer(&app, &id)
+                .ok_or_else(|| AppError::new(ErrorCode::PeerUnreachable).with("peer_id", id))?;
+            let key = peer.noise_public_key.clone().ok_or_else(|| {
+                AppError::new(ErrorCode::InvalidArgument).with("field", "noise_public_key")
+            })?;
+            (key, Some(peer))
+        }
+    };
+
+    let notify = |router: &Router| {
+        let prefix = if favorite { FAVORITED } else { UNFAVORITED };
+        if let Err(e) = router.send(&app, recipient, format!("{}{}", prefix, npub)) {
+            tracing::debug!("favorite notification to {} not sent: {}", recipient, e);
+        }
+    };
+    // Unfavoriting removes the Nostr route, so tell the peer first.
+    if !favorite {
+        notify(&router);
+    }
+    let result = state.update(&app, &noise_public_key, |f| {
+        f.is_favorite = favorite;
+        if let Some(peer) = &peer {
+            f.nickname = peer.nickname.clone();
+            f.last_peer_id = Some(peer.peer_id.clone());
+        }
+    })?;
+    if let Some(peer_id) = peer.and_then(|p| p.peer_id.parse().ok()) {
+        mesh::set_favorite(&app, peer_id, favorite);
+    }
+    contacts::favorite_changed(&app, &noise_public_key, favorite);
+    if favorite {
+        notify(&router);
+    }
+    Ok(result)
+}
+
+/// Handle a private message, decrypted by the frontend, that may be a
+/// favorite notification. `noise_public_key` (hex) is the key its Noise
+/// session authenticated and `peer_id` the mesh peer it came from, if it
+/// came over the mesh. Returns whether it was a notification, in which
+/// case it shouldn't be shown as a message.
+#[tauri::command]
+#[specta::specta]
+pub fn favorites_notification_received(
+    app: AppHandle,
+    noise_public_key: String,
+    peer_id: Option<String>,
+    content: String,
+    state: State<'_, FavoritesState>,
+) -> AppResult<bool> {
+    let (favorited, npub) = if let Some(npub) = content.strip_prefix(FAVORITED) {
+        (true, npub)
+    } else if let Some(npub) = content.strip_prefix(UNFAVORITED) {
+        (false, npub)
+    } else {
+        return Ok(false);
+    };
+    let noise_public_key = noise_public_key.to_lowercase();
+    let mut key = [0u8; 32];
+    hex::decode_to_slice(&noise_public_key, &mut key)
+        .map_err(|_| AppError::new(ErrorCode::InvalidArgument).with("field", "noise_public_key"))?;
+    if !is_npub(npub) {
+        tracing::debug!(
+            "favorite notification from {} with a bad npub",
+            noise_public_key
+        );
+        return Ok(true);
+    }
+    let peer = match peer_id {
+        Some(peer_id) => {
+            let peer = mesh::peer(&app, &peer_id.parse()?);
+            let announced = peer.as_ref().and_then(|p| p.noise_public_key.as_deref());
+            if announced != Some(noise_public_key.as_str()) {
+                tracing::debug!(
+                    "favorite notification from {} under another key than it announced",
+                    peer_id
+                );
+                return Ok(true);
+            }
+            peer
+        }
+        None => mesh::find_peer(&app, &key).and_then(|id| mesh::peer(&app, &id)),
+    };
+    state.update(&app, &noise_public_key, |f| {
+        f.favorited_us = favorited;
+        f.npub = Some(npub.to_string());
+        if let Some(peer) = &peer {
+            f.nickname = peer.nickname.clone();
+            f.last_peer_id = Some(peer.peer_id.clone());
+        }
+    })?;
+    contacts::nostr_key_shared(&app, &noise_public_key, npub);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NPUB: &str = "npub1qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqq";
+
+    fn favorites(is_favorite: bool, favorited_us: bool) -> BTreeMap<String, Favorite> {
+        let favorite = Favorite {
+            noise_public_key: hex::encode([1u8; 32]),
+            nickname: "alice".to_string(),
+            npub: Some(NPUB.to_string()),
+            is_favorite,
+            favorited_us,
+            last_peer_id: Some(PeerId([2; 8]).to_string()),
+            updated_at: 0,
+        };
+        BTreeMap::from([(favorite.noise_public_key.clone(), favorite)])
+    }
+
+    #[test]
+    fn routes_mutual_favorites_over_nostr() {
+        let by_key = Recipient::NoiseKey([1; 32]);
+        let by_peer = Recipient::Peer(PeerId([2; 8]));
+        let mutual = favorites(true, true);
+        assert_eq!(route(&mutual, &by_key).as_deref(), Some(NPUB));
+        assert_eq!(route(&mutual, &by_peer).as_deref(), Some(NPUB));
+
+        assert_eq!(route(&mutual, &Recipient::NoiseKey([3; 32])), None);
+        assert_eq!(route(&mutual, &Recipient::Peer(PeerId([3; 8]))), None);
+        for one_sided in [favorites(true, false), favorites(false, true)] {
+            assert_eq!(route(&one_sided, &by_key), None);
+            assert_eq!(route(&one_sided, &by_peer), None);
+        }
+
+        let mut no_npub = favorites(true, true);
+        no_npub.values_mut().for_each(|f| f.npub = None);
+        assert_eq!(route(&no_npub, &by_key), None);
+    }
+}
@@ -0,0 +1,256 @@
+//! Intake pipeline for files dropped onto a window.
+//!
+//! Each dropped path is validated, size-checked, hashed and typed off the
+//! main thread. Accepted files are parked under a random handle and
+//! announced with [`FileEvent::ReadyToSend`]; the send path later claims
+//! the contents with `files_take` (or the UI drops it with `files_discard`).
+//! Handles that are neither are forgotten after [`HANDLE_TTL`].
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use specta::Type;
+use tauri::{AppHandle, DragDropEvent, Manager, State, Window, WindowEvent};
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events::{self, BackendEvent};
+
+pub const MAX_FILE_SIZE: u64 = 25 * 1024 * 1024;
+const THUMBNAIL_SIZE: u32 = 256;
+/// Don't decode huge images just to thumbnail them.
+const MAX_THUMBNAIL_SOURCE: u64 = 10 * 1024 * 1024;
+/// Forget dropped files that haven't been taken after this long.
+pub const HANDLE_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct ReadyFile {
+    pub handle: String,
+    #[serde(skip)]
+    pub path: PathBuf,
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub mime: String,
+    /// PNG data URL, only for images.
+    pub thumbnail: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FileEvent {
+    ReadyToSend { file: ReadyFile },
+    Rejected { name: String, error: AppError },
+}
+
+impl From<FileEvent> for BackendEvent {
+    fn from(event: FileEvent) -> Self {
+        BackendEvent::Files(event)
+    }
+}
+
+/// Dropped files by handle, with when they were parked.
+#[derive(Default)]
+pub struct FileIntake(Mutex<HashMap<String, (ReadyFile, Instant)>>);
+
+impl FileIntake {
+    /// Park `file` under its handle, forgetting the expired ones.
+    fn park(&self, file: ReadyFile, now: Instant) {
+        let mut files = self.0.lock().unwrap();
+        files.retain(|_, (_, parked)| now.duration_since(*parked) < HANDLE_TTL);
+        files.insert(file.handle.clone(), (file, now));
+    }
+
+    fn take(&self, handle: &str, now: Instant) -> Option<ReadyFile> {
+        let (file, parked) = self.0.lock().unwrap().remove(handle)?;
+        Some(file).filter(|_| now.duration_since(parked) < HANDLE_TTL)
+    }
+}
+
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    let WindowEvent::DragDrop(DragDropEvent::Drop { paths, .. }) = event else {
+        return;
+    };
+    for path in paths.clone() {
+        let app = window.app_handle().clone();
+        let label = window.label().to_string();
+        tauri::async_runtime::spawn_blocking(move || {
+            let event = match intake(&path) {
+                Ok(file) => {
+                    app.state::<FileIntake>().park(file.clone(), Instant::now());
+                    FileEvent::ReadyToSend { file }
+                }
+                Err(error) => FileEvent::Rejected {
+                    name: file_name(&path),
+                    error,
+                },
+            };
+            events::emit_to(&app, &label, event);
+        });
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn intake(path: &Path) -> AppResult<ReadyFile> {
+    let path = path.canonicalize()?;
+    let metadata = path.metadata()?;
+    if !metadata.is_file() {
+        return Err(AppError::new(ErrorCode::NotAFile));
+    }
+    let size = metadata.len();
+    if size > MAX_FILE_SIZE {
+        return Err(AppError::new(ErrorCode::FileTooLarge)
+            .with("size", size)
+            .with("max", MAX_FILE_SIZE));
+    }
+
+    let mut file = File::open(&path)?;
+    let mut hasher = Sha256::new();
+    let mut contents = Vec::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        if size <= MAX_THUMBNAIL_SOURCE {
+            contents.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    let mime = mime_guess::from_path(&path)
+        .first_or_octet_stream()
+        .essence_str()
+        .to_string();
+    let thumbnail = if mime.starts_with("image/") && !contents.is_empty() {
+        thumbnail(&contents)
+    } else {
+        None
+    };
+
+    Ok(ReadyFile {
+        handle: hex::encode(rand::random::<[u8; 16]>()),
+        name: file_name(&path),
+        path,
+        size,
+        sha256: hex::encode(hasher.finalize()),
+        mime,
+        thumbnail,
+    })
+}
+
+fn thumbnail(bytes: &[u8]) -> Option<String> {
+    let image = image::load_from_memory(bytes).ok()?;
+    let mut png = Vec::new();
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .ok()?;
+    Some(format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(png)
+    ))
+}
+
+/// Claim a dropped file for sending. Each handle can be taken once.
+pub fn take(app: &AppHandle, handle: &str) -> Option<ReadyFile> {
+    app.state::<FileIntake>().take(handle, Instant::now())
+}
+
+/// Claim a dropped file and return its contents, base64-encoded.
+#[tauri::command]
+#[specta::specta]
+pub async fn files_take(app: AppHandle, handle: String) -> AppResult<String> {
+    let file = take(&app, &handle)
+        .ok_or_else(|| AppError::new(ErrorCode::UnknownFileHandle).with("handle", &handle))?;
+    let bytes = tokio::fs::read(&file.path).await?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn files_discard(handle: String, intake: State<'_, FileIntake>) {
+    intake.0.lock().unwrap().remove(&handle);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bitchat-files-{}",
+            hex::encode(rand::random::<[u8; 8]>())
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn hashes_and_types_files() {
+        let path = temp_file("hello.txt");
+        std::fs::write(&path, "hello").unwrap();
+        let file = intake(&path).unwrap();
+        assert_eq!(file.name, "hello.txt");
+        assert_eq!(file.size, 5);
+        assert_eq!(
+            file.sha256,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(file.mime, "text/plain");
+        assert!(file.thumbnail.is_none());
+    }
+
+    #[test]
+    fn rejects_large_files_and_directories() {
+        let path = temp_file("big.bin");
+        File::create(&path)
+            .unwrap()
+            .set_len(MAX_FILE_SIZE + 1)
+            .unwrap();
+        let error = intake(&path).unwrap_err();
+        assert_eq!(error.code, ErrorCode::FileTooLarge);
+        assert_eq!(error.params["max"], MAX_FILE_SIZE.to_string());
+
+        File::create(&path).unwrap().set_len(MAX_FILE_SIZE).unwrap();
+        assert_eq!(intake(&path).unwrap().size, MAX_FILE_SIZE);
+
+        let dir = path.parent().unwrap();
+        assert_eq!(intake(dir).unwrap_err().code, ErrorCode::NotAFile);
+    }
+
+    #[test]
+    fn forgets_files_never_taken() {
+        let path = temp_file("note.txt");
+        std::fs::write(&path, "note").unwrap();
+        let files = FileIntake::default();
+        let now = Instant::now();
+
+        let first = intake(&path).unwrap();
+        files.park(first.clone(), now);
+        assert_eq!(files.take(&first.handle, now).unwrap().handle, first.handle);
+        assert!(files.take(&first.handle, now).is_none());
+
+        let stale = intake(&path).unwrap();
+        files.park(stale.clone(), now);
+        assert!(files.take(&stale.handle, now + HANDLE_TTL).is_none());
+
+        let stale = intake(&path).unwrap();
+        files.park(stale.clone(), now);
+        files.park(intake(&path).unwrap(), now + HANDLE_TTL);
+        assert_eq!(files.0.lock().unwrap().len(), 1);
+        assert!(!files.0.lock().unwrap().contains_key(&stale.handle));
+    }
+}
@@ -0,0 +1,292 @@
+//! Geohash encoding, decoding and neighbor lookup.
+//!
+//! Location channels are keyed by the geohash we put in the `g` tag of
+//! published events, so the frontend asks the backend for these instead of
+//! shipping its own implementation.
+
+use serde::Serialize;
+use specta::Type;
+
+use crate::error::{AppError, AppResult, ErrorCode};
+
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Longest geohash we accept (~3.7cm x 1.9cm cells).
+pub const MAX_PRECISION: usize = 12;
+
+#[derive(Debug, Clone, Copy, Serialize, Type)]
+pub struct GeoBounds {
+    pub min_lat: f64,
+    pub max_lat: f64,
+    pub min_lon: f64,
+    pub max_lon: f64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Type)]
+pub struct DecodedGeohash {
+    pub lat: f64,
+    pub lon: f64,
+    pub bounds: GeoBounds,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct GeoNeighbors {
+    pub n: String,
+    pub ne: String,
+    pub e: String,
+    pub se: String,
+    pub s: String,
+    pub sw: String,
+    pub w: String,
+    pub nw: String,
+}
+
+pub fn encode(lat: f64, lon: f64, precision: usize) -> AppResult<String> {
+    if !(1..=MAX_PRECISION).contains(&precision) {
+        return Err(AppError::new(ErrorCode::InvalidPrecision)
+            .with("min", 1)
+            .with("max", MAX_PRECISION));
+    }
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(AppError::new(ErrorCode::InvalidCoordinates)
+            .with("lat", lat)
+            .with("lon", lon));
+    }
+
+    let (mut lat_lo, mut lat_hi) = (-90.0, 90.0);
+    let (mut lon_lo, mut lon_hi) = (-180.0, 180.0);
+    let mut hash = String::with_capacity(precision);
+    let mut even = true;
+    let mut bit = 0;
+    let mut idx = 0usize;
+
+    while hash.len() < precision {
+        if even {
+            let mid = (lon_lo + lon_hi) / 2.0;
+            if lon >= mid {
+                idx = (idx << 1) | 1;
+                lon_lo = mid;
+            } else {
+                idx <<= 1;
+                lon_hi = mid;
+            }
+        } else {
+            let mid = (lat_lo + lat_hi) / 2.0;
+            if lat >= mid {
+                idx = (idx << 1) | 1;
+                lat_lo = mid;
+            } else {
+                idx <<= 1;
+                lat_hi = mid;
+            }
+        }
+        even = !even;
+        bit += 1;
+        if bit == 5 {
+            hash.push(BASE32[idx] as char);
+            bit = 0;
+            idx = 0;
+        }
+    }
+
+    Ok(hash)
+}
+
+pub fn decode(hash: &str) -> AppResult<DecodedGeohash> {
+    if hash.is_empty() || hash.len() > MAX_PRECISION {
+        return Err(AppError::new(ErrorCode::InvalidGeohash).with("hash", hash));
+    }
+
+    let (mut lat_lo, mut lat_hi) = (-90.0, 90.0);
+    let (mut lon_lo, mut lon_hi) = (-180.0, 180.0);
+    let mut even = true;
+
+    for c in hash.to_ascii_lowercase().bytes() {
+        let idx = BASE32
+            .iter()
+            .position(|&b| b == c)
+            .ok_or_else(|| AppError::new(ErrorCode::InvalidGeohash).with("hash", hash))?;
+        for shift in (0..5).rev() {
+            let bit = (idx >> shift) & 1;
+            if even {
+                let mid = (lon_lo + lon_hi) / 2.0;
+                if bit == 1 {
+                    lon_lo = mid;
+                } else {
+                    lon_hi = mid;
+                }
+            } else {
+                let mid = (lat_lo + lat_hi) / 2.0;
+                if bit == 1 {
+                    lat_lo = mid;
+                } else {
+                    lat_hi = mid;
+                }
+            }
+            even = !even;
+        }
+    }
+
+    Ok(DecodedGeohash {
+        lat: (lat_lo + lat_hi) / 2.0,
+        lon: (lon_lo + lon_hi) / 2.0,
+        bounds: GeoBounds {
+            min_lat: lat_lo,
+            max_lat: lat_hi,
+            min_lon: lon_lo,
+            max_lon: lon_hi,
+        },
+    })
+}
+
+/// Geohash of the cell offset by `dlat`/`dlon` cells from `hash`.
+///
+/// Longitude wraps around the antimeridian; latitude is clamped at the poles,
+/// so the northern neighbor of a polar cell is the cell itself.
+pub fn adjacent(hash: &str, dlat: i32, dlon: i32) -> AppResult<String> {
+    let decoded = decode(hash)?;
+    let b = decoded.bounds;
+    let lat_step = b.max_lat - b.min_lat;
+    let lon_step = b.max_lon - b.min_lon;
+
+    let lat = (decoded.lat + dlat as f64 * lat_step).clamp(-90.0, 90.0);
+    let mut lon = decoded.lon + dlon as f64 * lon_step;
+    if lon > 180.0 {
+        lon -= 360.0;
+    } else if lon < -180.0 {
+        lon += 360.0;
+    }
+
+    encode(lat, lon, hash.len())
+}
+
+pub fn neighbors(hash: &str) -> AppResult<GeoNeighbors> {
+    Ok(GeoNeighbors {
+        n: adjacent(hash, 1, 0)?,
+        ne: adjacent(hash, 1, 1)?,
+        e: adjacent(hash, 0, 1)?,
+        se: adjacent(hash, -1, 1)?,
+        s: adjacent(hash, -1, 0)?,
+        sw: adjacent(hash, -1, -1)?,
+        w: adjacent(hash, 0, -1)?,
+        nw: adjacent(hash, 1, -1)?,
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn geo_encode(lat: f64, lon: f64, precision: usize) -> AppResult<String> {
+    encode(lat, lon, precision)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn geo_decode(hash: String) -> AppResult<DecodedGeohash> {
+    decode(&hash)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn geo_neighbors(hash: String) -> AppResult<GeoNeighbors> {
+    neighbors(&hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_and_decodes_known_hashes() {
+        assert_eq!(encode(42.6, -5.6, 5).unwrap(), "ezs42");
+        assert_eq!(encode(57.64911, 10.40744, 11).unwrap(), "u4pruydqqvj");
+        assert_eq!(encode(0.0, 0.0, 1).unwrap(), "s");
+
+        let decoded = decode("ezs42").unwrap();
+        assert!((decoded.lat - 42.6).abs() < 0.03);
+        assert!((decoded.lon - -5.6).abs() < 0.03);
+        let b = decoded.bounds;
+        assert!(b.min_lat <= 42.6 && 42.6 <= b.max_lat);
+        assert!(b.min_lon <= -5.6 && -5.6 <= b.max_lon);
+
+        // Every cell decodes to a point that encodes back to it.
+        for hash in ["0", "zzzzzz", "u4pruydqqvj", "9q8yyk8yuv2b"] {
+            let decoded = decode(hash).unwrap();
+            assert_eq!(encode(decoded.lat, decoded.lon, hash.len()).unwrap(), hash);
+        }
+        assert_eq!(decode("EZS42").unwrap().lat, decoded.lat);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert_eq!(
+            encode(0.0, 0.0, 0).unwrap_err().code,
+            ErrorCode::InvalidPrecision
+        );
+        assert_eq!(
+            encode(0.0, 0.0, MAX_PRECISION + 1).unwrap_err().code,
+            ErrorCode::InvalidPrecision
+        );
+        assert_eq!(
+            encode(0.0, 0.0, MAX_PRECISION).unwrap().len(),
+            MAX_PRECISION
+        );
+        for (lat, lon) in [(90.1, 0.0), (-90.1, 0.0), (0.0, 180.1), (f64::NAN, 0.0)] {
+            assert_eq!(
+                encode(lat, lon, 5).unwrap_err().code,
+                ErrorCode::InvalidCoordinates
+            );
+        }
+
+        for hash in [
+            "",
+            "ezs42ezs42ezs",
+            "ezsa2",
+            "ezsi2",
+            "ezsl2",
+            "ezso2",
+            "ez-42",
+        ] {
+            assert_eq!(decode(hash).unwrap_err().code, ErrorCode::InvalidGeohash);
+        }
+        assert_eq!(
+            neighbors("ezsa2").unwrap_err().code,
+            ErrorCode::InvalidGeohash
+        );
+    }
+
+    #[test]
+    fn finds_neighbors() {
+        let n = neighbors("dqcjq").unwrap();
+        assert_eq!(
+            [n.n, n.ne, n.e, n.se, n.s, n.sw, n.w, n.nw],
+            ["dqcjw", "dqcjx", "dqcjr", "dqcjp", "dqcjn", "dqcjj", "dqcjm", "dqcjt"]
+        );
+    }
+
+    #[test]
+    fn neighbors_wrap_at_the_antimeridian() {
+        let east_edge = encode(0.0, 179.99, 4).unwrap();
+        let west_edge = encode(0.0, -179.99, 4).unwrap();
+        assert_eq!(neighbors(&east_edge).unwrap().e, west_edge);
+        assert_eq!(neighbors(&west_edge).unwrap().w, east_edge);
+        assert_eq!(
+            neighbors(&east_edge).unwrap().ne,
+            adjacent(&west_edge, 1, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn neighbors_stop_at_the_poles() {
+        let north = encode(89.99, 0.0, 3).unwrap();
+        let n = neighbors(&north).unwrap();
+        assert_eq!(n.n, north);
+        assert_eq!(n.ne, n.e);
+        assert_ne!(n.s, north);
+
+        let south = encode(-89.99, 0.0, 3).unwrap();
+        let s = neighbors(&south).unwrap();
+        assert_eq!(s.s, south);
+        assert_eq!(s.sw, s.w);
+        assert_ne!(s.n, south);
+    }
+}
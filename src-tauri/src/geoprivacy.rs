@@ -0,0 +1,173 @@
+//! Location privacy controls applied before a geohash leaves the device.
+//!
+//! The frontend hands us raw coordinates and asks which geohash to publish
+//! to. Depending on the user's settings we cap the precision, snap to a
+//! random neighboring cell, or ignore the real position entirely and use a
+//! user-chosen ("teleported") geohash. The configuration is persisted as
+//! part of the privacy settings.
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{AppHandle, State};
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::geo;
+use crate::settings::SettingsState;
+
+/// Default cap: precision 6 is roughly a 1.2km x 0.6km cell.
+const DEFAULT_MAX_PRECISION: usize = 6;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct GeoPrivacyConfig {
+    /// Longest geohash we will ever publish, regardless of what was requested.
+    pub max_precision: usize,
+    /// Snap to the real cell or one of its eight neighbors at random.
+    pub jitter: bool,
+    /// Publish to this geohash instead of the real location.
+    pub teleport: Option<String>,
+}
+
+impl Default for GeoPrivacyConfig {
+    fn default() -> Self {
+        Self {
+            max_precision: DEFAULT_MAX_PRECISION,
+            jitter: false,
+            teleport: None,
+        }
+    }
+}
+
+impl GeoPrivacyConfig {
+    pub fn validate(&self) -> AppResult<()> {
+        if !(1..=geo::MAX_PRECISION).contains(&self.max_precision) {
+            return Err(AppError::new(ErrorCode::InvalidPrecision)
+                .with("min", 1)
+                .with("max", geo::MAX_PRECISION));
+        }
+        if let Some(hash) = &self.teleport {
+            geo::decode(hash)?;
+        }
+        Ok(())
+    }
+
+    /// Geohash to publish for the given position at the requested precision.
+    pub fn resolve(&self, lat: f64, lon: f64, precision: usize) -> AppResult<String> {
+        if !(1..=geo::MAX_PRECISION).contains(&precision) {
+            return Err(AppError::new(ErrorCode::InvalidPrecision)
+                .with("min", 1)
+                .with("max", geo::MAX_PRECISION));
+        }
+        let precision = precision.min(self.max_precision);
+
+        if let Some(hash) = &self.teleport {
+            let hash = hash.to_ascii_lowercase();
+            return Ok(hash[..precision.min(hash.len())].to_string());
+        }
+
+        let hash = geo::encode(lat, lon, precision)?;
+        if !self.jitter {
+            return Ok(hash);
+        }
+
+        let n = geo::neighbors(&hash)?;
+        let candidates = [hash, n.n, n.ne, n.e, n.se, n.s, n.sw, n.w, n.nw];
+        Ok(candidates
+            .choose(&mut rand::thread_rng())
+            .cloned()
+            .expect("candidates is non-empty"))
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn geo_get_privacy(settings: State<'_, SettingsState>) -> GeoPrivacyConfig {
+    settings.get().privacy.location
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn geo_set_privacy(
+    app: AppHandle,
+    config: GeoPrivacyConfig,
+    settings: State<'_, SettingsState>,
+) -> AppResult<()> {
+    settings.update(&app, |s| s.privacy.location = config)?;
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn geo_resolve_location(
+    lat: f64,
+    lon: f64,
+    precision: usize,
+    settings: State<'_, SettingsState>,
+) -> AppResult<String> {
+    settings.get().privacy.location.resolve(lat, lon, precision)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_precision: usize, jitter: bool, teleport: Option<&str>) -> GeoPrivacyConfig {
+        GeoPrivacyConfig {
+            max_precision,
+            jitter,
+            teleport: teleport.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn caps_the_precision() {
+        let config = config(5, false, None);
+        assert_eq!(config.resolve(57.64911, 10.40744, 11).unwrap(), "u4pru");
+        assert_eq!(config.resolve(57.64911, 10.40744, 3).unwrap(), "u4p");
+    }
+
+    #[test]
+    fn snaps_to_the_cell_or_a_neighbor() {
+        let config = config(5, true, None);
+        let n = geo::neighbors("u4pru").unwrap();
+        let cells = ["u4pru", &n.n, &n.ne, &n.e, &n.se, &n.s, &n.sw, &n.w, &n.nw];
+        for _ in 0..50 {
+            let hash = config.resolve(57.64911, 10.40744, 5).unwrap();
+            assert!(cells.contains(&hash.as_str()), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn teleporting_ignores_the_real_position() {
+        let config = config(4, false, Some("EZS42E"));
+        assert_eq!(config.resolve(57.64911, 10.40744, 9).unwrap(), "ezs4");
+        assert_eq!(config.resolve(57.64911, 10.40744, 2).unwrap(), "ez");
+    }
+
+    #[test]
+    fn rejects_out_of_range_precision() {
+        for config in [config(6, false, None), config(6, false, Some("ezs42"))] {
+            for precision in [0, geo::MAX_PRECISION + 1] {
+                assert_eq!(
+                    config.resolve(0.0, 0.0, precision).unwrap_err().code,
+                    ErrorCode::InvalidPrecision
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn validates_the_config() {
+        assert!(GeoPrivacyConfig::default().validate().is_ok());
+        assert!(config(geo::MAX_PRECISION, true, Some("ezs42"))
+            .validate()
+            .is_ok());
+        assert!(config(0, false, None).validate().is_err());
+        assert!(config(geo::MAX_PRECISION + 1, false, None)
+            .validate()
+            .is_err());
+        assert!(config(6, false, Some("")).validate().is_err());
+        assert!(config(6, false, Some("ezs4a")).validate().is_err());
+    }
+}
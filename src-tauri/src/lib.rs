@@ -1,15 +1,177 @@
+#[cfg(debug_assertions)]
+use specta_typescript::{BigIntExportBehavior, Typescript};
 use tauri::Manager;
+use tauri_specta::collect_commands;
+
+mod background;
+mod clipboard;
+mod error;
+mod events;
+mod favorites;
+mod files;
+mod geo;
+mod geoprivacy;
+mod logs;
+mod mesh;
+mod nostr;
+mod notifications;
+mod secure_store;
+mod settings;
+mod shortcuts;
+mod storage;
+mod transport;
+mod tray;
+mod windows;
 
 #[tauri::command]
+#[specta::specta]
 fn greet(name: &str) -> String {
     format!("Hello, {}! Welcome to BitChat.", name)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let builder = tauri_specta::Builder::<tauri::Wry>::new()
+        .commands(collect_commands![
+            greet,
+            background::app_set_background_mode,
+            background::app_set_launch_at_login,
+            clipboard::clipboard_copy_secret,
+            favorites::favorites_list,
+            favorites::favorites_set,
+            favorites::favorites_notification_received,
+            files::files_take,
+            files::files_discard,
+            geo::geo_encode,
+            geo::geo_decode,
+            geo::geo_neighbors,
+            geoprivacy::geo_get_privacy,
+            geoprivacy::geo_set_privacy,
+            geoprivacy::geo_resolve_location,
+            logs::logs_get_recent,
+            logs::logs_export,
+            mesh::bridge::bridge_from_nostr,
+            mesh::broadcast::mesh_broadcast,
+            mesh::channels::channel_join_protected,
+            mesh::channels::channel_leave,
+            mesh::channels::channel_list,
+            mesh::channels::channel_send,
+            mesh::mesh_announce,
+            mesh::mesh_get_peer_id,
+            mesh::mesh_get_peers,
+            mesh::mesh_get_relay_stats,
+            mesh::mesh_send_packet,
+            mesh::mesh_send_private,
+            mesh::mesh_session_established,
+            mesh::mesh_session_failed,
+            mesh::packet::mesh_encode_packet,
+            mesh::receipts::mesh_send_receipt,
+            nostr::relay_info::nostr_get_relay_info,
+            notifications::notifications_get_prefs,
+            notifications::notifications_set_prefs,
+            notifications::notifications_mute_conversation,
+            notifications::notifications_notify,
+            notifications::notifications_clear_badge,
+            secure_store::secure_store_set,
+            secure_store::secure_store_get,
+            secure_store::secure_store_delete,
+            settings::settings_get,
+            settings::settings_set,
+            shortcuts::shortcut_set_quick_compose,
+            shortcuts::compose_submit,
+            storage::messages_save,
+            storage::messages_mark_read,
+            storage::messages_list,
+            storage::messages_search,
+            storage::messages_conversations,
+            storage::messages_delete,
+            storage::messages_delete_conversation,
+            storage::contacts::contacts_list,
+            storage::contacts::contacts_get,
+            storage::contacts::contacts_create,
+            storage::contacts::contacts_update,
+            storage::contacts::contacts_delete,
+            storage::contacts::contacts_apply_profile,
+            transport::ble::ble_start,
+            transport::ble::ble_stop,
+            transport::ble::ble_get_links,
+            transport::ble::ble_send,
+            transport::ble_peripheral::ble_get_capabilities,
+            transport::ble_peripheral::ble_start_advertising,
+            transport::ble_peripheral::ble_stop_advertising,
+            transport::lan::lan_start,
+            transport::lan::lan_stop,
+            transport::lan::lan_get_links,
+            transport::delivery::transport_send_message,
+            transport::delivery::transport_mark_delivered,
+            transport::delivery::transport_mark_read,
+            transport::delivery::transport_get_status,
+            transport::webrtc::webrtc_connect,
+            transport::webrtc::webrtc_signal,
+            transport::webrtc::webrtc_close,
+            transport::webrtc::webrtc_get_links,
+            tray::tray_update,
+            windows::window_open_conversation,
+            windows::window_list_conversations,
+        ])
+        .typ::<events::BackendEvent>();
+
+    // Regenerate the frontend bindings on every debug run so they can't
+    // drift from the command signatures.
+    #[cfg(debug_assertions)]
+    builder
+        .export(
+            Typescript::default().bigint(BigIntExportBehavior::Number),
+            "../src/bindings.ts",
+        )
+        .expect("failed to export typescript bindings");
+
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_notification::init())
+        .plugin(tauri_plugin_clipboard_manager::init())
+        .plugin(shortcuts::plugin())
+        .plugin(background::autostart_plugin())
+        .manage(nostr::relay_info::RelayInfoCache::default())
+        .manage(windows::WindowRegistry::default())
+        .manage(files::FileIntake::default())
+        .manage(transport::ble::BleState::default())
+        .manage(transport::ble_peripheral::PeripheralState::default())
+        .manage(transport::lan::LanState::default())
+        .manage(transport::webrtc::WebrtcState::default())
+        .manage(transport::router::Router::default())
+        .manage(transport::scan::Foreground::default())
+        .manage(mesh::bridge::BridgeState::default())
+        .manage(mesh::broadcast::SigningState::default())
         .setup(|app| {
+            app.manage(logs::init(&app.path().app_log_dir()?)?);
+
+            let data_dir = app.path().app_data_dir()?;
+            app.manage(settings::SettingsState::load(
+                data_dir.join(settings::FILE_NAME),
+            ));
+            app.manage(mesh::channels::ChannelsState::load(
+                data_dir.join(mesh::channels::FILE_NAME),
+            ));
+            app.manage(favorites::FavoritesState::load(
+                data_dir.join(favorites::FILE_NAME),
+            ));
+            app.manage(storage::StorageState::open(
+                data_dir.join(storage::FILE_NAME),
+            ));
+            shortcuts::register_from_settings(app.handle());
+            transport::delivery::start(app.handle());
+            mesh::start(app.handle());
+
+            tray::setup(app.handle())?;
+            storage::refresh_badge(app.handle());
+            if background::started_in_background() {
+                if let Some(window) = app.get_webview_window(windows::MAIN_WINDOW) {
+                    window.hide()?;
+                }
+            }
+            transport::scan::init(app.handle());
+
             #[cfg(debug_assertions)]
             {
                 let window = app.get_webview_window("main").unwrap();
@@ -17,7 +179,12 @@ pub fn run() {
             }
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![greet])
+        .on_window_event(|window, event| {
+            background::on_window_event(window, event);
+            files::on_window_event(window, event);
+            transport::scan::on_window_event(window, event);
+        })
+        .invoke_handler(builder.invoke_handler())
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -0,0 +1,190 @@
+//! In-app log capture.
+//!
+//! `tracing` output goes to daily rolling files in the app log directory and
+//! to a bounded in-memory buffer, so users can attach recent logs to a bug
+//! report without digging through the filesystem.
+
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use specta::Type;
+use tauri::State;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt as tracing_fmt, Layer};
+
+use crate::error::{AppError, AppResult, ErrorCode};
+
+const BUFFER_CAPACITY: usize = 2000;
+const MAX_LOG_FILES: usize = 7;
+const FILE_PREFIX: &str = "bitchat";
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct LogEntry {
+    /// Unix milliseconds.
+    pub timestamp: u64,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct LogBuffer(Mutex<VecDeque<LogEntry>>);
+
+impl LogBuffer {
+    fn push(&self, entry: LogEntry) {
+        let mut entries = self.0.lock().unwrap();
+        if entries.len() == BUFFER_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+}
+
+/// Collects the `message` field and any structured fields of an event into
+/// one line.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+}
+
+struct BufferLayer(Arc<LogBuffer>);
+
+impl<S: Subscriber> Layer<S> for BufferLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        self.0.push(LogEntry {
+            timestamp,
+            level: event.metadata().level().to_string(),
+            target: event.metadata().target().to_string(),
+            message: visitor.message + &visitor.fields,
+        });
+    }
+}
+
+pub struct LogState {
+    buffer: Arc<LogBuffer>,
+    log_dir: PathBuf,
+    // Flushes the file writer when dropped at shutdown.
+    _guard: WorkerGuard,
+}
+
+/// Install the global subscriber. Must be called once, before anything logs.
+pub fn init(log_dir: &Path) -> AppResult<LogState> {
+    fs::create_dir_all(log_dir)?;
+    let file_appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(FILE_PREFIX)
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(log_dir)
+        .map_err(AppError::platform)?;
+    let (writer, guard) = tracing_appender::non_blocking(file_appender);
+    let buffer = Arc::new(LogBuffer::default());
+
+    let level = if cfg!(debug_assertions) {
+        LevelFilter::DEBUG
+    } else {
+        LevelFilter::INFO
+    };
+    tracing_subscriber::registry()
+        .with(level)
+        .with(tracing_fmt::layer().with_writer(writer).with_ansi(false))
+        .with(BufferLayer(buffer.clone()))
+        .try_init()
+        .map_err(AppError::platform)?;
+
+    Ok(LogState {
+        buffer,
+        log_dir: log_dir.to_path_buf(),
+        _guard: guard,
+    })
+}
+
+/// Most recent entries at `level` or more severe, oldest first.
+#[tauri::command]
+#[specta::specta]
+pub fn logs_get_recent(
+    level: Option<String>,
+    limit: Option<usize>,
+    state: State<'_, LogState>,
+) -> AppResult<Vec<LogEntry>> {
+    let min_level = match level {
+        Some(level) => Level::from_str(&level).map_err(|_| {
+            AppError::new(ErrorCode::InvalidArgument)
+                .with("field", "level")
+                .with("value", &level)
+        })?,
+        None => Level::TRACE,
+    };
+    let limit = limit.unwrap_or(BUFFER_CAPACITY);
+
+    let entries = state.buffer.0.lock().unwrap();
+    let mut recent: Vec<LogEntry> = entries
+        .iter()
+        .rev()
+        .filter(|e| Level::from_str(&e.level).is_ok_and(|l| l <= min_level))
+        .take(limit)
+        .cloned()
+        .collect();
+    recent.reverse();
+    Ok(recent)
+}
+
+/// Concatenate the on-disk log files, oldest first, into `path`.
+#[tauri::command]
+#[specta::specta]
+pub fn logs_export(path: String, state: State<'_, LogState>) -> AppResult<()> {
+    let mut files: Vec<PathBuf> = fs::read_dir(&state.log_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(FILE_PREFIX))
+        })
+        .collect();
+    // Rotated files are date-suffixed, so lexical order is chronological.
+    files.sort();
+
+    let mut out = Vec::new();
+    for file in files {
+        out.extend(fs::read(&file)?);
+    }
+    Ok(fs::write(&path, out)?)
+}
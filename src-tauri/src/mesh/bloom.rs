@@ -0,0 +1,146 @@
+//! Time-decaying bloom filter for packet deduplication.
+//!
+//! Two generations of bits are kept; inserts go to the current one and
+//! lookups check both. The generations rotate every half window (or when
+//! the current one reaches capacity), so an ID is remembered for between
+//! half a window and a full window, in fixed memory.
+
+/// Sizing for [`DecayingBloom`]. Memory use is two generations of
+/// `-capacity * ln(false_positive_rate) / ln(2)^2` bits.
+#[derive(Debug, Clone, Copy)]
+pub struct BloomConfig {
+    /// IDs per generation before it is rotated early.
+    pub capacity: usize,
+    /// Target false-positive rate at capacity.
+    pub false_positive_rate: f64,
+    /// How long an ID is remembered, at most.
+    pub window_ms: u64,
+}
+
+impl Default for BloomConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            false_positive_rate: 0.001,
+            window_ms: 5 * 60_000,
+        }
+    }
+}
+
+pub struct DecayingBloom {
+    config: BloomConfig,
+    bits: usize,
+    hashes: u32,
+    current: Vec<u64>,
+    previous: Vec<u64>,
+    current_count: usize,
+    rotated_at: u64,
+}
+
+impl DecayingBloom {
+    pub fn new(config: BloomConfig) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let capacity = config.capacity.max(1) as f64;
+        let rate = config.false_positive_rate.clamp(1e-9, 0.5);
+        let bits = (-capacity * rate.ln() / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hashes = ((bits as f64 / capacity) * ln2).round().clamp(1.0, 32.0) as u32;
+        let words = bits.div_ceil(64);
+        Self {
+            config,
+            bits: words * 64,
+            hashes,
+            current: vec![0; words],
+            previous: vec![0; words],
+            current_count: 0,
+            rotated_at: 0,
+        }
+    }
+
+    /// Bytes used by both generations.
+    pub fn memory_bytes(&self) -> usize {
+        2 * self.current.len() * 8
+    }
+
+    /// Record `id`. Returns false if it was (probably) already seen.
+    pub fn insert(&mut self, id: &[u8; 16], now: u64) -> bool {
+        self.maybe_rotate(now);
+        if self.contains(id) {
+            return false;
+        }
+        for bit in self.positions(id) {
+            self.current[bit / 64] |= 1 << (bit % 64);
+        }
+        self.current_count += 1;
+        true
+    }
+
+    pub fn contains(&self, id: &[u8; 16]) -> bool {
+        let test = |bits: &[u64]| {
+            self.positions(id)
+                .all(|bit| bits[bit / 64] & (1 << (bit % 64)) != 0)
+        };
+        test(&self.current) || test(&self.previous)
+    }
+
+    fn maybe_rotate(&mut self, now: u64) {
+        let expired = now.saturating_sub(self.rotated_at) >= self.config.window_ms / 2;
+        if expired || self.current_count >= self.config.capacity {
+            self.previous = std::mem::replace(&mut self.current, vec![0; self.previous.len()]);
+            self.current_count = 0;
+            self.rotated_at = now;
+        }
+    }
+
+    /// Double hashing over the two halves of the (already uniform) ID.
+    fn positions(&self, id: &[u8; 16]) -> impl Iterator<Item = usize> {
+        let h1 = u64::from_le_bytes(id[..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(id[8..].try_into().unwrap()) | 1;
+        let bits = self.bits as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Uniform pseudo-random IDs, standing in for packet hashes.
+    fn id(n: u64) -> [u8; 16] {
+        let mix = |mut z: u64| {
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        let mut id = [0u8; 16];
+        id[..8].copy_from_slice(&mix(n).to_le_bytes());
+        id[8..].copy_from_slice(&mix(n ^ 0x9e37_79b9_7f4a_7c15).to_le_bytes());
+        id
+    }
+
+    #[test]
+    fn remembers_within_the_window_and_forgets_after() {
+        let config = BloomConfig::default();
+        let mut bloom = DecayingBloom::new(config);
+        assert!(bloom.insert(&id(1), 0));
+        assert!(!bloom.insert(&id(1), config.window_ms / 2));
+        assert!(bloom.insert(&id(1), 2 * config.window_ms));
+    }
+
+    #[test]
+    fn false_positive_rate_stays_near_target() {
+        let config = BloomConfig {
+            capacity: 5000,
+            false_positive_rate: 0.01,
+            window_ms: u64::MAX,
+        };
+        let mut bloom = DecayingBloom::new(config);
+        for n in 0..config.capacity as u64 - 1 {
+            bloom.insert(&id(n), 1);
+        }
+        let false_positives = (1_000_000..1_010_000u64)
+            .filter(|n| bloom.contains(&id(*n)))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+        assert!(bloom.memory_bytes() < 2 * 6500);
+    }
+}
@@ -0,0 +1,332 @@
+//! Opt-in bridge between the local mesh and a Nostr geohash channel.
+//!
+//! With `settings.bridge` enabled, signed public mesh chat is republished
+//! into the configured geohash channel and that channel's messages are
+//! broadcast on the mesh, so an offline mesh can talk to the wider network
+//! through one connected desktop node. Nostr relays live in the frontend: outgoing
+//! messages are handed over as [`MeshEvent::BridgePublish`] for it to sign
+//! and publish, and it passes the geohash events it receives to
+//! [`bridge_from_nostr`].
+//!
+//! Bridged messages are marked so they never cross back. Events we publish
+//! carry a `["bridge", "mesh", <sender>]` tag, and Nostr messages travel the
+//! mesh as broadcast `Message` packets whose payload is:
+//!
+//! ```text
+//! "\0nostr" event_id:32 nick_len:1 nickname content
+//! ```
+//!
+//! They are signed like public chat (see [`super::broadcast`]). Anyone can
+//! put such a payload on the mesh, so nodes show only those signed with the
+//! key pinned for the bridge that sent them (see [`handle_packet`]), and no
+//! bridge republishes them.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use specta::Type;
+use tauri::{AppHandle, Manager, State};
+
+use super::broadcast::{self, SigningState};
+use super::packet::{MessageType, Packet};
+use super::{now_ms, MeshEvent, MeshState};
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events;
+use crate::settings::SettingsState;
+use crate::storage::{self, Conversation, StoredMessage};
+
+const PREFIX: &[u8] = b"\0nostr";
+const EVENT_ID_SIZE: usize = 32;
+const BRIDGE_TAG: &str = "bridge";
+/// How many bridged event IDs to remember; relays often resend events.
+const RECENT_EVENTS: usize = 1024;
+
+/// Mesh payload for a message bridged in from Nostr. Nicknames longer than
+/// 255 bytes are cut short.
+pub fn encode(event_id: &[u8; EVENT_ID_SIZE], nickname: &str, content: &str) -> Vec<u8> {
+    let mut end = nickname.len().min(u8::MAX as usize);
+    while !nickname.is_char_boundary(end) {
+        end -= 1;
+    }
+    let nickname = &nickname[..end];
+    let mut out = PREFIX.to_vec();
+    out.extend_from_slice(event_id);
+    out.push(nickname.len() as u8);
+    out.extend_from_slice(nickname.as_bytes());
+    out.extend_from_slice(content.as_bytes());
+    out
+}
+
+/// A message bridged in from Nostr, as found in a mesh payload.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Bridged<'a> {
+    pub event_id: [u8; EVENT_ID_SIZE],
+    pub nickname: &'a str,
+    pub content: &'a str,
+}
+
+pub fn parse(payload: &[u8]) -> Option<Bridged<'_>> {
+    let rest = payload.strip_prefix(PREFIX)?;
+    let (event_id, rest) = rest.split_first_chunk::<EVENT_ID_SIZE>()?;
+    let (&nick_len, rest) = rest.split_first()?;
+    let (nickname, content) = rest.split_at_checked(nick_len as usize)?;
+    Some(Bridged {
+        event_id: *event_id,
+        nickname: std::str::from_utf8(nickname).ok()?,
+        content: std::str::from_utf8(content).ok()?,
+    })
+}
+
+/// The bridged message in `packet`, if its sender signed it with
+/// `signing_key`, the key pinned for it. Unsigned ones are only a claim.
+pub fn verified(packet: &Packet, signing_key: Option<[u8; 32]>) -> Option<Bridged<'_>> {
+    let bridged = parse(&packet.payload)?;
+    let valid = signing_key.and_then(|key| broadcast::verify(packet, &key));
+    (valid == Some(true)).then_some(bridged)
+}
+
+/// Plain public chat text that may go out to Nostr: not a channel or
+/// bridged message, and not for a particular peer.
+pub fn public_text(packet: &Packet) -> Option<&str> {
+    if packet.message_type != MessageType::Message
+        || !packet.recipient_id.map_or(true, |id| id.is_broadcast())
+        || packet.payload.first() == Some(&0)
+    {
+        return None;
+    }
+    std::str::from_utf8(&packet.payload).ok()
+}
+
+/// A geohash channel event as the frontend received it.
+#[derive(Debug, Clone, Deserialize, Type)]
+pub struct NostrChannelEvent {
+    /// Hex event ID.
+    pub id: String,
+    /// Hex public key of the author.
+    pub pubkey: String,
+    pub content: String,
+    pub tags: Vec<Vec<String>>,
+}
+
+impl NostrChannelEvent {
+    /// The first value of the first `name` tag.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.first().map(String::as_str) == Some(name))
+            .and_then(|tag| tag.get(1))
+            .map(String::as_str)
+    }
+
+    /// Whether a bridge (ours or another) already published this from a
+    /// mesh.
+    pub fn is_bridged(&self) -> bool {
+        self.tag(BRIDGE_TAG).is_some()
+    }
+}
+
+/// Tags for a mesh message published into `geohash`.
+pub fn publish_tags(geohash: &str, nickname: &str, sender_id: &str) -> Vec<Vec<String>> {
+    [
+        vec!["g", geohash],
+        vec!["n", nickname],
+        vec![BRIDGE_TAG, "mesh", sender_id],
+    ]
+    .into_iter()
+    .map(|tag| tag.into_iter().map(str::to_string).collect())
+    .collect()
+}
+
+/// Event IDs bridged recently.
+#[derive(Default)]
+pub struct Recent(VecDeque<[u8; EVENT_ID_SIZE]>);
+
+impl Recent {
+    /// Remember `id`. Returns false if it was already there.
+    pub fn insert(&mut self, id: [u8; EVENT_ID_SIZE]) -> bool {
+        if self.0.contains(&id) {
+            return false;
+        }
+        if self.0.len() == RECENT_EVENTS {
+            self.0.pop_front();
+        }
+        self.0.push_back(id);
+        true
+    }
+}
+
+#[derive(Default)]
+pub struct BridgeState(Mutex<Recent>);
+
+/// The geohash to bridge with, if the bridge is on.
+fn geohash(app: &AppHandle) -> Option<String> {
+    let bridge = app.state::<SettingsState>().get().bridge;
+    bridge.geohash.filter(|_| bridge.enabled)
+}
+
+/// Show a message bridged in from Nostr, if the bridge signed it. Returns
+/// false for any other packet.
+pub fn handle_packet(app: &AppHandle, packet: &Packet) -> bool {
+    if packet.message_type != MessageType::Message || parse(&packet.payload).is_none() {
+        return false;
+    }
+    let Some(bridged) = verified(packet, super::signing_key(app, &packet.sender_id)) else {
+        tracing::debug!(
+            "dropping unverified bridged message from {}",
+            packet.sender_id
+        );
+        return true;
+    };
+    // Shown with the mesh's public chat, which is where it arrived.
+    storage::record(
+        app,
+        StoredMessage {
+            id: hex::encode(bridged.event_id),
+            conversation: Conversation::Mesh,
+            sender_id: packet.sender_id.to_string(),
+            nickname: Some(bridged.nickname.to_string()),
+            content: bridged.content.to_string(),
+            timestamp: packet.timestamp,
+            outgoing: false,
+            delivery: None,
+        },
+    );
+    events::emit(
+        app,
+        MeshEvent::NostrMessage {
+            event_id: hex::encode(bridged.event_id),
+            nickname: bridged.nickname.to_string(),
+            content: bridged.content.to_string(),
+            bridge_id: packet.sender_id.to_string(),
+            timestamp: packet.timestamp,
+        },
+    );
+    true
+}
+
+/// Hand public chat from the mesh to the frontend for publishing, if the
+/// bridge is on. Only for messages whose signature checked out (see
+/// [`super::broadcast`]), so nobody is impersonated on Nostr.
+pub fn publish(app: &AppHandle, sender_id: &str, nickname: &str, content: &str) {
+    let Some(geohash) = geohash(app) else {
+        return;
+    };
+    events::emit(
+        app,
+        MeshEvent::BridgePublish {
+            tags: publish_tags(&geohash, nickname, sender_id),
+            geohash,
+            content: content.to_string(),
+        },
+    );
+}
+
+/// Broadcast a geohash channel event on the mesh. Returns false if it
+/// wasn't bridged: the bridge is off, the event is for another geohash,
+/// came from a mesh itself, or was already bridged.
+#[tauri::command]
+#[specta::specta]
+pub fn bridge_from_nostr(
+    app: AppHandle,
+    event: NostrChannelEvent,
+    bridge: State<'_, BridgeState>,
+    signing: State<'_, SigningState>,
+    mesh: State<'_, MeshState>,
+) -> AppResult<bool> {
+    let Some(geohash) = geohash(&app) else {
+        return Ok(false);
+    };
+    if event.tag("g") != Some(geohash.as_str()) || event.is_bridged() {
+        return Ok(false);
+    }
+    let mut event_id = [0u8; EVENT_ID_SIZE];
+    hex::decode_to_slice(&event.id, &mut event_id)
+        .map_err(|_| AppError::new(ErrorCode::InvalidArgument).with("field", "id"))?;
+    let mut pubkey = [0u8; 32];
+    hex::decode_to_slice(&event.pubkey, &mut pubkey)
+        .map_err(|_| AppError::new(ErrorCode::InvalidArgument).with("field", "pubkey"))?;
+    if !bridge.0.lock().unwrap().insert(event_id) {
+        return Ok(false);
+    }
+    let fallback = hex::encode(&pubkey[..4]);
+    let nickname = event.tag("n").unwrap_or(&fallback);
+    let payload = encode(&event_id, nickname, &event.content);
+    let key = signing.key()?;
+    let actions = {
+        let mut node = mesh.node.lock().unwrap();
+        let now = now_ms();
+        let mut packet = Packet::new(MessageType::Message, node.peer_id(), None, payload, now);
+        broadcast::sign(&mut packet, &key)?;
+        node.originate(&packet, now)
+    };
+    super::perform(&app, actions);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::packet::PeerId;
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn bridged_payload_round_trips() {
+        let id = [7; EVENT_ID_SIZE];
+        let payload = encode(&id, "alice", "hello mesh");
+        assert_eq!(
+            parse(&payload),
+            Some(Bridged {
+                event_id: id,
+                nickname: "alice",
+                content: "hello mesh",
+            })
+        );
+        assert_eq!(parse(b"hello mesh"), None);
+
+        // Long nicknames are cut on a character boundary.
+        let long = "é".repeat(200);
+        let parsed_nick = parse(&encode(&id, &long, "x")).unwrap().nickname.len();
+        assert_eq!(parsed_nick, 254);
+
+        let packet = Packet::new(MessageType::Message, PeerId([1; 8]), None, payload, 0);
+        assert_eq!(public_text(&packet), None);
+    }
+
+    #[test]
+    fn only_signed_bridged_messages_are_shown() {
+        let key = SigningKey::from_bytes(&[5; 32]);
+        let public = key.verifying_key().to_bytes();
+        let payload = encode(&[7; EVENT_ID_SIZE], "alice", "hi");
+        let mut packet = Packet::new(MessageType::Message, PeerId([1; 8]), None, payload, 0);
+        assert_eq!(verified(&packet, Some(public)), None);
+
+        broadcast::sign(&mut packet, &key).unwrap();
+        assert_eq!(verified(&packet, Some(public)).unwrap().nickname, "alice");
+        // The sender has no pinned key, or another one.
+        assert_eq!(verified(&packet, None), None);
+        let other = SigningKey::from_bytes(&[6; 32]).verifying_key().to_bytes();
+        assert_eq!(verified(&packet, Some(other)), None);
+
+        let mut forged = packet.clone();
+        forged.payload = encode(&[7; EVENT_ID_SIZE], "alice", "bye");
+        assert_eq!(verified(&forged, Some(public)), None);
+    }
+
+    #[test]
+    fn bridged_events_are_not_bridged_back() {
+        let tags = publish_tags("u4pruy", "bob", "0101010101010101");
+        let event = NostrChannelEvent {
+            id: hex::encode([1; EVENT_ID_SIZE]),
+            pubkey: hex::encode([2; 32]),
+            content: "hi".to_string(),
+            tags,
+        };
+        assert_eq!(event.tag("g"), Some("u4pruy"));
+        assert!(event.is_bridged());
+
+        let mut recent = Recent::default();
+        assert!(recent.insert([1; EVENT_ID_SIZE]));
+        assert!(!recent.insert([1; EVENT_ID_SIZE]));
+    }
+}
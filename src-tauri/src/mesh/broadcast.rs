@@ -0,0 +1,230 @@
+//! Public chat with everyone nearby.
+//!
+//! Public messages are plain `Message` packets to the broadcast address,
+//! relayed hop by hop until their TTL runs out. They aren't encrypted, but
+//! they are signed with an Ed25519 key kept in the OS keyring, whose public
+//! half goes out in our announces. The signature covers the packet encoded
+//! without compression, signature or TTL (see [`signed_bytes`]), as in the
+//! native apps, so relays can decrement the TTL without breaking it.
+//!
+//! Incoming messages are checked against the key their sender announced,
+//! once it's pinned by a Noise handshake (see [`super::peers`]). Bad
+//! signatures are dropped; unsigned messages, and those from peers we have
+//! no pinned key for, are shown as unverified. Only verified messages are
+//! passed on to the Nostr [`bridge`].
+
+use std::sync::Mutex;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use tauri::{AppHandle, State};
+
+use super::bridge;
+use super::packet::{MessageType, Packet};
+use super::{now_ms, MeshEvent, MeshState};
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events;
+use crate::secure_store;
+use crate::storage::{self, Conversation, StoredMessage};
+
+const SIGNING_KEY_ENTRY: &str = "mesh_signing_key";
+
+/// What a public message's signature covers.
+pub fn signed_bytes(packet: &Packet) -> AppResult<Vec<u8>> {
+    Packet {
+        ttl: 0,
+        signature: None,
+        ..packet.clone()
+    }
+    .encode_with(false)
+}
+
+/// Sign `packet` as ours.
+pub fn sign(packet: &mut Packet, key: &SigningKey) -> AppResult<()> {
+    packet.signature = Some(key.sign(&signed_bytes(packet)?).to_bytes());
+    Ok(())
+}
+
+/// Our signing key, loaded from the keyring (or created) on first use.
+#[derive(Default)]
+pub struct SigningState(Mutex<Option<SigningKey>>);
+
+impl SigningState {
+    pub fn key(&self) -> AppResult<SigningKey> {
+        let mut key = self.0.lock().unwrap();
+        if let Some(key) = key.as_ref() {
+            return Ok(key.clone());
+        }
+        let loaded = match secure_store::get(SIGNING_KEY_ENTRY)? {
+            Some(stored) => {
+                let mut secret = [0u8; 32];
+                hex::decode_to_slice(stored, &mut secret).map_err(|_| {
+                    AppError::new(ErrorCode::SecureStoreUnavailable)
+                        .with("message", "stored mesh signing key is corrupt")
+                })?;
+                SigningKey::from_bytes(&secret)
+            }
+            None => {
+                let generated = SigningKey::generate(&mut rand::rngs::OsRng);
+                secure_store::set(SIGNING_KEY_ENTRY, &hex::encode(generated.to_bytes()))?;
+                generated
+            }
+        };
+        Ok(key.insert(loaded).clone())
+    }
+
+    /// The public key to announce, if the keyring is usable.
+    pub fn public_key(&self) -> Option<[u8; 32]> {
+        match self.key() {
+            Ok(key) => Some(key.verifying_key().to_bytes()),
+            Err(e) => {
+                tracing::warn!("can't load the mesh signing key: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Whether `packet` carries a valid signature by `public_key`. `None` if
+/// it's unsigned.
+pub fn verify(packet: &Packet, public_key: &[u8; 32]) -> Option<bool> {
+    let signature = Signature::from_bytes(packet.signature.as_ref()?);
+    let valid = VerifyingKey::from_bytes(public_key).is_ok_and(|key| {
+        signed_bytes(packet).is_ok_and(|bytes| key.verify(&bytes, &signature).is_ok())
+    });
+    Some(valid)
+}
+
+/// Show public chat from the mesh. Returns false for any other packet.
+pub fn handle_packet(app: &AppHandle, packet: &Packet) -> bool {
+    let Some(content) = bridge::public_text(packet) else {
+        return false;
+    };
+    let verified =
+        match super::signing_key(app, &packet.sender_id).and_then(|key| verify(packet, &key)) {
+            Some(true) => true,
+            Some(false) => {
+                tracing::debug!(
+                    "dropping public message with a bad signature from {}",
+                    packet.sender_id
+                );
+                return true;
+            }
+            None => false,
+        };
+    let sender_id = packet.sender_id.to_string();
+    let nickname = super::peer(app, &packet.sender_id)
+        .map(|peer| peer.nickname)
+        .unwrap_or_else(|| sender_id.clone());
+    if verified {
+        bridge::publish(app, &sender_id, &nickname, content);
+    }
+    storage::record(
+        app,
+        StoredMessage {
+            id: hex::encode(packet.id()),
+            conversation: Conversation::Mesh,
+            sender_id: sender_id.clone(),
+            nickname: Some(nickname.clone()),
+            content: content.to_string(),
+            timestamp: packet.timestamp,
+            outgoing: false,
+            delivery: None,
+        },
+    );
+    events::emit(
+        app,
+        MeshEvent::PublicMessage {
+            sender_id,
+            nickname,
+            content: content.to_string(),
+            timestamp: packet.timestamp,
+            verified,
+        },
+    );
+    true
+}
+
+/// Send a signed public message to everyone in range of the mesh.
+#[tauri::command]
+#[specta::specta]
+pub fn mesh_broadcast(
+    app: AppHandle,
+    content: String,
+    signing: State<'_, SigningState>,
+    mesh: State<'_, MeshState>,
+) -> AppResult<()> {
+    // A leading NUL marks channel and bridged payloads.
+    if content.is_empty() || content.starts_with('\0') {
+        return Err(AppError::new(ErrorCode::InvalidArgument).with("field", "content"));
+    }
+    let key = signing.key()?;
+    let (packet, actions) = {
+        let mut node = mesh.node.lock().unwrap();
+        let now = now_ms();
+        let mut packet = Packet::new(
+            MessageType::Message,
+            node.peer_id(),
+            None,
+            content.clone().into_bytes(),
+            now,
+        );
+        sign(&mut packet, &key)?;
+        let actions = node.originate(&packet, now);
+        (packet, actions)
+    };
+    super::perform(&app, actions);
+    storage::record(
+        &app,
+        StoredMessage {
+            id: hex::encode(packet.id()),
+            conversation: Conversation::Mesh,
+            sender_id: packet.sender_id.to_string(),
+            nickname: None,
+            content,
+            timestamp: packet.timestamp,
+            outgoing: true,
+            delivery: None,
+        },
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::packet::PeerId;
+
+    #[test]
+    fn signature_survives_relaying() {
+        let key = SigningKey::from_bytes(&[5; 32]);
+        let mut packet = Packet::new(
+            MessageType::Message,
+            PeerId([1; 8]),
+            None,
+            b"hello everyone".to_vec(),
+            42,
+        );
+        assert_eq!(verify(&packet, &key.verifying_key().to_bytes()), None);
+        packet.signature = Some(key.sign(&signed_bytes(&packet).unwrap()).to_bytes());
+
+        // Relays decrement the TTL and may compress.
+        packet.ttl -= 2;
+        let relayed = Packet::decode(&packet.encode().unwrap()).unwrap();
+        assert_eq!(
+            verify(&relayed, &key.verifying_key().to_bytes()),
+            Some(true)
+        );
+
+        let mut forged = relayed.clone();
+        forged.payload = b"hello everybody".to_vec();
+        assert_eq!(
+            verify(&forged, &key.verifying_key().to_bytes()),
+            Some(false)
+        );
+        let other = SigningKey::from_bytes(&[6; 32]);
+        assert_eq!(
+            verify(&relayed, &other.verifying_key().to_bytes()),
+            Some(false)
+        );
+    }
+}
@@ -0,0 +1,404 @@
+//! Password-protected channels.
+//!
+//! Every member derives the same 256-bit key from the channel password
+//! with Argon2id, salted with the channel name, and channel messages are
+//! AES-256-GCM encrypted with it. They travel as broadcast `Message`
+//! packets whose payload is:
+//!
+//! ```text
+//! "\0chan" name_len:1 name nonce:12 ciphertext
+//! ```
+//!
+//! with the channel name as associated data. Joined channels are listed in
+//! `channels.json` in the app data directory; their keys are kept in the
+//! OS keyring.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use specta::Type;
+use tauri::{AppHandle, Manager, State};
+
+use super::packet::{MessageType, Packet};
+use super::{now_ms, MeshEvent, MeshState};
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::storage::{self, Conversation, StoredMessage};
+use crate::{events, secure_store};
+
+pub const FILE_NAME: &str = "channels.json";
+
+const PREFIX: &[u8] = b"\0chan";
+const NONCE_SIZE: usize = 12;
+const MAX_NAME: usize = 32;
+const SALT_CONTEXT: &[u8] = b"bitchat-channel:";
+const KEYRING_PREFIX: &str = "channel_key:";
+/// Argon2id cost: 64 MiB, 3 passes, one lane.
+const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+const ARGON2_ITERATIONS: u32 = 3;
+const ARGON2_LANES: u32 = 1;
+
+/// Lowercase `name` and check it looks like `#channel`.
+pub fn normalize_name(name: &str) -> AppResult<String> {
+    let name = name.trim().to_lowercase();
+    let valid = name.len() > 1
+        && name.len() <= MAX_NAME
+        && name.starts_with('#')
+        && name[1..]
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(name)
+    } else {
+        Err(AppError::new(ErrorCode::InvalidArgument).with("field", "name"))
+    }
+}
+
+pub struct ChannelKey([u8; 32]);
+
+impl ChannelKey {
+    /// Derive the key for `name` (already normalized) from `password`.
+    /// Deliberately slow; don't call it on the async runtime.
+    pub fn derive(name: &str, password: &str) -> AppResult<Self> {
+        Self::derive_with(name, password, ARGON2_MEMORY_KIB, ARGON2_ITERATIONS)
+    }
+
+    fn derive_with(
+        name: &str,
+        password: &str,
+        memory_kib: u32,
+        iterations: u32,
+    ) -> AppResult<Self> {
+        let params = Params::new(memory_kib, iterations, ARGON2_LANES, Some(32))
+            .map_err(AppError::platform)?;
+        let salt = Sha256::new()
+            .chain_update(SALT_CONTEXT)
+            .chain_update(name.as_bytes())
+            .finalize();
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), &salt, &mut key)
+            .map_err(AppError::platform)?;
+        Ok(Self(key))
+    }
+
+    /// Hex SHA-256 of the key, so members can check they typed the same
+    /// password without revealing it.
+    pub fn commitment(&self) -> String {
+        hex::encode(Sha256::digest(self.0))
+    }
+
+    pub fn encrypt(&self, name: &str, plaintext: &[u8]) -> Vec<u8> {
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let ciphertext = self
+            .cipher()
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: name.as_bytes(),
+                },
+            )
+            .expect("AES-GCM encryption of an in-memory buffer");
+        let mut out = nonce.to_vec();
+        out.extend_from_slice(&ciphertext);
+        out
+    }
+
+    pub fn decrypt(&self, name: &str, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < NONCE_SIZE {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        self.cipher()
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: name.as_bytes(),
+                },
+            )
+            .ok()
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new_from_slice(&self.0).expect("32-byte key")
+    }
+}
+
+/// Build a channel message payload.
+pub fn encode(name: &str, key: &ChannelKey, content: &str) -> Vec<u8> {
+    let mut out = PREFIX.to_vec();
+    out.push(name.len() as u8);
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(&key.encrypt(name, content.as_bytes()));
+    out
+}
+
+/// Split a channel message payload into channel name and encrypted data.
+pub fn parse(payload: &[u8]) -> Option<(&str, &[u8])> {
+    let rest = payload.strip_prefix(PREFIX)?;
+    let (&len, rest) = rest.split_first()?;
+    let name = std::str::from_utf8(rest.get(..len as usize)?).ok()?;
+    Some((name, &rest[len as usize..]))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct ChannelInfo {
+    pub name: String,
+    /// See [`ChannelKey::commitment`].
+    pub key_commitment: String,
+    /// Milliseconds since the epoch.
+    pub joined_at: u64,
+}
+
+struct Joined {
+    info: ChannelInfo,
+    key: ChannelKey,
+}
+
+pub struct ChannelsState {
+    path: PathBuf,
+    channels: Mutex<BTreeMap<String, Joined>>,
+}
+
+impl ChannelsState {
+    /// Load joined channels from `path` and their keys from the keyring.
+    /// Channels whose key is missing are dropped.
+    pub fn load(path: PathBuf) -> Self {
+        let list: Vec<ChannelInfo> = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let channels = list
+            .into_iter()
+            .filter_map(|info| match load_key(&info.name) {
+                Ok(Some(key)) => Some((info.name.clone(), Joined { info, key })),
+                Ok(None) => None,
+                Err(e) => {
+                    tracing::warn!("can't load key for {}: {}", info.name, e);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            path,
+            channels: Mutex::new(channels),
+        }
+    }
+
+    fn save(&self, channels: &BTreeMap<String, Joined>) -> AppResult<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let list: Vec<&ChannelInfo> = channels.values().map(|j| &j.info).collect();
+        let json = serde_json::to_string_pretty(&list)
+            .map_err(|e| AppError::new(ErrorCode::Io).with("message", e))?;
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        Ok(fs::rename(&tmp, &self.path)?)
+    }
+}
+
+fn load_key(name: &str) -> AppResult<Option<ChannelKey>> {
+    let Some(value) = secure_store::get(&format!("{}{}", KEYRING_PREFIX, name))? else {
+        return Ok(None);
+    };
+    let mut key = [0u8; 32];
+    hex::decode_to_slice(&value, &mut key)
+        .map_err(|_| AppError::new(ErrorCode::SecureStoreUnavailable).with("key", name))?;
+    Ok(Some(ChannelKey(key)))
+}
+
+/// Decrypt a channel message for a channel we've joined. Returns false for
+/// any other packet.
+pub fn handle_packet(app: &AppHandle, packet: &Packet) -> bool {
+    if packet.message_type != MessageType::Message
+        || !packet.recipient_id.map_or(true, |id| id.is_broadcast())
+    {
+        return false;
+    }
+    let Some((name, data)) = parse(&packet.payload) else {
+        return false;
+    };
+    let state = app.state::<ChannelsState>();
+    let channels = state.channels.lock().unwrap();
+    let Some(joined) = channels.get(name) else {
+        // Not ours to read, but still a channel message, not chat text.
+        return true;
+    };
+    let Some(content) = joined.key.decrypt(name, data) else {
+        tracing::debug!("undecryptable message in {}", name);
+        return true;
+    };
+    let content = String::from_utf8_lossy(&content).into_owned();
+    storage::record(
+        app,
+        StoredMessage {
+            id: hex::encode(packet.id()),
+            conversation: Conversation::Channel {
+                name: name.to_string(),
+            },
+            sender_id: packet.sender_id.to_string(),
+            nickname: super::peer(app, &packet.sender_id).map(|peer| peer.nickname),
+            content: content.clone(),
+            timestamp: packet.timestamp,
+            outgoing: false,
+            delivery: None,
+        },
+    );
+    events::emit(
+        app,
+        MeshEvent::ChannelMessage {
+            channel: name.to_string(),
+            sender_id: packet.sender_id.to_string(),
+            content,
+            timestamp: packet.timestamp,
+        },
+    );
+    true
+}
+
+/// Join `name` with `password`, or re-key it if already joined.
+#[tauri::command]
+#[specta::specta]
+pub async fn channel_join_protected(
+    app: AppHandle,
+    name: String,
+    password: String,
+) -> AppResult<ChannelInfo> {
+    let name = normalize_name(&name)?;
+    if password.is_empty() {
+        return Err(AppError::new(ErrorCode::InvalidArgument).with("field", "password"));
+    }
+    let key = {
+        let name = name.clone();
+        tauri::async_runtime::spawn_blocking(move || ChannelKey::derive(&name, &password))
+            .await
+            .map_err(AppError::platform)??
+    };
+    secure_store::set(&format!("{}{}", KEYRING_PREFIX, name), &hex::encode(key.0))?;
+
+    let info = ChannelInfo {
+        name: name.clone(),
+        key_commitment: key.commitment(),
+        joined_at: now_ms(),
+    };
+    let state = app.state::<ChannelsState>();
+    let mut channels = state.channels.lock().unwrap();
+    channels.insert(
+        name,
+        Joined {
+            info: info.clone(),
+            key,
+        },
+    );
+    state.save(&channels)?;
+    Ok(info)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn channel_leave(name: String, state: State<'_, ChannelsState>) -> AppResult<()> {
+    let name = normalize_name(&name)?;
+    let mut channels = state.channels.lock().unwrap();
+    if channels.remove(&name).is_some() {
+        state.save(&channels)?;
+    }
+    secure_store::delete(&format!("{}{}", KEYRING_PREFIX, name))
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn channel_list(state: State<'_, ChannelsState>) -> Vec<ChannelInfo> {
+    let channels = state.channels.lock().unwrap();
+    channels.values().map(|j| j.info.clone()).collect()
+}
+
+/// Encrypt `content` for a joined channel and broadcast it on the mesh.
+#[tauri::command]
+#[specta::specta]
+pub fn channel_send(
+    app: AppHandle,
+    name: String,
+    content: String,
+    channels: State<'_, ChannelsState>,
+    mesh: State<'_, MeshState>,
+) -> AppResult<()> {
+    let name = normalize_name(&name)?;
+    let payload = {
+        let channels = channels.channels.lock().unwrap();
+        let joined = channels
+            .get(&name)
+            .ok_or_else(|| AppError::new(ErrorCode::InvalidArgument).with("field", "name"))?;
+        encode(&name, &joined.key, &content)
+    };
+    let (packet, actions) = {
+        let mut node = mesh.node.lock().unwrap();
+        let now = now_ms();
+        let packet = Packet::new(MessageType::Message, node.peer_id(), None, payload, now);
+        let actions = node.originate(&packet, now);
+        (packet, actions)
+    };
+    super::perform(&app, actions);
+    storage::record(
+        &app,
+        StoredMessage {
+            id: hex::encode(packet.id()),
+            conversation: Conversation::Channel { name },
+            sender_id: packet.sender_id.to_string(),
+            nickname: None,
+            content,
+            timestamp: packet.timestamp,
+            outgoing: true,
+            delivery: None,
+        },
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_normalized() {
+        assert_eq!(normalize_name(" #Bitchat ").unwrap(), "#bitchat");
+        assert!(normalize_name("bitchat").is_err());
+        assert!(normalize_name("#").is_err());
+        assert!(normalize_name("#a b").is_err());
+    }
+
+    /// Cheap parameters; the real cost makes tests crawl.
+    fn derive(password: &str) -> ChannelKey {
+        ChannelKey::derive_with("#test", password, 64, 1).unwrap()
+    }
+
+    #[test]
+    fn members_with_the_same_password_share_a_key() {
+        let a = derive("hunter2");
+        let b = derive("hunter2");
+        let other = derive("hunter3");
+        assert_eq!(a.commitment(), b.commitment());
+        assert_ne!(a.commitment(), other.commitment());
+
+        let payload = encode("#test", &a, "hello");
+        let (name, data) = parse(&payload).unwrap();
+        assert_eq!(name, "#test");
+        assert_eq!(b.decrypt(name, data).unwrap(), b"hello");
+        assert_eq!(other.decrypt(name, data), None);
+        // Bound to the channel name.
+        assert_eq!(b.decrypt("#other", data), None);
+    }
+
+    #[test]
+    fn plain_text_is_not_a_channel_message() {
+        assert_eq!(parse(b"hello"), None);
+    }
+}
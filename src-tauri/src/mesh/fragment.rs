@@ -0,0 +1,502 @@
+//! Splitting packets that don't fit in one frame, and putting them back
+//! together.
+//!
+//! As in the native apps, the whole encoded packet is cut into chunks and
+//! each chunk travels as a fragment packet (start, continue, end) whose
+//! payload is:
+//!
+//! ```text
+//! fragment_id:8 index:2 total:2 original_type:1 data
+//! ```
+//!
+//! Fragments may arrive in any order; incomplete messages are dropped after
+//! [`REASSEMBLY_TIMEOUT_MS`].
+//!
+//! Fragmented messages addressed to one peer get feedback, so a lossy link
+//! only costs the fragments it dropped. The recipient acknowledges a
+//! complete message with a `FragmentAck`, and when fragments stop arriving
+//! for [`NACK_AFTER_MS`] it asks for the missing ones with a `FragmentNack`.
+//! The sender keeps what it sent in an [`Outbox`] until acknowledged.
+//! Native apps have no such feedback, so these two types use codes
+//! (`0xf0`, `0xf1`) outside the range they assign; they relay them like any
+//! unknown type.
+//!
+//! ```text
+//! ack:  fragment_id:8
+//! nack: fragment_id:8 index:2..
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::packet::{MessageType, Packet, PeerId, HEADER_SIZE, PEER_ID_SIZE};
+use crate::error::{AppError, AppResult, ErrorCode};
+
+/// Frame size used when the transport doesn't say otherwise; fits the
+/// 512-byte ATT limit used by the mobile apps.
+pub const DEFAULT_MAX_FRAME: usize = 512;
+pub const FRAGMENT_HEADER_SIZE: usize = 13;
+pub const REASSEMBLY_TIMEOUT_MS: u64 = 30_000;
+/// Upper bounds so a misbehaving peer can't make us buffer without limit.
+pub const MAX_FRAGMENTS: u16 = 1024;
+const MAX_PENDING_MESSAGES: usize = 128;
+/// Ask for missing fragments after this long without a new one.
+pub const NACK_AFTER_MS: u64 = 3_000;
+/// Ask at most this many times per message.
+pub const MAX_NACKS: u8 = 3;
+/// Keeps a nack within one default frame.
+const MAX_NACK_INDICES: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentHeader {
+    pub fragment_id: [u8; 8],
+    pub index: u16,
+    pub total: u16,
+    pub original_type: MessageType,
+}
+
+impl FragmentHeader {
+    pub fn parse(payload: &[u8]) -> AppResult<(Self, &[u8])> {
+        if payload.len() < FRAGMENT_HEADER_SIZE {
+            return Err(AppError::new(ErrorCode::MalformedPacket).with("reason", "fragment_header"));
+        }
+        let header = Self {
+            fragment_id: payload[0..8].try_into().unwrap(),
+            index: u16::from_be_bytes([payload[8], payload[9]]),
+            total: u16::from_be_bytes([payload[10], payload[11]]),
+            original_type: MessageType::from_u8(payload[12]),
+        };
+        if header.total == 0 || header.total > MAX_FRAGMENTS || header.index >= header.total {
+            return Err(AppError::new(ErrorCode::MalformedPacket).with("reason", "fragment_index"));
+        }
+        Ok((header, &payload[FRAGMENT_HEADER_SIZE..]))
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.fragment_id);
+        out.extend_from_slice(&self.index.to_be_bytes());
+        out.extend_from_slice(&self.total.to_be_bytes());
+        out.push(self.original_type.as_u8());
+    }
+}
+
+pub fn is_fragment(message_type: MessageType) -> bool {
+    matches!(
+        message_type,
+        MessageType::FragmentStart | MessageType::FragmentContinue | MessageType::FragmentEnd
+    )
+}
+
+/// Split `packet` into frames of at most `max_frame` bytes. Packets that
+/// already fit come back as a single unfragmented packet.
+pub fn split(packet: &Packet, max_frame: usize) -> AppResult<Vec<Packet>> {
+    split_with(packet, max_frame, true)
+}
+
+/// [`split`], sizing frames as encoded with or without `compression`.
+pub fn split_with(packet: &Packet, max_frame: usize, compression: bool) -> AppResult<Vec<Packet>> {
+    let encoded = packet.encode_with(compression)?;
+    if encoded.len() <= max_frame {
+        return Ok(vec![packet.clone()]);
+    }
+
+    let overhead = HEADER_SIZE
+        + PEER_ID_SIZE
+        + packet.recipient_id.map_or(0, |_| PEER_ID_SIZE)
+        + FRAGMENT_HEADER_SIZE;
+    if max_frame <= overhead {
+        return Err(AppError::new(ErrorCode::InvalidArgument).with("field", "max_frame"));
+    }
+    let chunk_size = max_frame - overhead;
+    let total = encoded.len().div_ceil(chunk_size);
+    if total > MAX_FRAGMENTS as usize {
+        return Err(AppError::new(ErrorCode::PayloadTooLarge)
+            .with("size", encoded.len())
+            .with("max", MAX_FRAGMENTS as usize * chunk_size));
+    }
+
+    let fragment_id = rand::random();
+    Ok(encoded
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let message_type = match index {
+                0 => MessageType::FragmentStart,
+                i if i + 1 == total => MessageType::FragmentEnd,
+                _ => MessageType::FragmentContinue,
+            };
+            let mut payload = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            FragmentHeader {
+                fragment_id,
+                index: index as u16,
+                total: total as u16,
+                original_type: packet.message_type,
+            }
+            .write(&mut payload);
+            payload.extend_from_slice(chunk);
+            Packet {
+                message_type,
+                payload,
+                signature: None,
+                ..packet.clone()
+            }
+        })
+        .collect())
+}
+
+/// What the recipient of a private fragmented message tells its sender.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Feedback {
+    Ack {
+        fragment_id: [u8; 8],
+    },
+    Nack {
+        fragment_id: [u8; 8],
+        missing: Vec<u16>,
+    },
+}
+
+impl Feedback {
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Self::Ack { .. } => MessageType::FragmentAck,
+            Self::Nack { .. } => MessageType::FragmentNack,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::Ack { fragment_id } => fragment_id.to_vec(),
+            Self::Nack {
+                fragment_id,
+                missing,
+            } => {
+                let mut out = fragment_id.to_vec();
+                for index in missing {
+                    out.extend_from_slice(&index.to_be_bytes());
+                }
+                out
+            }
+        }
+    }
+
+    pub fn parse(packet: &Packet) -> AppResult<Self> {
+        let malformed =
+            || AppError::new(ErrorCode::MalformedPacket).with("reason", "fragment_feedback");
+        let (fragment_id, rest) = packet
+            .payload
+            .split_first_chunk::<8>()
+            .ok_or_else(malformed)?;
+        match packet.message_type {
+            MessageType::FragmentAck if rest.is_empty() => Ok(Self::Ack {
+                fragment_id: *fragment_id,
+            }),
+            MessageType::FragmentNack if !rest.is_empty() && rest.len() % 2 == 0 => {
+                Ok(Self::Nack {
+                    fragment_id: *fragment_id,
+                    missing: rest
+                        .chunks_exact(2)
+                        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                        .collect(),
+                })
+            }
+            _ => Err(malformed()),
+        }
+    }
+}
+
+struct Pending {
+    total: u16,
+    chunks: BTreeMap<u16, Vec<u8>>,
+    started_at: u64,
+    /// When the last fragment arrived, or we last asked for the rest.
+    updated_at: u64,
+    /// Addressed to us alone, so its sender wants feedback.
+    private: bool,
+    nacks: u8,
+}
+
+/// Reassembly buffers, keyed by sender and fragment ID.
+#[derive(Default)]
+pub struct Reassembler {
+    pending: HashMap<(PeerId, [u8; 8]), Pending>,
+}
+
+impl Reassembler {
+    /// Feed one fragment packet. Returns the original packet once every
+    /// fragment has arrived.
+    pub fn accept(&mut self, fragment: &Packet, now: u64) -> AppResult<Option<Packet>> {
+        self.expire(now);
+        let (header, data) = FragmentHeader::parse(&fragment.payload)?;
+        let key = (fragment.sender_id, header.fragment_id);
+
+        if !self.pending.contains_key(&key) && self.pending.len() >= MAX_PENDING_MESSAGES {
+            // Make room by dropping the oldest incomplete message.
+            if let Some(oldest) = self
+                .pending
+                .iter()
+                .min_by_key(|(_, p)| p.started_at)
+                .map(|(k, _)| *k)
+            {
+                self.pending.remove(&oldest);
+            }
+        }
+        let pending = self.pending.entry(key).or_insert_with(|| Pending {
+            total: header.total,
+            chunks: BTreeMap::new(),
+            started_at: now,
+            updated_at: now,
+            private: fragment.recipient_id.is_some_and(|id| !id.is_broadcast()),
+            nacks: 0,
+        });
+        if pending.total != header.total {
+            self.pending.remove(&key);
+            return Err(AppError::new(ErrorCode::MalformedPacket).with("reason", "fragment_total"));
+        }
+        pending.chunks.insert(header.index, data.to_vec());
+        pending.updated_at = now;
+        if pending.chunks.len() < pending.total as usize {
+            return Ok(None);
+        }
+
+        let pending = self.pending.remove(&key).unwrap();
+        let bytes: Vec<u8> = pending.chunks.into_values().flatten().collect();
+        Packet::decode(&bytes).map(Some)
+    }
+
+    /// Drop incomplete messages older than [`REASSEMBLY_TIMEOUT_MS`].
+    pub fn expire(&mut self, now: u64) {
+        self.pending
+            .retain(|_, p| now.saturating_sub(p.started_at) < REASSEMBLY_TIMEOUT_MS);
+    }
+
+    /// Nacks for private messages that have stalled, with the peer each
+    /// goes to.
+    pub fn nacks(&mut self, now: u64) -> Vec<(PeerId, Feedback)> {
+        self.expire(now);
+        self.pending
+            .iter_mut()
+            .filter(|(_, p)| {
+                p.private
+                    && p.nacks < MAX_NACKS
+                    && now.saturating_sub(p.updated_at) >= NACK_AFTER_MS
+            })
+            .map(|((sender, fragment_id), p)| {
+                p.nacks += 1;
+                p.updated_at = now;
+                let missing = (0..p.total)
+                    .filter(|i| !p.chunks.contains_key(i))
+                    .take(MAX_NACK_INDICES)
+                    .collect();
+                (
+                    *sender,
+                    Feedback::Nack {
+                        fragment_id: *fragment_id,
+                        missing,
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+struct Sent {
+    recipient: PeerId,
+    fragments: Vec<Packet>,
+    sent_at: u64,
+}
+
+/// Fragments of our own private messages, kept until their recipient
+/// acknowledges them or could no longer reassemble them.
+#[derive(Default)]
+pub struct Outbox {
+    sent: HashMap<[u8; 8], Sent>,
+}
+
+impl Outbox {
+    /// Keep `fragments` (from [`split`]) if they make up a private
+    /// fragmented message.
+    pub fn track(&mut self, fragments: &[Packet], now: u64) {
+        let Some(first) = fragments.first() else {
+            return;
+        };
+        let Some(recipient) = first.recipient_id.filter(|id| !id.is_broadcast()) else {
+            return;
+        };
+        if !is_fragment(first.message_type) {
+            return;
+        }
+        let Ok((header, _)) = FragmentHeader::parse(&first.payload) else {
+            return;
+        };
+        self.expire(now);
+        self.sent.insert(
+            header.fragment_id,
+            Sent {
+                recipient,
+                fragments: fragments.to_vec(),
+                sent_at: now,
+            },
+        );
+    }
+
+    /// Act on feedback from `from`: forget acknowledged messages, and
+    /// return the fragments a nack asks for, restamped so relays don't take
+    /// them for duplicates.
+    pub fn feedback(&mut self, from: PeerId, feedback: &Feedback, now: u64) -> Vec<Packet> {
+        match feedback {
+            Feedback::Ack { fragment_id } => {
+                if self
+                    .sent
+                    .get(fragment_id)
+                    .is_some_and(|s| s.recipient == from)
+                {
+                    self.sent.remove(fragment_id);
+                }
+                Vec::new()
+            }
+            Feedback::Nack {
+                fragment_id,
+                missing,
+            } => {
+                let Some(sent) = self.sent.get(fragment_id).filter(|s| s.recipient == from) else {
+                    return Vec::new();
+                };
+                missing
+                    .iter()
+                    .filter_map(|&i| sent.fragments.get(i as usize))
+                    .map(|fragment| Packet {
+                        timestamp: now,
+                        ..fragment.clone()
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Forget messages the recipient has given up reassembling.
+    pub fn expire(&mut self, now: u64) {
+        self.sent
+            .retain(|_, s| now.saturating_sub(s.sent_at) < REASSEMBLY_TIMEOUT_MS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(payload_len: usize) -> Packet {
+        Packet {
+            version: 1,
+            message_type: MessageType::Message,
+            ttl: 5,
+            timestamp: 42,
+            sender_id: PeerId([3; 8]),
+            recipient_id: Some(PeerId([4; 8])),
+            // Incompressible, so the encoded size tracks `payload_len`.
+            payload: (0..payload_len as u32)
+                .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+                .collect(),
+            signature: Some([9; 64]),
+        }
+    }
+
+    #[test]
+    fn small_packets_are_not_fragmented() {
+        let p = packet(10);
+        assert_eq!(split(&p, DEFAULT_MAX_FRAME).unwrap(), vec![p]);
+    }
+
+    #[test]
+    fn fragments_fit_the_frame_and_reassemble_in_any_order() {
+        let p = packet(5000);
+        let mut fragments = split(&p, DEFAULT_MAX_FRAME).unwrap();
+        assert!(fragments.len() > 2);
+        assert_eq!(fragments[0].message_type, MessageType::FragmentStart);
+        assert_eq!(
+            fragments.last().unwrap().message_type,
+            MessageType::FragmentEnd
+        );
+        for f in &fragments {
+            assert!(f.encode().unwrap().len() <= DEFAULT_MAX_FRAME);
+        }
+
+        fragments.reverse();
+        fragments.swap(0, 1);
+        let mut r = Reassembler::default();
+        let (last, rest) = fragments.split_last().unwrap();
+        for f in rest {
+            assert_eq!(r.accept(f, 0).unwrap(), None);
+        }
+        assert_eq!(r.accept(last, 0).unwrap(), Some(p));
+    }
+
+    #[test]
+    fn incomplete_messages_time_out() {
+        let fragments = split(&packet(2000), DEFAULT_MAX_FRAME).unwrap();
+        let mut r = Reassembler::default();
+        for f in &fragments[1..] {
+            r.accept(f, 0).unwrap();
+        }
+        assert_eq!(
+            r.accept(&fragments[0], REASSEMBLY_TIMEOUT_MS).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn resends_only_the_fragments_a_nack_asks_for() {
+        let fragments = split(&packet(3000), DEFAULT_MAX_FRAME).unwrap();
+        let mut outbox = Outbox::default();
+        outbox.track(&fragments, 0);
+
+        let mut r = Reassembler::default();
+        for (i, f) in fragments.iter().enumerate() {
+            if i != 1 && i != 4 {
+                assert_eq!(r.accept(f, 0).unwrap(), None);
+            }
+        }
+        assert!(r.nacks(NACK_AFTER_MS - 1).is_empty());
+        let mut nacks = r.nacks(NACK_AFTER_MS);
+        let (to, nack) = nacks.pop().unwrap();
+        assert_eq!(to, PeerId([3; 8]));
+        let nack = Feedback::parse(&Packet::new(
+            nack.message_type(),
+            PeerId([4; 8]),
+            Some(to),
+            nack.encode(),
+            0,
+        ))
+        .unwrap();
+        let Feedback::Nack {
+            fragment_id,
+            missing,
+        } = &nack
+        else {
+            panic!("expected a nack");
+        };
+        assert_eq!(missing, &[1, 4]);
+
+        // Only the recipient's feedback counts.
+        assert!(outbox.feedback(PeerId([5; 8]), &nack, 10).is_empty());
+        let resent = outbox.feedback(PeerId([4; 8]), &nack, 10);
+        assert_eq!(resent.len(), 2);
+        assert_ne!(resent[0].id(), fragments[1].id());
+        assert_eq!(r.accept(&resent[0], 10).unwrap(), None);
+        assert_eq!(r.accept(&resent[1], 10).unwrap(), Some(packet(3000)));
+
+        let ack = Feedback::Ack {
+            fragment_id: *fragment_id,
+        };
+        outbox.feedback(PeerId([4; 8]), &ack, 20);
+        assert!(outbox.feedback(PeerId([4; 8]), &nack, 30).is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_fragment_headers() {
+        let mut f = split(&packet(2000), DEFAULT_MAX_FRAME).unwrap().remove(0);
+        f.payload[10..12].copy_from_slice(&0u16.to_be_bytes());
+        assert!(Reassembler::default().accept(&f, 0).is_err());
+        f.payload.truncate(5);
+        assert!(Reassembler::default().accept(&f, 0).is_err());
+    }
+}
@@ -0,0 +1,404 @@
+//! The bitchat mesh protocol, independent of the transport carrying it.
+//!
+//! A single [`node::Node`] owns the protocol state. [`start`] spawns the
+//! loop that feeds it everything the transports report and carries out the
+//! resulting actions: packets for us go to the frontend as [`MeshEvent`]s,
+//! relayed frames go back out through [`crate::transport`]. Noise
+//! handshakes are scheduled here (see [`sessions`]) but performed by the
+//! frontend, which reports back through the `mesh_session_*` commands.
+
+pub mod bloom;
+pub mod bridge;
+pub mod broadcast;
+pub mod channels;
+pub mod fragment;
+pub mod node;
+pub mod packet;
+pub mod peers;
+pub mod receipts;
+pub mod sessions;
+#[cfg(test)]
+mod sim;
+pub mod store_forward;
+pub mod version;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use base64::Engine;
+use rand::Rng;
+use serde::Serialize;
+use specta::Type;
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::mpsc;
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events::{self, BackendEvent};
+use crate::favorites;
+use crate::settings::SettingsState;
+use crate::storage;
+use crate::transport::{self, Inbound, TransportEvent};
+use node::{Action, Node, RelayStats};
+use packet::{Packet, PeerId, WirePacket};
+use peers::{Announcement, PeerInfo};
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MeshEvent {
+    PacketReceived {
+        link_id: String,
+        packet: WirePacket,
+    },
+    PeerDiscovered {
+        peer: PeerInfo,
+    },
+    PeerLost {
+        peer_id: String,
+    },
+    /// The peer moved to another proximity bucket.
+    PeerSignalChanged {
+        peer: PeerInfo,
+    },
+    /// Run the Noise XX handshake with this peer, as initiator.
+    HandshakeRequested {
+        peer_id: String,
+        noise_public_key: String,
+    },
+    /// Transport encryption with the peer is established.
+    PeerReady {
+        peer: PeerInfo,
+    },
+    /// Handshakes with the peer kept failing; we've stopped trying.
+    HandshakeFailed {
+        peer_id: String,
+    },
+    /// Public chat from someone nearby. `verified` if it was signed with
+    /// the key the sender announced.
+    PublicMessage {
+        sender_id: String,
+        nickname: String,
+        content: String,
+        timestamp: u64,
+        verified: bool,
+    },
+    /// A delivery ack or read receipt from `peer_id`, still encrypted
+    /// (base64). See [`receipts`].
+    ReceiptReceived {
+        peer_id: String,
+        kind: receipts::ReceiptKind,
+        payload: String,
+    },
+    /// A decrypted message in a joined password-protected channel.
+    ChannelMessage {
+        channel: String,
+        sender_id: String,
+        content: String,
+        timestamp: u64,
+    },
+    /// A geohash channel message, broadcast on the mesh by the bridge node
+    /// `bridge_id`.
+    NostrMessage {
+        event_id: String,
+        nickname: String,
+        content: String,
+        bridge_id: String,
+        timestamp: u64,
+    },
+    /// Public mesh chat to publish into the bridged geohash channel, with
+    /// these tags.
+    BridgePublish {
+        geohash: String,
+        content: String,
+        tags: Vec<Vec<String>>,
+    },
+}
+
+impl From<MeshEvent> for BackendEvent {
+    fn from(event: MeshEvent) -> Self {
+        BackendEvent::Mesh(event)
+    }
+}
+
+/// How often [`Node::tick`] runs.
+const TICK_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct MeshState {
+    node: Mutex<Node>,
+    inbound: mpsc::UnboundedSender<Inbound>,
+}
+
+pub fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+pub fn start(app: &AppHandle) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    app.manage(MeshState {
+        node: Mutex::new(Node::new(PeerId::random(), rand::random())),
+        inbound: tx,
+    });
+
+    let ticker = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(TICK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let actions = ticker
+                .state::<MeshState>()
+                .node
+                .lock()
+                .unwrap()
+                .tick(now_ms());
+            perform(&ticker, actions);
+        }
+    });
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        while let Some(input) = rx.recv().await {
+            let actions = handle(&app, input);
+            perform(&app, actions);
+        }
+    });
+}
+
+/// Where transports report links and frames.
+pub fn inbound(app: &AppHandle) -> mpsc::UnboundedSender<Inbound> {
+    app.state::<MeshState>().inbound.clone()
+}
+
+/// `peer_id`, if it is currently reachable over the mesh.
+pub fn peer(app: &AppHandle, peer_id: &PeerId) -> Option<PeerInfo> {
+    app.state::<MeshState>().node.lock().unwrap().peer(peer_id)
+}
+
+/// The signing key `peer_id` announced, if any.
+pub fn signing_key(app: &AppHandle, peer_id: &PeerId) -> Option<[u8; 32]> {
+    app.state::<MeshState>()
+        .node
+        .lock()
+        .unwrap()
+        .signing_key(peer_id)
+}
+
+/// The nearby peer announcing `noise_public_key`, if any.
+pub fn find_peer(app: &AppHandle, noise_public_key: &[u8; 32]) -> Option<PeerId> {
+    app.state::<MeshState>()
+        .node
+        .lock()
+        .unwrap()
+        .find_peer(noise_public_key)
+}
+
+/// Give `peer_id` the longer store-and-forward retention.
+pub fn set_favorite(app: &AppHandle, peer_id: PeerId, favorite: bool) {
+    app.state::<MeshState>()
+        .node
+        .lock()
+        .unwrap()
+        .set_favorite(peer_id, favorite);
+}
+
+fn handle(app: &AppHandle, input: Inbound) -> Vec<Action> {
+    let mut node = app.state::<MeshState>().node.lock().unwrap();
+    match input {
+        Inbound::LinkUp { link_id, name } => {
+            let actions = node.link_up(&link_id, now_ms());
+            events::emit(app, TransportEvent::LinkUp { link_id, name });
+            actions
+        }
+        Inbound::LinkDown { link_id } => {
+            node.link_down(&link_id);
+            events::emit(app, TransportEvent::LinkDown { link_id });
+            Vec::new()
+        }
+        Inbound::Rssi { link_id, rssi } => node.set_rssi(&link_id, rssi),
+        Inbound::Frame { link_id, data } => node.handle_frame(&link_id, &data, now_ms()),
+    }
+}
+
+fn perform(app: &AppHandle, actions: Vec<Action>) {
+    for action in actions {
+        match action {
+            Action::Deliver { link_id, packet } => {
+                if receipts::handle_packet(app, &packet)
+                    || channels::handle_packet(app, &packet)
+                    || bridge::handle_packet(app, &packet)
+                {
+                    continue;
+                }
+                if broadcast::handle_packet(app, &packet) {
+                    continue;
+                }
+                events::emit(
+                    app,
+                    MeshEvent::PacketReceived {
+                        link_id,
+                        packet: WirePacket::from(&packet),
+                    },
+                )
+            }
+            Action::Broadcast {
+                data,
+                exclude,
+                delay_ms,
+                pad,
+            } => {
+                let (data, delay_ms) = shape(app, data, delay_ms, pad);
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                    transport::broadcast(&app, &data, exclude.as_deref()).await;
+                });
+            }
+            Action::Emit(event) => {
+                if let MeshEvent::PeerDiscovered { peer } = &event {
+                    favorites::peer_discovered(app, peer);
+                    storage::contacts::peer_discovered(app, peer);
+                    // Queued messages may be able to go out now.
+                    transport::delivery::retry(app);
+                }
+                events::emit(app, event)
+            }
+            Action::Send { link_id, data, pad } => {
+                let (data, delay_ms) = shape(app, data, 0, pad);
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                    if let Err(e) = transport::send_to(&app, &link_id, &data).await {
+                        tracing::debug!("send to {} failed: {}", link_id, e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Pad an outgoing frame (if its receivers accept padding) and add send
+/// jitter to its delay, as the traffic privacy settings ask.
+fn shape(app: &AppHandle, data: Vec<u8>, delay_ms: u64, pad: bool) -> (Vec<u8>, u64) {
+    let traffic = app.state::<SettingsState>().get().privacy.traffic;
+    let data = if pad && traffic.pad_packets {
+        packet::pad(data)
+    } else {
+        data
+    };
+    let jitter = rand::thread_rng().gen_range(0..=traffic.max_send_jitter_ms);
+    (data, delay_ms + jitter)
+}
+
+/// This node's mesh peer ID, for building packets with `mesh_encode_packet`.
+#[tauri::command]
+#[specta::specta]
+pub fn mesh_get_peer_id(state: State<'_, MeshState>) -> String {
+    state.node.lock().unwrap().peer_id().to_string()
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn mesh_get_peers(state: State<'_, MeshState>) -> Vec<PeerInfo> {
+    state.node.lock().unwrap().peers()
+}
+
+/// Start announcing ourselves to nearby peers under `nickname`. The Noise
+/// public key (hex) lets native clients open a session with us.
+#[tauri::command]
+#[specta::specta]
+pub fn mesh_announce(
+    app: AppHandle,
+    nickname: String,
+    noise_public_key: Option<String>,
+    signing: State<'_, broadcast::SigningState>,
+    state: State<'_, MeshState>,
+) -> AppResult<()> {
+    let noise_public_key = match noise_public_key {
+        Some(key) => {
+            let mut bytes = [0u8; 32];
+            hex::decode_to_slice(&key, &mut bytes).map_err(|_| {
+                AppError::new(ErrorCode::InvalidArgument).with("field", "noise_public_key")
+            })?;
+            Some(bytes)
+        }
+        None => None,
+    };
+    let announcement = Announcement {
+        nickname,
+        noise_public_key,
+        signing_public_key: signing.public_key(),
+    };
+    let actions = state
+        .node
+        .lock()
+        .unwrap()
+        .set_announcement(announcement, now_ms());
+    perform(&app, actions);
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn mesh_get_relay_stats(state: State<'_, MeshState>) -> RelayStats {
+    state.node.lock().unwrap().stats()
+}
+
+/// Send a packet built by the frontend (e.g. a Noise handshake message)
+/// over the mesh, fragmenting it if needed.
+#[tauri::command]
+#[specta::specta]
+pub fn mesh_send_packet(
+    app: AppHandle,
+    packet: WirePacket,
+    state: State<'_, MeshState>,
+) -> AppResult<()> {
+    let packet = Packet::try_from(packet)?;
+    let actions = state.node.lock().unwrap().originate(&packet, now_ms());
+    perform(&app, actions);
+    Ok(())
+}
+
+/// Send a private message the router handed over as
+/// [`TransportEvent::MeshSend`], once the frontend has sealed it with the
+/// Noise session with `peer_id`. `ciphertext` is base64.
+#[tauri::command]
+#[specta::specta]
+pub fn mesh_send_private(
+    app: AppHandle,
+    peer_id: String,
+    ciphertext: String,
+    state: State<'_, MeshState>,
+) -> AppResult<()> {
+    let peer_id = peer_id.parse()?;
+    let ciphertext = base64::engine::general_purpose::STANDARD
+        .decode(ciphertext)
+        .map_err(|_| AppError::new(ErrorCode::InvalidArgument).with("field", "ciphertext"))?;
+    let actions = state
+        .node
+        .lock()
+        .unwrap()
+        .send_private(peer_id, ciphertext, now_ms());
+    perform(&app, actions);
+    Ok(())
+}
+
+/// Report that the Noise handshake with `peer_id` completed.
+#[tauri::command]
+#[specta::specta]
+pub fn mesh_session_established(
+    app: AppHandle,
+    peer_id: String,
+    state: State<'_, MeshState>,
+) -> AppResult<()> {
+    let peer_id = peer_id.parse()?;
+    let actions = state.node.lock().unwrap().session_established(peer_id);
+    perform(&app, actions);
+    Ok(())
+}
+
+/// Report that the Noise handshake with `peer_id` failed, so it's retried.
+#[tauri::command]
+#[specta::specta]
+pub fn mesh_session_failed(peer_id: String, state: State<'_, MeshState>) -> AppResult<()> {
+    let peer_id = peer_id.parse()?;
+    state.node.lock().unwrap().session_failed(peer_id, now_ms());
+    Ok(())
+}
@@ -0,0 +1,923 @@
+//! The mesh node's decision logic, kept free of I/O.
+//!
+//! [`Node::handle_frame`] takes one inbound frame and returns the
+//! [`Action`]s it implies: packets to deliver locally and frames to relay.
+//! Time and randomness come from the caller, so the same logic runs under
+//! the real transports and in tests.
+//!
+//! Private packets relayed for a peer we can't currently reach are also
+//! kept in [`StoreForward`] and re-sent when that peer announces itself.
+//! Announces feed the [`Roster`]; [`Node::tick`] expires silent peers,
+//! re-sends our own announce and retries due Noise handshakes.
+//!
+//! Private fragmented messages are acknowledged, and only the fragments the
+//! recipient reports missing are resent (see [`super::fragment`]).
+//!
+//! Each new link starts with a version hello (see [`super::version`]);
+//! compression and padding are only used where the negotiated features
+//! allow.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use specta::Type;
+
+use super::bloom::{BloomConfig, DecayingBloom};
+use super::fragment::{self, Feedback, Outbox, Reassembler};
+use super::packet::{MessageType, Packet, PeerId};
+use super::peers::{Announced, Announcement, PeerInfo, Roster};
+use super::sessions::Sessions;
+use super::store_forward::StoreForward;
+use super::version::{self, Ack, Features, Hello, Versions};
+use super::MeshEvent;
+
+/// Random delay before relaying, so neighbors that heard the same packet
+/// don't all transmit at once.
+pub const RELAY_DELAY_MS: RangeInclusive<u64> = 10..=100;
+/// Relay probability never drops below this, however dense the mesh.
+const MIN_RELAY_PROBABILITY: f64 = 0.4;
+/// A peer heard from this recently is assumed to be in range.
+const REACHABLE_MS: u64 = 60_000;
+/// Packets are only cached for peers heard from this recently (or favorites).
+const RECENT_PEER_MS: u64 = 24 * 60 * 60 * 1000;
+pub const ANNOUNCE_INTERVAL_MS: u64 = 30_000;
+
+#[derive(Debug, Clone, Default, Serialize, Type)]
+pub struct RelayStats {
+    pub received: u64,
+    pub delivered: u64,
+    pub relayed: u64,
+    /// Already seen, or our own packet coming back.
+    pub duplicates: u64,
+    /// TTL ran out here.
+    pub expired: u64,
+    /// Skipped by gossip damping.
+    pub damped: u64,
+    pub malformed: u64,
+    /// Stored for a peer that was out of range.
+    pub cached: u64,
+    /// Sent from the cache when the peer came back.
+    pub forwarded_from_cache: u64,
+    /// Currently held in the cache.
+    pub held: u64,
+    /// Memory used by the duplicate filter.
+    pub dedup_bytes: u64,
+    /// Fragments sent again because the recipient reported them missing.
+    pub fragments_resent: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// Hand a packet (reassembled if it was fragmented) to the app.
+    Deliver { link_id: String, packet: Packet },
+    /// Send a frame on every link except `exclude`, after `delay_ms`.
+    /// `pad` says whether the receivers accept padded frames.
+    Broadcast {
+        data: Vec<u8>,
+        exclude: Option<String>,
+        delay_ms: u64,
+        pad: bool,
+    },
+    /// Send a frame on one link.
+    Send {
+        link_id: String,
+        data: Vec<u8>,
+        pad: bool,
+    },
+    /// Tell the frontend something changed.
+    Emit(MeshEvent),
+}
+
+pub struct Node {
+    peer_id: PeerId,
+    rng: StdRng,
+    links: HashSet<String>,
+    seen: DecayingBloom,
+    reassembler: Reassembler,
+    outbox: Outbox,
+    /// Peer ID -> when we last heard from it.
+    last_seen: HashMap<PeerId, u64>,
+    store: StoreForward,
+    roster: Roster,
+    sessions: Sessions,
+    versions: Versions,
+    /// Link ID -> the peer at the other end: the sender of a version packet
+    /// on it (those travel one hop), or else of the first announce on it
+    /// from a peer not heard on another link.
+    neighbors: HashMap<String, PeerId>,
+    /// What we announce about ourselves, once the frontend has told us.
+    announcement: Option<Announcement>,
+    last_announce: u64,
+    stats: RelayStats,
+}
+
+impl Node {
+    pub fn new(peer_id: PeerId, seed: u64) -> Self {
+        Self {
+            peer_id,
+            rng: StdRng::seed_from_u64(seed),
+            links: HashSet::new(),
+            seen: DecayingBloom::new(BloomConfig::default()),
+            reassembler: Reassembler::default(),
+            outbox: Outbox::default(),
+            last_seen: HashMap::new(),
+            store: StoreForward::default(),
+            roster: Roster::default(),
+            sessions: Sessions::default(),
+            versions: Versions::default(),
+            neighbors: HashMap::new(),
+            announcement: None,
+            last_announce: 0,
+            stats: RelayStats::default(),
+        }
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    pub fn stats(&self) -> RelayStats {
+        RelayStats {
+            held: self.store.len() as u64,
+            dedup_bytes: self.seen.memory_bytes() as u64,
+            ..self.stats.clone()
+        }
+    }
+
+    /// Give `peer_id` the longer store-and-forward retention.
+    pub fn set_favorite(&mut self, peer_id: PeerId, favorite: bool) {
+        self.store.set_favorite(peer_id, favorite);
+    }
+
+    /// Features agreed with the neighbor on `link_id`.
+    pub fn features(&self, link_id: &str) -> Features {
+        self.versions.features(Some(link_id))
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        self.roster
+            .list()
+            .into_iter()
+            .map(|peer| self.complete(peer))
+            .collect()
+    }
+
+    /// `peer_id`, if it has announced itself and hasn't timed out since.
+    pub fn peer(&self, peer_id: &PeerId) -> Option<PeerInfo> {
+        self.roster.get(peer_id).map(|peer| self.complete(peer))
+    }
+
+    /// Fill in what the roster doesn't know about `peer`.
+    fn complete(&self, mut peer: PeerInfo) -> PeerInfo {
+        if let Ok(peer_id) = peer.peer_id.parse() {
+            peer.session = self.sessions.status(&peer_id);
+            peer.protocol_version = self.versions.version(&peer_id);
+        }
+        peer
+    }
+
+    /// The frontend established a Noise session with `peer_id`.
+    pub fn session_established(&mut self, peer_id: PeerId) -> Vec<Action> {
+        self.roster.bind(&peer_id);
+        if !self.sessions.established(peer_id) {
+            return Vec::new();
+        }
+        self.peer(&peer_id)
+            .map(|peer| Action::Emit(MeshEvent::PeerReady { peer }))
+            .into_iter()
+            .collect()
+    }
+
+    /// The frontend's handshake with `peer_id` failed; it will be retried.
+    pub fn session_failed(&mut self, peer_id: PeerId, now: u64) {
+        self.sessions.failed(peer_id, now);
+    }
+
+    pub fn signing_key(&self, peer_id: &PeerId) -> Option<[u8; 32]> {
+        self.roster.signing_key(peer_id)
+    }
+
+    /// The nearby peer announcing `noise_public_key`, if any.
+    pub fn find_peer(&self, noise_public_key: &[u8; 32]) -> Option<PeerId> {
+        self.roster.find(noise_public_key)
+    }
+
+    /// Send a private message to `recipient` over the mesh. `ciphertext`
+    /// is sealed with our Noise session with the peer.
+    pub fn send_private(
+        &mut self,
+        recipient: PeerId,
+        ciphertext: Vec<u8>,
+        now: u64,
+    ) -> Vec<Action> {
+        let packet = Packet::new(
+            MessageType::NoiseEncrypted,
+            self.peer_id,
+            Some(recipient),
+            ciphertext,
+            now,
+        );
+        self.originate(&packet, now)
+    }
+
+    /// A link came up; say hello on it.
+    pub fn link_up(&mut self, link_id: &str, now: u64) -> Vec<Action> {
+        self.links.insert(link_id.to_string());
+        let hello = Packet::new(
+            MessageType::VersionHello,
+            self.peer_id,
+            None,
+            Hello::ours().encode(),
+            now,
+        );
+        self.send_one_hop(link_id, hello).into_iter().collect()
+    }
+
+    pub fn link_down(&mut self, link_id: &str) {
+        self.links.remove(link_id);
+        self.roster.link_down(link_id);
+        self.versions.link_down(link_id);
+        self.neighbors.remove(link_id);
+    }
+
+    /// Record a signal reading; peers on the link whose proximity changed
+    /// are reported.
+    pub fn set_rssi(&mut self, link_id: &str, rssi: i16) -> Vec<Action> {
+        if !self.roster.set_rssi(link_id, rssi) {
+            return Vec::new();
+        }
+        self.peers()
+            .into_iter()
+            .filter(|peer| peer.link_id == link_id)
+            .map(|peer| Action::Emit(MeshEvent::PeerSignalChanged { peer }))
+            .collect()
+    }
+
+    /// Set what we announce about ourselves and announce it right away.
+    pub fn set_announcement(&mut self, announcement: Announcement, now: u64) -> Vec<Action> {
+        self.announcement = Some(announcement);
+        self.announce(now)
+    }
+
+    /// Periodic housekeeping: drop silent peers and re-announce ourselves.
+    pub fn tick(&mut self, now: u64) -> Vec<Action> {
+        let mut actions: Vec<Action> = self
+            .roster
+            .expire(now)
+            .into_iter()
+            .map(|id| {
+                self.sessions.remove(&id);
+                Action::Emit(MeshEvent::PeerLost {
+                    peer_id: id.to_string(),
+                })
+            })
+            .collect();
+        for (sender, nack) in self.reassembler.nacks(now) {
+            actions.extend(self.send_feedback(sender, &nack, now));
+        }
+        self.outbox.expire(now);
+        // Peer IDs rotate. Favorites are cached for whether or not they
+        // were seen (see `should_cache`), so they need no entry either.
+        self.last_seen
+            .retain(|_, at| now.saturating_sub(*at) < RECENT_PEER_MS);
+        let due = self.sessions.due(now);
+        actions.extend(
+            due.start
+                .into_iter()
+                .filter_map(|id| self.handshake_request(&id)),
+        );
+        actions.extend(due.failed.into_iter().map(|id| {
+            Action::Emit(MeshEvent::HandshakeFailed {
+                peer_id: id.to_string(),
+            })
+        }));
+        if now.saturating_sub(self.last_announce) >= ANNOUNCE_INTERVAL_MS {
+            actions.extend(self.announce(now));
+        }
+        actions
+    }
+
+    fn announce(&mut self, now: u64) -> Vec<Action> {
+        let Some(announcement) = &self.announcement else {
+            return Vec::new();
+        };
+        self.last_announce = now;
+        let packet = Packet::new(
+            MessageType::Announce,
+            self.peer_id,
+            None,
+            announcement.encode(),
+            now,
+        );
+        self.originate(&packet, now)
+    }
+
+    /// Send a packet of our own to every link, fragmenting it if needed.
+    pub fn originate(&mut self, packet: &Packet, now: u64) -> Vec<Action> {
+        let compression = self.versions.features(None).contains(Features::COMPRESSION);
+        let fragments = match fragment::split_with(packet, fragment::DEFAULT_MAX_FRAME, compression)
+        {
+            Ok(fragments) => fragments,
+            Err(e) => {
+                tracing::warn!("can't send {:?} packet: {}", packet.message_type, e);
+                return Vec::new();
+            }
+        };
+        self.outbox.track(&fragments, now);
+        self.broadcast_own(&fragments, now)
+    }
+
+    /// Broadcast packets of ours that already fit in a frame.
+    fn broadcast_own(&mut self, packets: &[Packet], now: u64) -> Vec<Action> {
+        let features = self.versions.features(None);
+        let compression = features.contains(Features::COMPRESSION);
+        packets
+            .iter()
+            .filter_map(|p| {
+                self.seen.insert(&p.id(), now);
+                p.encode_with(compression).ok()
+            })
+            .map(|data| Action::Broadcast {
+                data,
+                exclude: None,
+                delay_ms: 0,
+                pad: features.contains(Features::PADDING),
+            })
+            .collect()
+    }
+
+    pub fn handle_frame(&mut self, link_id: &str, data: &[u8], now: u64) -> Vec<Action> {
+        self.stats.received += 1;
+        let packet = match Packet::decode(data) {
+            Ok(packet) => packet,
+            Err(e) => {
+                self.stats.malformed += 1;
+                tracing::debug!("dropping frame from {}: {}", link_id, e);
+                return Vec::new();
+            }
+        };
+        if packet.sender_id == self.peer_id || !self.seen.insert(&packet.id(), now) {
+            self.stats.duplicates += 1;
+            return Vec::new();
+        }
+
+        let mut actions = Vec::new();
+        self.last_seen.insert(packet.sender_id, now);
+        self.roster.touch(&packet.sender_id, now);
+        match packet.message_type {
+            MessageType::Announce => {
+                self.handle_announce(link_id, &packet, now, &mut actions);
+                self.flush_cache(link_id, packet.sender_id, now, &mut actions);
+            }
+            MessageType::VersionHello | MessageType::VersionAck => {
+                // Single-hop and only meant for us; never delivered or relayed.
+                self.handle_version(link_id, &packet, now, &mut actions);
+                return actions;
+            }
+            MessageType::FragmentAck | MessageType::FragmentNack
+                if packet.recipient_id == Some(self.peer_id) =>
+            {
+                self.handle_feedback(&packet, now, &mut actions);
+                return actions;
+            }
+            MessageType::Leave if self.roster.remove(&packet.sender_id) => {
+                self.sessions.remove(&packet.sender_id);
+                actions.push(Action::Emit(MeshEvent::PeerLost {
+                    peer_id: packet.sender_id.to_string(),
+                }));
+            }
+            _ => {}
+        }
+
+        let for_us = packet
+            .recipient_id
+            .map_or(true, |id| id.is_broadcast() || id == self.peer_id);
+        if for_us {
+            self.deliver(link_id, &packet, now, &mut actions);
+        }
+        if packet.recipient_id != Some(self.peer_id) {
+            self.relay(link_id, packet, now, &mut actions);
+        }
+        actions
+    }
+
+    fn handle_version(
+        &mut self,
+        link_id: &str,
+        packet: &Packet,
+        now: u64,
+        actions: &mut Vec<Action>,
+    ) {
+        let ack = if packet.message_type == MessageType::VersionHello {
+            Hello::parse(&packet.payload).map(|hello| version::answer(&hello))
+        } else if packet.recipient_id == Some(self.peer_id) {
+            Ack::parse(&packet.payload)
+        } else {
+            return;
+        };
+        let ack = match ack {
+            Ok(ack) => ack,
+            Err(e) => {
+                tracing::debug!("bad version packet from {}: {}", packet.sender_id, e);
+                return;
+            }
+        };
+        if ack.version.is_none() {
+            tracing::debug!("no common protocol version with {}", packet.sender_id);
+        }
+        self.versions.agreed(link_id, packet.sender_id, &ack);
+        self.neighbors.insert(link_id.to_string(), packet.sender_id);
+        if packet.message_type == MessageType::VersionHello {
+            let reply = Packet::new(
+                MessageType::VersionAck,
+                self.peer_id,
+                Some(packet.sender_id),
+                ack.encode(),
+                now,
+            );
+            actions.extend(self.send_one_hop(link_id, reply));
+        }
+    }
+
+    fn handle_feedback(&mut self, packet: &Packet, now: u64, actions: &mut Vec<Action>) {
+        let feedback = match Feedback::parse(packet) {
+            Ok(feedback) => feedback,
+            Err(e) => {
+                tracing::debug!("bad fragment feedback from {}: {}", packet.sender_id, e);
+                return;
+            }
+        };
+        let resend = self.outbox.feedback(packet.sender_id, &feedback, now);
+        self.stats.fragments_resent += resend.len() as u64;
+        actions.extend(self.broadcast_own(&resend, now));
+    }
+
+    /// Tell `sender` how its fragmented message is arriving.
+    fn send_feedback(&mut self, sender: PeerId, feedback: &Feedback, now: u64) -> Vec<Action> {
+        let packet = Packet::new(
+            feedback.message_type(),
+            self.peer_id,
+            Some(sender),
+            feedback.encode(),
+            now,
+        );
+        self.originate(&packet, now)
+    }
+
+    /// A single-hop packet for the neighbor on `link_id`.
+    fn send_one_hop(&self, link_id: &str, mut packet: Packet) -> Option<Action> {
+        packet.ttl = 1;
+        let features = self.versions.features(Some(link_id));
+        let data = packet
+            .encode_with(features.contains(Features::COMPRESSION))
+            .ok()?;
+        Some(Action::Send {
+            link_id: link_id.to_string(),
+            data,
+            pad: features.contains(Features::PADDING),
+        })
+    }
+
+    fn handle_announce(
+        &mut self,
+        link_id: &str,
+        packet: &Packet,
+        now: u64,
+        actions: &mut Vec<Action>,
+    ) {
+        let announcement = match Announcement::parse(&packet.payload) {
+            Ok(announcement) => announcement,
+            Err(e) => {
+                tracing::debug!("bad announce from {}: {}", packet.sender_id, e);
+                return;
+            }
+        };
+        // Senders pick their own starting TTL, so the link, not the TTL,
+        // says who's at the other end.
+        let direct = match self.neighbors.get(link_id) {
+            Some(neighbor) => *neighbor == packet.sender_id,
+            None => {
+                let elsewhere = self.neighbors.values().any(|p| *p == packet.sender_id)
+                    || self
+                        .roster
+                        .get(&packet.sender_id)
+                        .is_some_and(|peer| peer.link_id != link_id);
+                if !elsewhere {
+                    self.neighbors.insert(link_id.to_string(), packet.sender_id);
+                }
+                !elsewhere
+            }
+        };
+        match self
+            .roster
+            .announce(packet.sender_id, announcement, link_id, direct, now)
+        {
+            Announced::New => {
+                if let Some(peer) = self.roster.get(&packet.sender_id) {
+                    let initiate = peer.noise_public_key.is_some()
+                        && self
+                            .sessions
+                            .peer_connected(self.peer_id, packet.sender_id, now);
+                    actions.push(Action::Emit(MeshEvent::PeerDiscovered {
+                        peer: self.complete(peer),
+                    }));
+                    if initiate {
+                        actions.extend(self.handshake_request(&packet.sender_id));
+                    }
+                }
+            }
+            Announced::Known => {}
+            Announced::Rejected => {
+                tracing::debug!(
+                    "ignoring announce from {} with changed keys",
+                    packet.sender_id
+                );
+            }
+        }
+    }
+
+    fn handshake_request(&self, peer_id: &PeerId) -> Option<Action> {
+        let noise_public_key = self.roster.get(peer_id)?.noise_public_key?;
+        Some(Action::Emit(MeshEvent::HandshakeRequested {
+            peer_id: peer_id.to_string(),
+            noise_public_key,
+        }))
+    }
+
+    fn flush_cache(&mut self, link_id: &str, peer_id: PeerId, now: u64, actions: &mut Vec<Action>) {
+        let features = self.versions.features(Some(link_id));
+        for packet in self.store.take(&peer_id, now) {
+            if let Ok(data) = packet.encode_with(features.contains(Features::COMPRESSION)) {
+                self.stats.forwarded_from_cache += 1;
+                actions.push(Action::Send {
+                    link_id: link_id.to_string(),
+                    data,
+                    pad: features.contains(Features::PADDING),
+                });
+            }
+        }
+    }
+
+    /// Whether a private packet for `recipient`, or a fragment of one,
+    /// should be kept for later: we know the peer, but haven't heard from
+    /// it lately.
+    fn should_cache(&self, packet: &Packet, now: u64) -> bool {
+        let Some(recipient) = packet.recipient_id else {
+            return false;
+        };
+        if recipient.is_broadcast()
+            || !matches!(
+                packet.message_type,
+                MessageType::Message
+                    | MessageType::NoiseEncrypted
+                    | MessageType::DeliveryAck
+                    | MessageType::ReadReceipt
+                    | MessageType::FragmentStart
+                    | MessageType::FragmentContinue
+                    | MessageType::FragmentEnd
+            )
+        {
+            return false;
+        }
+        match self.last_seen.get(&recipient) {
+            Some(at) => {
+                let age = now.saturating_sub(*at);
+                age >= REACHABLE_MS && (age < RECENT_PEER_MS || self.store.is_favorite(&recipient))
+            }
+            None => self.store.is_favorite(&recipient),
+        }
+    }
+
+    fn deliver(&mut self, link_id: &str, packet: &Packet, now: u64, actions: &mut Vec<Action>) {
+        let packet = if fragment::is_fragment(packet.message_type) {
+            match self.reassembler.accept(packet, now) {
+                Ok(Some(whole)) => {
+                    if packet.recipient_id.is_some_and(|id| !id.is_broadcast()) {
+                        if let Ok((header, _)) = fragment::FragmentHeader::parse(&packet.payload) {
+                            let ack = Feedback::Ack {
+                                fragment_id: header.fragment_id,
+                            };
+                            actions.extend(self.send_feedback(packet.sender_id, &ack, now));
+                        }
+                    }
+                    whole
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    self.stats.malformed += 1;
+                    tracing::debug!("dropping fragment from {}: {}", link_id, e);
+                    return;
+                }
+            }
+        } else {
+            packet.clone()
+        };
+        self.stats.delivered += 1;
+        actions.push(Action::Deliver {
+            link_id: link_id.to_string(),
+            packet,
+        });
+    }
+
+    fn relay(&mut self, link_id: &str, mut packet: Packet, now: u64, actions: &mut Vec<Action>) {
+        if self.should_cache(&packet, now) {
+            self.stats.cached += 1;
+            let mut cached = packet.clone();
+            cached.ttl = cached.ttl.saturating_sub(1);
+            self.store.store(cached, now);
+        }
+        if packet.ttl <= 1 {
+            self.stats.expired += 1;
+            return;
+        }
+        if !self.rng.gen_bool(self.relay_probability()) {
+            self.stats.damped += 1;
+            return;
+        }
+        packet.ttl -= 1;
+        let features = self.versions.features(None);
+        let Ok(data) = packet.encode_with(features.contains(Features::COMPRESSION)) else {
+            return;
+        };
+        self.stats.relayed += 1;
+        actions.push(Action::Broadcast {
+            data,
+            exclude: Some(link_id.to_string()),
+            delay_ms: self.rng.gen_range(RELAY_DELAY_MS),
+            pad: features.contains(Features::PADDING),
+        });
+    }
+
+    /// Relay everything in a sparse mesh; with many neighbors, most of them
+    /// will have heard the packet too, so relay less.
+    fn relay_probability(&self) -> f64 {
+        match self.links.len() {
+            0..=2 => 1.0,
+            n => (2.0 / n as f64).max(MIN_RELAY_PROBABILITY),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::packet::{MessageType, FLAG_IS_COMPRESSED, HEADER_SIZE};
+    use crate::mesh::sessions::SessionStatus;
+
+    const US: PeerId = PeerId([1; 8]);
+    const THEM: PeerId = PeerId([2; 8]);
+
+    fn frame(ttl: u8, recipient_id: Option<PeerId>) -> Vec<u8> {
+        Packet {
+            version: 1,
+            message_type: MessageType::Message,
+            ttl,
+            timestamp: 1,
+            sender_id: THEM,
+            recipient_id,
+            payload: b"hi".to_vec(),
+            signature: None,
+        }
+        .encode()
+        .unwrap()
+    }
+
+    fn relayed_ttl(actions: &[Action]) -> Option<u8> {
+        actions.iter().find_map(|a| match a {
+            Action::Broadcast { data, .. } => Some(Packet::decode(data).unwrap().ttl),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn delivers_and_relays_broadcasts_with_decremented_ttl() {
+        let mut node = Node::new(US, 0);
+        let actions = node.handle_frame("a", &frame(3, None), 0);
+        assert!(matches!(actions[0], Action::Deliver { .. }));
+        assert_eq!(relayed_ttl(&actions), Some(2));
+    }
+
+    #[test]
+    fn drops_duplicates_and_expired_ttl() {
+        let mut node = Node::new(US, 0);
+        node.handle_frame("a", &frame(3, None), 0);
+        assert!(node.handle_frame("b", &frame(3, None), 10).is_empty());
+
+        let actions = node.handle_frame("a", &frame(1, Some(PeerId([3; 8]))), 0);
+        assert!(actions.is_empty());
+        let stats = node.stats();
+        assert_eq!((stats.duplicates, stats.expired), (1, 1));
+    }
+
+    #[test]
+    fn caches_for_absent_peers_until_they_announce() {
+        let absent = PeerId([3; 8]);
+        let mut node = Node::new(US, 0);
+        let mut announce = Packet::decode(&frame(3, None)).unwrap();
+        announce.sender_id = absent;
+        announce.message_type = MessageType::Announce;
+        node.handle_frame("a", &announce.encode().unwrap(), 0);
+
+        let mut private = Packet::decode(&frame(3, Some(absent))).unwrap();
+        private.message_type = MessageType::NoiseEncrypted;
+        node.handle_frame("b", &private.encode().unwrap(), REACHABLE_MS);
+        assert_eq!(node.stats().cached, 1);
+
+        announce.timestamp += 1;
+        let actions = node.handle_frame("c", &announce.encode().unwrap(), REACHABLE_MS + 1);
+        assert!(actions
+            .iter()
+            .any(|a| matches!(a, Action::Send { link_id, .. } if link_id == "c")));
+    }
+
+    #[test]
+    fn forgets_senders_not_heard_from_in_a_day() {
+        let mut node = Node::new(US, 0);
+        node.handle_frame("a", &frame(7, None), 0);
+        node.tick(RECENT_PEER_MS - 1);
+        assert!(node.last_seen.contains_key(&THEM));
+        node.tick(RECENT_PEER_MS);
+        assert!(node.last_seen.is_empty());
+    }
+
+    #[test]
+    fn announces_populate_the_roster() {
+        let mut node = Node::new(US, 0);
+        let announcement = Announcement::parse(b"bob").unwrap();
+        let packet = Packet::new(MessageType::Announce, THEM, None, announcement.encode(), 0);
+        let actions = node.handle_frame("a", &packet.encode().unwrap(), 0);
+        assert!(actions
+            .iter()
+            .any(|a| matches!(a, Action::Emit(MeshEvent::PeerDiscovered { .. }))));
+        assert_eq!(node.peers()[0].nickname, "bob");
+
+        let actions = node.tick(crate::mesh::peers::PEER_TIMEOUT_MS);
+        assert!(matches!(
+            actions[0],
+            Action::Emit(MeshEvent::PeerLost { .. })
+        ));
+        assert!(node.peers().is_empty());
+    }
+
+    #[test]
+    fn only_direct_neighbors_get_the_link_signal() {
+        let mut node = Node::new(US, 0);
+        let announce = |sender_id: PeerId, nickname: &[u8], ttl: u8, now: u64| {
+            let mut packet = Packet::new(
+                MessageType::Announce,
+                sender_id,
+                None,
+                nickname.to_vec(),
+                now,
+            );
+            packet.ttl = ttl;
+            packet.encode().unwrap()
+        };
+        // The neighbor starts its announces low, and a relay passes on
+        // another peer's without decrementing.
+        node.handle_frame("a", &announce(THEM, b"bob", 3, 0), 0);
+        let relayed = PeerId([3; 8]);
+        node.handle_frame("a", &announce(relayed, b"carol", 7, 0), 0);
+        node.set_rssi("a", -50);
+
+        assert_eq!(node.peer(&THEM).unwrap().rssi, Some(-50));
+        let carol = node.peer(&relayed).unwrap();
+        assert_eq!(carol.link_id, "a");
+        assert_eq!(carol.rssi, None);
+        assert_eq!(carol.proximity, None);
+
+        // A version hello names the neighbor outright.
+        let hello = Packet::new(
+            MessageType::VersionHello,
+            relayed,
+            None,
+            Hello::ours().encode(),
+            1,
+        );
+        node.handle_frame("b", &hello.encode().unwrap(), 1);
+        node.handle_frame("b", &announce(relayed, b"carol", 2, 1), 1);
+        node.set_rssi("b", -60);
+        assert_eq!(node.peer(&relayed).unwrap().rssi, Some(-60));
+    }
+
+    #[test]
+    fn peers_with_noise_keys_get_a_handshake() {
+        let mut node = Node::new(US, 0);
+        let announcement = Announcement {
+            nickname: "bob".to_string(),
+            noise_public_key: Some([7; 32]),
+            signing_public_key: None,
+        };
+        let packet = Packet::new(MessageType::Announce, THEM, None, announcement.encode(), 0);
+        let actions = node.handle_frame("a", &packet.encode().unwrap(), 0);
+        assert!(actions
+            .iter()
+            .any(|a| matches!(a, Action::Emit(MeshEvent::HandshakeRequested { .. }))));
+        assert_eq!(node.peers()[0].session, Some(SessionStatus::Handshaking));
+
+        let actions = node.session_established(THEM);
+        assert!(matches!(
+            actions[..],
+            [Action::Emit(MeshEvent::PeerReady { .. })]
+        ));
+        assert_eq!(node.peers()[0].session, Some(SessionStatus::Ready));
+    }
+
+    #[test]
+    fn negotiates_versions_and_gates_features() {
+        let mut node = Node::new(US, 0);
+        let actions = node.link_up("a", 0);
+        let [Action::Send { data, .. }] = &actions[..] else {
+            panic!("expected a hello");
+        };
+        let hello = Packet::decode(data).unwrap();
+        assert_eq!(hello.message_type, MessageType::VersionHello);
+        assert_eq!(hello.ttl, 1);
+
+        // A peer that can't take compression or padding.
+        let hello = Hello {
+            versions: vec![crate::mesh::packet::VERSION],
+            features: Features::NONE,
+        };
+        let packet = Packet::new(MessageType::VersionHello, THEM, None, hello.encode(), 0);
+        let actions = node.handle_frame("a", &packet.encode().unwrap(), 0);
+        let [Action::Send {
+            data, pad: false, ..
+        }] = &actions[..]
+        else {
+            panic!("expected an unpadded ack");
+        };
+        let ack = Ack::parse(&Packet::decode(data).unwrap().payload).unwrap();
+        assert_eq!(ack.features, Features::NONE);
+
+        let mut message = Packet::new(MessageType::Message, US, None, vec![b'a'; 300], 0);
+        message.ttl = 3;
+        let actions = node.originate(&message, 0);
+        let [Action::Broadcast {
+            data, pad: false, ..
+        }] = &actions[..]
+        else {
+            panic!("expected one unpadded frame");
+        };
+        assert_eq!(data[HEADER_SIZE - 3] & FLAG_IS_COMPRESSED, 0);
+    }
+
+    #[test]
+    fn packets_for_us_are_not_relayed_and_others_not_delivered() {
+        let mut node = Node::new(US, 0);
+        let actions = node.handle_frame("a", &frame(5, Some(US)), 0);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(relayed_ttl(&actions), None);
+
+        let actions = node.handle_frame("a", &frame(5, Some(PeerId([3; 8]))), 0);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(relayed_ttl(&actions), Some(4));
+    }
+
+    #[test]
+    fn resends_only_missing_fragments() {
+        fn frames(actions: &[Action]) -> Vec<Vec<u8>> {
+            actions
+                .iter()
+                .filter_map(|a| match a {
+                    Action::Broadcast { data, .. } => Some(data.clone()),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        let mut sender = Node::new(US, 0);
+        let mut receiver = Node::new(THEM, 0);
+        let payload: Vec<u8> = (0..2000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let sent = frames(&sender.send_private(THEM, payload.clone(), 0));
+        assert!(sent.len() > 2);
+        for data in sent.iter().skip(1) {
+            assert!(receiver.handle_frame("a", data, 0).is_empty());
+        }
+
+        let nack = frames(&receiver.tick(fragment::NACK_AFTER_MS));
+        let [nack] = &nack[..] else {
+            panic!("expected one nack");
+        };
+        let resent = frames(&sender.handle_frame("a", nack, 10));
+        let [resent] = &resent[..] else {
+            panic!("expected one fragment resent");
+        };
+        assert_eq!(sender.stats().fragments_resent, 1);
+
+        let actions = receiver.handle_frame("a", resent, 20);
+        assert!(actions.iter().any(|a| matches!(
+            a,
+            Action::Deliver { packet, .. } if packet.payload == payload
+        )));
+        let [ack] = &frames(&actions)[..] else {
+            panic!("expected an ack");
+        };
+        assert!(frames(&sender.handle_frame("a", ack, 30)).is_empty());
+    }
+}
@@ -0,0 +1,659 @@
+//! Bitchat binary packet format, as spoken by the iOS and Android apps.
+//!
+//! ```text
+//! version:1 type:1 ttl:1 timestamp:8 flags:1 payload_len:2
+//! sender_id:8 [recipient_id:8] payload:payload_len [signature:64]
+//! ```
+//!
+//! Integers are big-endian and the timestamp is in milliseconds. The
+//! recipient and signature are present only when their flag is set.
+//!
+//! Payloads over [`COMPRESSION_THRESHOLD`] bytes are LZ4-compressed (raw
+//! block) when that makes them smaller. The compressed flag is then set and
+//! the payload field holds `original_len:2` followed by the block.
+//! [`Packet::payload`] is always the uncompressed payload.
+//!
+//! Frames may be [`pad`]ded up to the next of [`PADDING_BUCKETS`] with
+//! PKCS#7-style padding, so their length says little about the content.
+//! [`Packet::decode`] accepts frames with or without it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use specta::Type;
+use tauri::State;
+
+use super::fragment;
+use super::version::Features;
+use super::MeshState;
+use crate::error::{AppError, AppResult, ErrorCode};
+
+pub const VERSION: u8 = 1;
+pub const HEADER_SIZE: usize = 14;
+pub const PEER_ID_SIZE: usize = 8;
+pub const SIGNATURE_SIZE: usize = 64;
+pub const MAX_PAYLOAD: usize = u16::MAX as usize;
+/// Hops a packet we originate may travel.
+pub const DEFAULT_TTL: u8 = 7;
+
+pub const FLAG_HAS_RECIPIENT: u8 = 0x01;
+pub const FLAG_HAS_SIGNATURE: u8 = 0x02;
+pub const FLAG_IS_COMPRESSED: u8 = 0x04;
+/// Smaller payloads aren't worth compressing.
+pub const COMPRESSION_THRESHOLD: usize = 100;
+/// Frame sizes [`pad`] rounds up to, as in the native apps.
+pub const PADDING_BUCKETS: [usize; 4] = [256, 512, 1024, 2048];
+
+/// 8-byte mesh peer ID, shown as 16 hex characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId(pub [u8; PEER_ID_SIZE]);
+
+impl PeerId {
+    /// Recipient of packets meant for everyone.
+    pub const BROADCAST: PeerId = PeerId([0xff; PEER_ID_SIZE]);
+
+    pub fn random() -> Self {
+        PeerId(rand::random())
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl FromStr for PeerId {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> AppResult<Self> {
+        let mut id = [0u8; PEER_ID_SIZE];
+        hex::decode_to_slice(s, &mut id)
+            .map_err(|_| AppError::new(ErrorCode::InvalidPeerId).with("peer_id", s))?;
+        Ok(PeerId(id))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageType {
+    Announce,
+    Leave,
+    Message,
+    FragmentStart,
+    FragmentContinue,
+    FragmentEnd,
+    /// A private fragmented message arrived whole. Ours alone, like
+    /// `FragmentNack`, so it has a code no native version uses.
+    FragmentAck,
+    /// Fragments of a private message are missing; resend them.
+    FragmentNack,
+    DeliveryAck,
+    DeliveryStatusRequest,
+    ReadReceipt,
+    NoiseHandshakeInit,
+    NoiseHandshakeResp,
+    NoiseEncrypted,
+    NoiseIdentityAnnounce,
+    VersionHello,
+    VersionAck,
+    /// A type this build doesn't know. Kept so it can still be relayed.
+    Other(u8),
+}
+
+impl MessageType {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0x01 => Self::Announce,
+            0x03 => Self::Leave,
+            0x04 => Self::Message,
+            0x05 => Self::FragmentStart,
+            0x06 => Self::FragmentContinue,
+            0x07 => Self::FragmentEnd,
+            0x0a => Self::DeliveryAck,
+            0x0b => Self::DeliveryStatusRequest,
+            0x0c => Self::ReadReceipt,
+            0x10 => Self::NoiseHandshakeInit,
+            0x11 => Self::NoiseHandshakeResp,
+            0x12 => Self::NoiseEncrypted,
+            0x13 => Self::NoiseIdentityAnnounce,
+            0x20 => Self::VersionHello,
+            0x21 => Self::VersionAck,
+            0xf0 => Self::FragmentAck,
+            0xf1 => Self::FragmentNack,
+            other => Self::Other(other),
+        }
+    }
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            Self::Announce => 0x01,
+            Self::Leave => 0x03,
+            Self::Message => 0x04,
+            Self::FragmentStart => 0x05,
+            Self::FragmentContinue => 0x06,
+            Self::FragmentEnd => 0x07,
+            Self::DeliveryAck => 0x0a,
+            Self::DeliveryStatusRequest => 0x0b,
+            Self::ReadReceipt => 0x0c,
+            Self::NoiseHandshakeInit => 0x10,
+            Self::NoiseHandshakeResp => 0x11,
+            Self::NoiseEncrypted => 0x12,
+            Self::NoiseIdentityAnnounce => 0x13,
+            Self::VersionHello => 0x20,
+            Self::VersionAck => 0x21,
+            Self::FragmentAck => 0xf0,
+            Self::FragmentNack => 0xf1,
+            Self::Other(other) => other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub version: u8,
+    pub message_type: MessageType,
+    pub ttl: u8,
+    pub timestamp: u64,
+    pub sender_id: PeerId,
+    pub recipient_id: Option<PeerId>,
+    pub payload: Vec<u8>,
+    pub signature: Option<[u8; SIGNATURE_SIZE]>,
+}
+
+fn malformed(reason: &str) -> AppError {
+    AppError::new(ErrorCode::MalformedPacket).with("reason", reason)
+}
+
+impl Packet {
+    pub fn new(
+        message_type: MessageType,
+        sender_id: PeerId,
+        recipient_id: Option<PeerId>,
+        payload: Vec<u8>,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            version: VERSION,
+            message_type,
+            ttl: DEFAULT_TTL,
+            timestamp,
+            sender_id,
+            recipient_id,
+            payload,
+            signature: None,
+        }
+    }
+
+    /// Stable ID for duplicate suppression. Covers everything relays don't
+    /// change, so it survives the TTL being decremented.
+    pub fn id(&self) -> [u8; 16] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.sender_id.0);
+        hasher.update(self.recipient_id.map_or([0; PEER_ID_SIZE], |id| id.0));
+        hasher.update(self.timestamp.to_be_bytes());
+        hasher.update([self.message_type.as_u8()]);
+        hasher.update(&self.payload);
+        hasher.finalize()[..16].try_into().unwrap()
+    }
+
+    pub fn encode(&self) -> AppResult<Vec<u8>> {
+        self.encode_with(true)
+    }
+
+    /// Encode, compressing the payload only if `compression` is allowed.
+    pub fn encode_with(&self, compression: bool) -> AppResult<Vec<u8>> {
+        if self.payload.len() > MAX_PAYLOAD {
+            return Err(AppError::new(ErrorCode::PayloadTooLarge)
+                .with("size", self.payload.len())
+                .with("max", MAX_PAYLOAD));
+        }
+
+        let compressed = if compression {
+            compress(&self.payload)
+        } else {
+            None
+        };
+        let payload_len = compressed
+            .as_ref()
+            .map_or(self.payload.len(), |c| c.len() + 2);
+
+        let mut flags = 0;
+        if compressed.is_some() {
+            flags |= FLAG_IS_COMPRESSED;
+        }
+        if self.recipient_id.is_some() {
+            flags |= FLAG_HAS_RECIPIENT;
+        }
+        if self.signature.is_some() {
+            flags |= FLAG_HAS_SIGNATURE;
+        }
+
+        let mut out =
+            Vec::with_capacity(HEADER_SIZE + 2 * PEER_ID_SIZE + payload_len + SIGNATURE_SIZE);
+        out.push(self.version);
+        out.push(self.message_type.as_u8());
+        out.push(self.ttl);
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        out.push(flags);
+        out.extend_from_slice(&(payload_len as u16).to_be_bytes());
+        out.extend_from_slice(&self.sender_id.0);
+        if let Some(recipient) = &self.recipient_id {
+            out.extend_from_slice(&recipient.0);
+        }
+        match &compressed {
+            Some(block) => {
+                out.extend_from_slice(&(self.payload.len() as u16).to_be_bytes());
+                out.extend_from_slice(block);
+            }
+            None => out.extend_from_slice(&self.payload),
+        }
+        if let Some(signature) = &self.signature {
+            out.extend_from_slice(signature);
+        }
+        Ok(out)
+    }
+
+    pub fn decode(data: &[u8]) -> AppResult<Self> {
+        let mut r = Reader(data);
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(AppError::new(ErrorCode::UnsupportedVersion).with("version", version));
+        }
+        let message_type = MessageType::from_u8(r.u8()?);
+        let ttl = r.u8()?;
+        let timestamp = u64::from_be_bytes(r.array()?);
+        let flags = r.u8()?;
+        let payload_len = u16::from_be_bytes(r.array()?) as usize;
+        let sender_id = PeerId(r.array()?);
+        let recipient_id = if flags & FLAG_HAS_RECIPIENT != 0 {
+            Some(PeerId(r.array()?))
+        } else {
+            None
+        };
+        let payload = r.take(payload_len)?;
+        let payload = if flags & FLAG_IS_COMPRESSED != 0 {
+            decompress(payload)?
+        } else {
+            payload.to_vec()
+        };
+        let signature = if flags & FLAG_HAS_SIGNATURE != 0 {
+            Some(r.array()?)
+        } else {
+            None
+        };
+        if !r.0.is_empty() && !is_padding(r.0) {
+            return Err(malformed("trailing_bytes"));
+        }
+
+        Ok(Self {
+            version,
+            message_type,
+            ttl,
+            timestamp,
+            sender_id,
+            recipient_id,
+            payload,
+            signature,
+        })
+    }
+}
+
+/// Pad an encoded frame to the smallest bucket that fits it. Every padding
+/// byte holds the padding length, so frames that would need more than 255
+/// bytes (or are bigger than the largest bucket) are left as they are.
+pub fn pad(mut frame: Vec<u8>) -> Vec<u8> {
+    let Some(bucket) = PADDING_BUCKETS.into_iter().find(|b| *b > frame.len()) else {
+        return frame;
+    };
+    if let Ok(n) = u8::try_from(bucket - frame.len()) {
+        frame.resize(bucket, n);
+    }
+    frame
+}
+
+fn is_padding(rest: &[u8]) -> bool {
+    let n = rest[rest.len() - 1];
+    rest.len() == n as usize && rest.iter().all(|b| *b == n)
+}
+
+/// LZ4 block for `payload`, if it's large enough to bother and actually
+/// shrinks (counting the 2-byte length prefix).
+fn compress(payload: &[u8]) -> Option<Vec<u8>> {
+    if payload.len() < COMPRESSION_THRESHOLD {
+        return None;
+    }
+    let block = lz4_flex::block::compress(payload);
+    (block.len() + 2 < payload.len()).then_some(block)
+}
+
+fn decompress(field: &[u8]) -> AppResult<Vec<u8>> {
+    let (len, block) = field
+        .split_first_chunk::<2>()
+        .ok_or_else(|| malformed("compressed_length"))?;
+    let len = u16::from_be_bytes(*len) as usize;
+    match lz4_flex::block::decompress(block, len) {
+        Ok(payload) if payload.len() == len => Ok(payload),
+        _ => Err(malformed("compression")),
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> AppResult<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(malformed("truncated"));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> AppResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> AppResult<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+}
+
+/// A packet as the frontend sees it: IDs and signature in hex, payload in
+/// base64.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct WirePacket {
+    pub message_type: u8,
+    pub ttl: u8,
+    pub timestamp: u64,
+    pub sender_id: String,
+    pub recipient_id: Option<String>,
+    pub payload: String,
+    pub signature: Option<String>,
+}
+
+impl From<&Packet> for WirePacket {
+    fn from(packet: &Packet) -> Self {
+        Self {
+            message_type: packet.message_type.as_u8(),
+            ttl: packet.ttl,
+            timestamp: packet.timestamp,
+            sender_id: packet.sender_id.to_string(),
+            recipient_id: packet.recipient_id.map(|id| id.to_string()),
+            payload: base64::engine::general_purpose::STANDARD.encode(&packet.payload),
+            signature: packet.signature.map(hex::encode),
+        }
+    }
+}
+
+impl TryFrom<WirePacket> for Packet {
+    type Error = AppError;
+
+    fn try_from(wire: WirePacket) -> AppResult<Self> {
+        let invalid = |field: &str| AppError::new(ErrorCode::InvalidArgument).with("field", field);
+        let signature = match wire.signature {
+            Some(s) => {
+                let mut signature = [0u8; SIGNATURE_SIZE];
+                hex::decode_to_slice(&s, &mut signature).map_err(|_| invalid("signature"))?;
+                Some(signature)
+            }
+            None => None,
+        };
+        Ok(Self {
+            version: VERSION,
+            message_type: MessageType::from_u8(wire.message_type),
+            ttl: wire.ttl,
+            timestamp: wire.timestamp,
+            sender_id: wire.sender_id.parse()?,
+            recipient_id: wire.recipient_id.map(|id| id.parse()).transpose()?,
+            payload: base64::engine::general_purpose::STANDARD
+                .decode(&wire.payload)
+                .map_err(|_| invalid("payload"))?,
+            signature,
+        })
+    }
+}
+
+/// Encode a packet for `ble_send` on `link_id` as base64 frames,
+/// fragmenting it if it doesn't fit in `max_frame` bytes. The payload is
+/// compressed only if the neighbor on the link agreed to it.
+#[tauri::command]
+#[specta::specta]
+pub fn mesh_encode_packet(
+    packet: WirePacket,
+    link_id: String,
+    max_frame: Option<u32>,
+    state: State<'_, MeshState>,
+) -> AppResult<Vec<String>> {
+    let max_frame = max_frame.map_or(fragment::DEFAULT_MAX_FRAME, |n| n as usize);
+    let compression = state
+        .node
+        .lock()
+        .unwrap()
+        .features(&link_id)
+        .contains(Features::COMPRESSION);
+    fragment::split_with(&Packet::try_from(packet)?, max_frame, compression)?
+        .iter()
+        .map(|p| {
+            let data = p.encode_with(compression)?;
+            Ok(base64::engine::general_purpose::STANDARD.encode(data))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_TYPES: [MessageType; 17] = [
+        MessageType::Announce,
+        MessageType::Leave,
+        MessageType::Message,
+        MessageType::FragmentStart,
+        MessageType::FragmentContinue,
+        MessageType::FragmentEnd,
+        MessageType::FragmentAck,
+        MessageType::FragmentNack,
+        MessageType::DeliveryAck,
+        MessageType::DeliveryStatusRequest,
+        MessageType::ReadReceipt,
+        MessageType::NoiseHandshakeInit,
+        MessageType::NoiseHandshakeResp,
+        MessageType::NoiseEncrypted,
+        MessageType::NoiseIdentityAnnounce,
+        MessageType::VersionHello,
+        MessageType::VersionAck,
+    ];
+
+    fn packet(message_type: MessageType, payload_len: usize) -> Packet {
+        Packet {
+            version: VERSION,
+            message_type,
+            ttl: 7,
+            timestamp: 1_700_000_000_123,
+            sender_id: PeerId([1, 2, 3, 4, 5, 6, 7, 8]),
+            recipient_id: None,
+            payload: (0..payload_len).map(|i| i as u8).collect(),
+            signature: None,
+        }
+    }
+
+    fn round_trip(p: &Packet) {
+        let bytes = p.encode().unwrap();
+        assert_eq!(&Packet::decode(&bytes).unwrap(), p);
+    }
+
+    #[test]
+    fn message_type_round_trips_every_byte() {
+        for value in 0..=u8::MAX {
+            assert_eq!(MessageType::from_u8(value).as_u8(), value);
+        }
+        for t in ALL_TYPES {
+            assert_ne!(
+                MessageType::from_u8(t.as_u8()),
+                MessageType::Other(t.as_u8())
+            );
+        }
+        // Older native builds use these for their own types.
+        assert_eq!(MessageType::from_u8(0x08), MessageType::Other(0x08));
+        assert_eq!(MessageType::from_u8(0x09), MessageType::Other(0x09));
+    }
+
+    #[test]
+    fn round_trips_all_types_and_optional_fields() {
+        for t in ALL_TYPES.into_iter().chain([MessageType::Other(0xee)]) {
+            for recipient in [None, Some(PeerId([9; 8])), Some(PeerId::BROADCAST)] {
+                for signature in [None, Some([0xab; SIGNATURE_SIZE])] {
+                    for len in [0, 1, 255, 256, 4096] {
+                        let mut p = packet(t, len);
+                        p.recipient_id = recipient;
+                        p.signature = signature;
+                        round_trip(&p);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_field_extremes() {
+        for ttl in [0, 1, u8::MAX] {
+            for timestamp in [0, 1, u64::MAX] {
+                let mut p = packet(MessageType::Message, 3);
+                p.ttl = ttl;
+                p.timestamp = timestamp;
+                round_trip(&p);
+            }
+        }
+        round_trip(&packet(MessageType::Message, MAX_PAYLOAD));
+    }
+
+    #[test]
+    fn encodes_the_native_layout() {
+        let mut p = packet(MessageType::Message, 2);
+        p.recipient_id = Some(PeerId([0xaa; 8]));
+        let bytes = p.encode().unwrap();
+        let mut expected = vec![1, 0x04, 7];
+        expected.extend_from_slice(&1_700_000_000_123u64.to_be_bytes());
+        expected.extend_from_slice(&[FLAG_HAS_RECIPIENT, 0, 2]);
+        expected.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        expected.extend_from_slice(&[0xaa; 8]);
+        expected.extend_from_slice(&[0, 1]);
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn compresses_large_repetitive_payloads() {
+        let mut p = packet(MessageType::Message, 0);
+        p.payload = b"hello mesh ".repeat(50);
+        let bytes = p.encode().unwrap();
+        assert_ne!(bytes[HEADER_SIZE - 3] & FLAG_IS_COMPRESSED, 0);
+        assert!(bytes.len() < p.payload.len());
+        assert_eq!(Packet::decode(&bytes).unwrap(), p);
+
+        // Incompressible payloads go out as-is.
+        p.payload = (0..500u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let bytes = p.encode().unwrap();
+        assert_eq!(bytes[HEADER_SIZE - 3] & FLAG_IS_COMPRESSED, 0);
+        assert_eq!(Packet::decode(&bytes).unwrap(), p);
+    }
+
+    #[test]
+    fn rejects_corrupt_compressed_payloads() {
+        let mut p = packet(MessageType::Message, 0);
+        p.payload = vec![b'a'; 1000];
+        let mut bytes = p.encode().unwrap();
+        // Claim a larger original size than the block decodes to.
+        let at = HEADER_SIZE + PEER_ID_SIZE;
+        bytes[at..at + 2].copy_from_slice(&2000u16.to_be_bytes());
+        assert_eq!(
+            Packet::decode(&bytes).unwrap_err().code,
+            ErrorCode::MalformedPacket
+        );
+    }
+
+    #[test]
+    fn pads_to_buckets() {
+        let p = packet(MessageType::Message, 10);
+        let bytes = p.encode().unwrap();
+        let padded = pad(bytes.clone());
+        assert_eq!(padded.len(), PADDING_BUCKETS[0]);
+        assert_eq!(Packet::decode(&padded).unwrap(), p);
+
+        // A frame exactly on a bucket would need 256 bytes of padding.
+        let exact = vec![1; PADDING_BUCKETS[0]];
+        assert_eq!(pad(exact.clone()), exact);
+        let huge = vec![1; 3000];
+        assert_eq!(pad(huge.clone()).len(), 3000);
+
+        let mut bad = bytes;
+        bad.extend_from_slice(&[3, 3]);
+        assert_eq!(
+            Packet::decode(&bad).unwrap_err().code,
+            ErrorCode::MalformedPacket
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_payload() {
+        let err = packet(MessageType::Message, MAX_PAYLOAD + 1)
+            .encode()
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::PayloadTooLarge);
+    }
+
+    #[test]
+    fn rejects_every_truncation() {
+        let mut p = packet(MessageType::Message, 10);
+        p.recipient_id = Some(PeerId([9; 8]));
+        p.signature = Some([1; SIGNATURE_SIZE]);
+        let bytes = p.encode().unwrap();
+        for len in 0..bytes.len() {
+            let err = Packet::decode(&bytes[..len]).unwrap_err();
+            assert_eq!(err.code, ErrorCode::MalformedPacket, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_bytes_and_unknown_versions() {
+        let mut bytes = packet(MessageType::Message, 1).encode().unwrap();
+        bytes.push(0);
+        assert_eq!(
+            Packet::decode(&bytes).unwrap_err().code,
+            ErrorCode::MalformedPacket
+        );
+
+        let mut bytes = packet(MessageType::Message, 1).encode().unwrap();
+        bytes[0] = 9;
+        assert_eq!(
+            Packet::decode(&bytes).unwrap_err().code,
+            ErrorCode::UnsupportedVersion
+        );
+    }
+
+    #[test]
+    fn wire_packet_round_trips() {
+        let mut p = packet(MessageType::Announce, 5);
+        p.recipient_id = Some(PeerId::BROADCAST);
+        p.signature = Some([7; SIGNATURE_SIZE]);
+        let wire = WirePacket::from(&p);
+        assert_eq!(wire.sender_id, "0102030405060708");
+        assert_eq!(Packet::try_from(wire).unwrap(), p);
+    }
+
+    #[test]
+    fn peer_id_parses_hex() {
+        let id: PeerId = "0102030405060708".parse().unwrap();
+        assert_eq!(id, PeerId([1, 2, 3, 4, 5, 6, 7, 8]));
+        assert!("0102".parse::<PeerId>().is_err());
+        assert!("zz02030405060708".parse::<PeerId>().is_err());
+    }
+}
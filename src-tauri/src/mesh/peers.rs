@@ -0,0 +1,463 @@
+//! Announce payloads and the roster of nearby peers built from them.
+//!
+//! Announce payloads are TLV-encoded (`type:1 length:1 value`) like the
+//! native apps': nickname, Noise static public key, signing public key.
+//! Older clients send the bare nickname, which is accepted too.
+//!
+//! Announces aren't signed, so anyone can announce someone else's Noise
+//! key with their own signing key. A signing key is therefore only trusted
+//! once a Noise handshake with the peer completes, proving it holds the
+//! Noise key it announced; the pair is pinned then. Until that, the peer
+//! has no [`Roster::signing_key`] and its public messages are unverified.
+//! A peer ID keeps the keys it first announced, and a pinned Noise key
+//! keeps its signing key even across peer ID changes; announces that would
+//! swap either are rejected.
+//!
+//! RSSI readings per link are smoothed with an exponential moving average
+//! and bucketed into a rough [`Proximity`], so one noisy sample doesn't
+//! reorder the peer list. A link's signal says nothing about peers relayed
+//! over it, so only direct neighbors get one.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use specta::Type;
+
+use super::packet::PeerId;
+use super::sessions::SessionStatus;
+use crate::error::{AppError, AppResult, ErrorCode};
+
+const TLV_NICKNAME: u8 = 0x01;
+const TLV_NOISE_PUBLIC_KEY: u8 = 0x02;
+const TLV_SIGNING_PUBLIC_KEY: u8 = 0x03;
+const MAX_NICKNAME: usize = 255;
+
+/// A peer is considered gone when we haven't heard from it for this long.
+pub const PEER_TIMEOUT_MS: u64 = 3 * 60_000;
+
+/// Weight of a new RSSI reading in the running average.
+const RSSI_SMOOTHING: f32 = 0.3;
+/// Smoothed RSSI (dBm) at or above which a peer is near, or medium.
+const NEAR_RSSI: i16 = -60;
+const MEDIUM_RSSI: i16 = -80;
+/// RSSI range mapped onto signal quality 0..=100.
+const WEAKEST_RSSI: i16 = -100;
+const STRONGEST_RSSI: i16 = -40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum Proximity {
+    Near,
+    Medium,
+    Far,
+}
+
+impl Proximity {
+    pub fn from_rssi(rssi: i16) -> Self {
+        if rssi >= NEAR_RSSI {
+            Proximity::Near
+        } else if rssi >= MEDIUM_RSSI {
+            Proximity::Medium
+        } else {
+            Proximity::Far
+        }
+    }
+}
+
+/// `rssi` as a 0-100 score, for signal bars.
+pub fn signal_quality(rssi: i16) -> u8 {
+    let clamped = rssi.clamp(WEAKEST_RSSI, STRONGEST_RSSI);
+    ((clamped - WEAKEST_RSSI) * 100 / (STRONGEST_RSSI - WEAKEST_RSSI)) as u8
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    pub nickname: String,
+    pub noise_public_key: Option<[u8; 32]>,
+    pub signing_public_key: Option<[u8; 32]>,
+}
+
+impl Announcement {
+    pub fn parse(payload: &[u8]) -> AppResult<Self> {
+        match Self::parse_tlv(payload) {
+            Some(announcement) => Ok(announcement),
+            None => {
+                let nickname = std::str::from_utf8(payload).map_err(|_| {
+                    AppError::new(ErrorCode::MalformedPacket).with("reason", "announce")
+                })?;
+                Ok(Self {
+                    nickname: nickname.to_string(),
+                    noise_public_key: None,
+                    signing_public_key: None,
+                })
+            }
+        }
+    }
+
+    fn parse_tlv(mut payload: &[u8]) -> Option<Self> {
+        let mut nickname = None;
+        let mut noise_public_key = None;
+        let mut signing_public_key = None;
+        while !payload.is_empty() {
+            let (&kind, rest) = payload.split_first()?;
+            let (&len, rest) = rest.split_first()?;
+            let value = rest.get(..len as usize)?;
+            payload = &rest[len as usize..];
+            match kind {
+                TLV_NICKNAME => nickname = Some(std::str::from_utf8(value).ok()?.to_string()),
+                TLV_NOISE_PUBLIC_KEY => noise_public_key = Some(value.try_into().ok()?),
+                TLV_SIGNING_PUBLIC_KEY => signing_public_key = Some(value.try_into().ok()?),
+                // Unknown fields are skipped for forward compatibility.
+                _ => {}
+            }
+        }
+        Some(Self {
+            nickname: nickname?,
+            noise_public_key,
+            signing_public_key,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let nickname = truncate(&self.nickname, MAX_NICKNAME);
+        let mut out = vec![TLV_NICKNAME, nickname.len() as u8];
+        out.extend_from_slice(nickname.as_bytes());
+        if let Some(key) = &self.noise_public_key {
+            out.extend_from_slice(&[TLV_NOISE_PUBLIC_KEY, 32]);
+            out.extend_from_slice(key);
+        }
+        if let Some(key) = &self.signing_public_key {
+            out.extend_from_slice(&[TLV_SIGNING_PUBLIC_KEY, 32]);
+            out.extend_from_slice(key);
+        }
+        out
+    }
+}
+
+/// Cut `s` to at most `max` bytes without splitting a character.
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub nickname: String,
+    /// Hex-encoded Noise static public key, if announced.
+    pub noise_public_key: Option<String>,
+    /// Milliseconds since the epoch.
+    pub last_seen: u64,
+    /// Link the peer's announce arrived on.
+    pub link_id: String,
+    /// Smoothed signal strength of that link, in dBm, if the peer is a
+    /// direct neighbor.
+    pub rssi: Option<i16>,
+    /// `rssi` as a 0-100 score.
+    pub signal_quality: Option<u8>,
+    pub proximity: Option<Proximity>,
+    /// Noise session state; `None` for peers without a Noise key.
+    pub session: Option<SessionStatus>,
+    /// Protocol version negotiated with the peer, if it's a direct
+    /// neighbor that has said hello.
+    pub protocol_version: Option<u8>,
+}
+
+struct Entry {
+    announcement: Announcement,
+    link_id: String,
+    /// The announce came straight from the peer, not through relays.
+    direct: bool,
+    last_seen: u64,
+}
+
+/// What became of an announce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Announced {
+    New,
+    Known,
+    /// It changed a pinned key and was ignored.
+    Rejected,
+}
+
+#[derive(Default)]
+pub struct Roster {
+    peers: HashMap<PeerId, Entry>,
+    /// Noise static public key -> the signing key announced with it by a
+    /// peer we completed a handshake with.
+    signing_keys: HashMap<[u8; 32], [u8; 32]>,
+    /// Link ID -> smoothed RSSI reported by the transport.
+    rssi: HashMap<String, f32>,
+}
+
+impl Roster {
+    /// Record an announce that arrived on `link_id`, `direct`ly from the
+    /// peer or relayed, unless it changes a pinned key.
+    pub fn announce(
+        &mut self,
+        peer_id: PeerId,
+        announcement: Announcement,
+        link_id: &str,
+        direct: bool,
+        now: u64,
+    ) -> Announced {
+        if !self.keeps_keys(&peer_id, &announcement) {
+            return Announced::Rejected;
+        }
+        let entry = Entry {
+            announcement,
+            link_id: link_id.to_string(),
+            direct,
+            last_seen: now,
+        };
+        match self.peers.insert(peer_id, entry) {
+            Some(_) => Announced::Known,
+            None => Announced::New,
+        }
+    }
+
+    fn keeps_keys(&self, peer_id: &PeerId, announcement: &Announcement) -> bool {
+        if let Some(entry) = self.peers.get(peer_id) {
+            let known = &entry.announcement;
+            let changed =
+                |old: Option<[u8; 32]>, new: Option<[u8; 32]>| old.is_some() && old != new;
+            if changed(known.noise_public_key, announcement.noise_public_key)
+                || changed(known.signing_public_key, announcement.signing_public_key)
+            {
+                return false;
+            }
+        }
+        match (
+            announcement.noise_public_key,
+            announcement.signing_public_key,
+        ) {
+            (Some(noise), Some(signing)) => self
+                .signing_keys
+                .get(&noise)
+                .map_or(true, |pinned| *pinned == signing),
+            _ => true,
+        }
+    }
+
+    /// Any packet from a known peer keeps it alive.
+    pub fn touch(&mut self, peer_id: &PeerId, now: u64) {
+        if let Some(entry) = self.peers.get_mut(peer_id) {
+            entry.last_seen = now;
+        }
+    }
+
+    pub fn remove(&mut self, peer_id: &PeerId) -> bool {
+        self.peers.remove(peer_id).is_some()
+    }
+
+    /// Fold a reading into the link's average. Returns true if the
+    /// proximity of peers on the link changed.
+    pub fn set_rssi(&mut self, link_id: &str, rssi: i16) -> bool {
+        let before = self.rssi(link_id).map(Proximity::from_rssi);
+        let average = self
+            .rssi
+            .entry(link_id.to_string())
+            .or_insert(f32::from(rssi));
+        *average += RSSI_SMOOTHING * (f32::from(rssi) - *average);
+        before != self.rssi(link_id).map(Proximity::from_rssi)
+    }
+
+    fn rssi(&self, link_id: &str) -> Option<i16> {
+        self.rssi.get(link_id).map(|average| average.round() as i16)
+    }
+
+    pub fn link_down(&mut self, link_id: &str) {
+        self.rssi.remove(link_id);
+    }
+
+    /// Remove peers not heard from within [`PEER_TIMEOUT_MS`].
+    pub fn expire(&mut self, now: u64) -> Vec<PeerId> {
+        let lost: Vec<PeerId> = self
+            .peers
+            .iter()
+            .filter(|(_, e)| now.saturating_sub(e.last_seen) >= PEER_TIMEOUT_MS)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &lost {
+            self.peers.remove(id);
+        }
+        lost
+    }
+
+    pub fn get(&self, peer_id: &PeerId) -> Option<PeerInfo> {
+        let entry = self.peers.get(peer_id)?;
+        let rssi = self.rssi(&entry.link_id).filter(|_| entry.direct);
+        Some(PeerInfo {
+            peer_id: peer_id.to_string(),
+            nickname: entry.announcement.nickname.clone(),
+            noise_public_key: entry.announcement.noise_public_key.map(hex::encode),
+            last_seen: entry.last_seen,
+            link_id: entry.link_id.clone(),
+            rssi,
+            signal_quality: rssi.map(signal_quality),
+            proximity: rssi.map(Proximity::from_rssi),
+            session: None,
+            protocol_version: None,
+        })
+    }
+
+    /// A Noise handshake with `peer_id` completed, so it holds the Noise
+    /// key it announced: pin the signing key announced with it.
+    pub fn bind(&mut self, peer_id: &PeerId) {
+        let Some(entry) = self.peers.get(peer_id) else {
+            return;
+        };
+        if let (Some(noise), Some(signing)) = (
+            entry.announcement.noise_public_key,
+            entry.announcement.signing_public_key,
+        ) {
+            self.signing_keys.entry(noise).or_insert(signing);
+        }
+    }
+
+    /// The Ed25519 key `peer_id` announced for signing public messages,
+    /// once it's pinned to the peer's Noise key.
+    pub fn signing_key(&self, peer_id: &PeerId) -> Option<[u8; 32]> {
+        let announcement = &self.peers.get(peer_id)?.announcement;
+        let signing = announcement.signing_public_key?;
+        let pinned = self.signing_keys.get(&announcement.noise_public_key?)?;
+        (*pinned == signing).then_some(signing)
+    }
+
+    /// The peer currently announcing `noise_public_key`, if any.
+    pub fn find(&self, noise_public_key: &[u8; 32]) -> Option<PeerId> {
+        self.peers
+            .iter()
+            .filter(|(_, e)| e.announcement.noise_public_key.as_ref() == Some(noise_public_key))
+            .max_by_key(|(_, e)| e.last_seen)
+            .map(|(id, _)| *id)
+    }
+
+    pub fn list(&self) -> Vec<PeerInfo> {
+        let mut peers: Vec<PeerInfo> = self.peers.keys().filter_map(|id| self.get(id)).collect();
+        peers.sort_by(|a, b| a.nickname.cmp(&b.nickname));
+        peers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announcement_round_trips() {
+        let a = Announcement {
+            nickname: "alice".to_string(),
+            noise_public_key: Some([7; 32]),
+            signing_public_key: None,
+        };
+        assert_eq!(Announcement::parse(&a.encode()).unwrap(), a);
+    }
+
+    #[test]
+    fn accepts_bare_nickname() {
+        let a = Announcement::parse(b"bob").unwrap();
+        assert_eq!(a.nickname, "bob");
+        assert_eq!(a.noise_public_key, None);
+    }
+
+    #[test]
+    fn roster_expires_silent_peers() {
+        let mut roster = Roster::default();
+        let id = PeerId([1; 8]);
+        let a = Announcement::parse(b"bob").unwrap();
+        assert_eq!(roster.announce(id, a.clone(), "l", true, 0), Announced::New);
+        assert_eq!(roster.announce(id, a, "l", true, 0), Announced::Known);
+        roster.set_rssi("l", -60);
+        assert_eq!(roster.find(&[7; 32]), None);
+        assert_eq!(roster.list()[0].rssi, Some(-60));
+        roster.touch(&id, PEER_TIMEOUT_MS - 1);
+        assert!(roster.expire(PEER_TIMEOUT_MS).is_empty());
+        assert_eq!(roster.expire(2 * PEER_TIMEOUT_MS), vec![id]);
+    }
+
+    #[test]
+    fn signing_keys_are_pinned_after_a_handshake() {
+        let mut roster = Roster::default();
+        let victim = PeerId([1; 8]);
+        let impostor = PeerId([2; 8]);
+        let announce = |signing: u8| Announcement {
+            nickname: "alice".to_string(),
+            noise_public_key: Some([7; 32]),
+            signing_public_key: Some([signing; 32]),
+        };
+
+        // Announcing the victim's Noise key first, with another signing
+        // key, gets the impostor nothing: it can't complete a handshake.
+        assert_eq!(
+            roster.announce(impostor, announce(2), "l", true, 0),
+            Announced::New
+        );
+        assert_eq!(roster.signing_key(&impostor), None);
+        assert_eq!(
+            roster.announce(victim, announce(1), "l", true, 0),
+            Announced::New
+        );
+        assert_eq!(roster.signing_key(&victim), None);
+        roster.bind(&victim);
+        assert_eq!(roster.signing_key(&victim), Some([1; 32]));
+        assert_eq!(roster.signing_key(&impostor), None);
+
+        // Re-announcing the peer, or its Noise key, with another signing
+        // key doesn't take it over.
+        assert_eq!(
+            roster.announce(victim, announce(2), "l", true, 1),
+            Announced::Rejected
+        );
+        assert_eq!(
+            roster.announce(impostor, announce(2), "l", true, 1),
+            Announced::Rejected
+        );
+        assert_eq!(roster.signing_key(&victim), Some([1; 32]));
+
+        // The pin outlives the peer ID.
+        roster.expire(PEER_TIMEOUT_MS);
+        let rotated = PeerId([3; 8]);
+        assert_eq!(
+            roster.announce(rotated, announce(2), "l", true, PEER_TIMEOUT_MS),
+            Announced::Rejected
+        );
+        assert_eq!(
+            roster.announce(rotated, announce(1), "l", true, PEER_TIMEOUT_MS),
+            Announced::New
+        );
+        assert_eq!(roster.signing_key(&rotated), Some([1; 32]));
+    }
+
+    #[test]
+    fn rssi_is_smoothed_into_proximity() {
+        let mut roster = Roster::default();
+        let id = PeerId([1; 8]);
+        roster.announce(id, Announcement::parse(b"bob").unwrap(), "l", true, 0);
+        assert!(roster.set_rssi("l", -40));
+        assert_eq!(roster.get(&id).unwrap().proximity, Some(Proximity::Near));
+
+        // One bad reading doesn't move a near peer away.
+        assert!(!roster.set_rssi("l", -90));
+        assert_eq!(roster.get(&id).unwrap().rssi, Some(-55));
+        assert!(roster.set_rssi("l", -90));
+        assert_eq!(roster.get(&id).unwrap().proximity, Some(Proximity::Medium));
+
+        // A peer relayed over the link isn't as close as the neighbor.
+        let relayed = PeerId([2; 8]);
+        let a = Announcement::parse(b"carol").unwrap();
+        roster.announce(relayed, a, "l", false, 0);
+        let carol = roster.get(&relayed).unwrap();
+        assert_eq!(carol.link_id, "l");
+        assert_eq!(carol.rssi, None);
+        assert_eq!(carol.signal_quality, None);
+        assert_eq!(carol.proximity, None);
+
+        assert_eq!(signal_quality(-120), 0);
+        assert_eq!(signal_quality(-70), 50);
+        assert_eq!(signal_quality(-30), 100);
+    }
+}
@@ -0,0 +1,96 @@
+//! Delivery acks and read receipts for private mesh messages.
+//!
+//! Receipts travel as `DeliveryAck` and `ReadReceipt` packets addressed to
+//! the message's sender. Their payload names the message and is encrypted
+//! with the Noise session between the two peers, so like the messages
+//! themselves it's sealed and opened by the frontend: it builds one with
+//! [`mesh_send_receipt`], and receipts that arrive for us are handed over
+//! as [`MeshEvent::ReceiptReceived`]. Once decrypted, the frontend reports
+//! the message ID to the delivery manager
+//! ([`crate::transport::delivery`]).
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{AppHandle, State};
+
+use super::packet::{MessageType, Packet};
+use super::{now_ms, MeshEvent, MeshState};
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptKind {
+    Delivered,
+    Read,
+}
+
+impl ReceiptKind {
+    pub fn message_type(self) -> MessageType {
+        match self {
+            ReceiptKind::Delivered => MessageType::DeliveryAck,
+            ReceiptKind::Read => MessageType::ReadReceipt,
+        }
+    }
+
+    pub fn of(message_type: MessageType) -> Option<Self> {
+        match message_type {
+            MessageType::DeliveryAck => Some(ReceiptKind::Delivered),
+            MessageType::ReadReceipt => Some(ReceiptKind::Read),
+            _ => None,
+        }
+    }
+}
+
+/// Hand a receipt addressed to us to the frontend to decrypt. Returns
+/// false for any other packet.
+pub fn handle_packet(app: &AppHandle, packet: &Packet) -> bool {
+    let Some(kind) = ReceiptKind::of(packet.message_type) else {
+        return false;
+    };
+    if packet.recipient_id.map_or(true, |id| id.is_broadcast()) {
+        // Receipts are always for one peer.
+        return true;
+    }
+    events::emit(
+        app,
+        MeshEvent::ReceiptReceived {
+            peer_id: packet.sender_id.to_string(),
+            kind,
+            payload: base64::engine::general_purpose::STANDARD.encode(&packet.payload),
+        },
+    );
+    true
+}
+
+/// Send a receipt to `peer_id`. `payload` is the base64 Noise ciphertext
+/// naming the message.
+#[tauri::command]
+#[specta::specta]
+pub fn mesh_send_receipt(
+    app: AppHandle,
+    peer_id: String,
+    kind: ReceiptKind,
+    payload: String,
+    state: State<'_, MeshState>,
+) -> AppResult<()> {
+    let recipient = peer_id.parse()?;
+    let payload = base64::engine::general_purpose::STANDARD
+        .decode(payload)
+        .map_err(|_| AppError::new(ErrorCode::InvalidArgument).with("field", "payload"))?;
+    let actions = {
+        let mut node = state.node.lock().unwrap();
+        let now = now_ms();
+        let packet = Packet::new(
+            kind.message_type(),
+            node.peer_id(),
+            Some(recipient),
+            payload,
+            now,
+        );
+        node.originate(&packet, now)
+    };
+    super::perform(&app, actions);
+    Ok(())
+}
@@ -0,0 +1,207 @@
+//! Noise session bookkeeping for nearby peers.
+//!
+//! The XX handshake itself runs in the frontend's crypto module; this
+//! module decides when one is due. When a peer with a Noise key appears,
+//! the side with the lower peer ID initiates, as in the native apps. A
+//! handshake that fails or doesn't finish within [`HANDSHAKE_TIMEOUT_MS`]
+//! is retried with exponential backoff, up to [`MAX_ATTEMPTS`] times. A
+//! peer only counts as ready once the frontend reports the session is
+//! established.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use specta::Type;
+
+use super::packet::PeerId;
+
+pub const HANDSHAKE_TIMEOUT_MS: u64 = 10_000;
+pub const INITIAL_BACKOFF_MS: u64 = 2_000;
+pub const MAX_BACKOFF_MS: u64 = 60_000;
+pub const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStatus {
+    /// A handshake is under way or waiting to be retried.
+    Handshaking,
+    /// Transport encryption is established.
+    Ready,
+    /// Gave up after [`MAX_ATTEMPTS`].
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// We're the initiator; an attempt times out at `until`.
+    InProgress {
+        until: u64,
+    },
+    /// We're the initiator; retry at `at`.
+    Backoff {
+        at: u64,
+    },
+    /// The peer initiates; nothing to do until it does.
+    AwaitingPeer,
+    Ready,
+    Failed,
+}
+
+struct Session {
+    state: State,
+    attempts: u32,
+}
+
+/// What [`Sessions::due`] found.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Due {
+    /// Start a (new) handshake with these peers.
+    pub start: Vec<PeerId>,
+    /// These peers ran out of attempts.
+    pub failed: Vec<PeerId>,
+}
+
+#[derive(Default)]
+pub struct Sessions {
+    sessions: HashMap<PeerId, Session>,
+}
+
+impl Sessions {
+    /// A peer with a Noise key has appeared. Returns true if we should
+    /// start the handshake now.
+    pub fn peer_connected(&mut self, us: PeerId, peer_id: PeerId, now: u64) -> bool {
+        let initiator = us.0 < peer_id.0;
+        let state = if initiator {
+            State::InProgress {
+                until: now + HANDSHAKE_TIMEOUT_MS,
+            }
+        } else {
+            State::AwaitingPeer
+        };
+        self.sessions.insert(
+            peer_id,
+            Session {
+                state,
+                attempts: u32::from(initiator),
+            },
+        );
+        initiator
+    }
+
+    /// The frontend finished the handshake. Returns true if the peer just
+    /// became ready.
+    pub fn established(&mut self, peer_id: PeerId) -> bool {
+        let session = self.sessions.entry(peer_id).or_insert(Session {
+            state: State::AwaitingPeer,
+            attempts: 0,
+        });
+        let was_ready = session.state == State::Ready;
+        session.state = State::Ready;
+        !was_ready
+    }
+
+    /// The frontend's handshake failed; schedule a retry.
+    pub fn failed(&mut self, peer_id: PeerId, now: u64) {
+        if let Some(session) = self.sessions.get_mut(&peer_id) {
+            session.state = State::Backoff {
+                at: now + backoff(session.attempts),
+            };
+        }
+    }
+
+    pub fn remove(&mut self, peer_id: &PeerId) {
+        self.sessions.remove(peer_id);
+    }
+
+    pub fn status(&self, peer_id: &PeerId) -> Option<SessionStatus> {
+        Some(match self.sessions.get(peer_id)?.state {
+            State::Ready => SessionStatus::Ready,
+            State::Failed => SessionStatus::Failed,
+            _ => SessionStatus::Handshaking,
+        })
+    }
+
+    /// Time out stalled attempts and pick the retries that are due.
+    pub fn due(&mut self, now: u64) -> Due {
+        let mut due = Due::default();
+        for (peer_id, session) in &mut self.sessions {
+            match session.state {
+                State::InProgress { until } if now >= until => {
+                    session.state = State::Backoff {
+                        at: now + backoff(session.attempts),
+                    };
+                }
+                _ => {}
+            }
+            if let State::Backoff { at } = session.state {
+                if now < at {
+                    continue;
+                }
+                if session.attempts >= MAX_ATTEMPTS {
+                    session.state = State::Failed;
+                    due.failed.push(*peer_id);
+                } else {
+                    session.attempts += 1;
+                    session.state = State::InProgress {
+                        until: now + HANDSHAKE_TIMEOUT_MS,
+                    };
+                    due.start.push(*peer_id);
+                }
+            }
+        }
+        due
+    }
+}
+
+/// Wait before the attempt after `attempts` failed ones.
+fn backoff(attempts: u32) -> u64 {
+    INITIAL_BACKOFF_MS
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(MAX_BACKOFF_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOW: PeerId = PeerId([1; 8]);
+    const HIGH: PeerId = PeerId([2; 8]);
+
+    #[test]
+    fn lower_peer_id_initiates() {
+        let mut sessions = Sessions::default();
+        assert!(sessions.peer_connected(LOW, HIGH, 0));
+        assert!(!sessions.peer_connected(HIGH, LOW, 0));
+        assert_eq!(sessions.status(&LOW), Some(SessionStatus::Handshaking));
+        assert!(sessions.established(LOW));
+        assert!(!sessions.established(LOW));
+        assert_eq!(sessions.status(&LOW), Some(SessionStatus::Ready));
+    }
+
+    #[test]
+    fn retries_with_backoff_then_gives_up() {
+        let mut sessions = Sessions::default();
+        sessions.peer_connected(LOW, HIGH, 0);
+        sessions.failed(HIGH, 0);
+        assert_eq!(sessions.due(INITIAL_BACKOFF_MS - 1), Due::default());
+        assert_eq!(sessions.due(INITIAL_BACKOFF_MS).start, [HIGH]);
+
+        // Later attempts time out instead of failing outright.
+        let mut now = INITIAL_BACKOFF_MS;
+        let mut starts = 1;
+        loop {
+            now += HANDSHAKE_TIMEOUT_MS;
+            sessions.due(now);
+            now += MAX_BACKOFF_MS;
+            let due = sessions.due(now);
+            if !due.failed.is_empty() {
+                assert_eq!(due.failed, [HIGH]);
+                break;
+            }
+            assert_eq!(due.start, [HIGH]);
+            starts += 1;
+        }
+        assert_eq!(starts + 1, MAX_ATTEMPTS);
+        assert_eq!(sessions.status(&HIGH), Some(SessionStatus::Failed));
+    }
+}
@@ -0,0 +1,432 @@
+//! A deterministic in-process mesh, for testing the protocol without
+//! radios.
+//!
+//! [`Simulation`] runs N real [`Node`]s and plays the part of their
+//! transports: every frame a node sends is encoded (and padded) as it
+//! would be on air, then lost or delivered to each neighbor after a random
+//! latency. Time is simulated and all randomness comes from one seed, so a
+//! run can be replayed exactly. Links can be cut and restored mid-run to
+//! exercise store-and-forward, and [`SimStats`] counts what went over the
+//! air for comparing protocol changes.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap};
+use std::ops::RangeInclusive;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::node::{Action, Node};
+use super::packet::{self, Packet, PeerId};
+use super::peers::Announcement;
+use super::MeshEvent;
+
+/// How often each node's [`Node::tick`] runs, as in the real loop.
+pub const TICK_MS: u64 = 5_000;
+const LINK_PREFIX: &str = "sim:";
+
+/// Which nodes are in range of each other.
+#[derive(Debug, Clone)]
+pub enum Topology {
+    /// Each node hears only the one before and after it.
+    Line,
+    /// A line whose ends also hear each other.
+    Ring,
+    /// Everyone hears everyone.
+    Full,
+    Edges(Vec<(usize, usize)>),
+}
+
+impl Topology {
+    fn edges(&self, n: usize) -> Vec<(usize, usize)> {
+        match self {
+            Topology::Line => (1..n).map(|i| (i - 1, i)).collect(),
+            Topology::Ring => (0..n)
+                .map(|i| (i, (i + 1) % n))
+                .filter(|(a, b)| a != b)
+                .collect(),
+            Topology::Full => (0..n)
+                .flat_map(|a| (a + 1..n).map(move |b| (a, b)))
+                .collect(),
+            Topology::Edges(edges) => edges.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub seed: u64,
+    /// Chance that any one frame is lost on any one link.
+    pub loss: f64,
+    /// One-way latency of a link, drawn per frame.
+    pub latency_ms: RangeInclusive<u64>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            loss: 0.0,
+            latency_ms: 5..=20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimStats {
+    /// Frames put on a link, lost or not.
+    pub frames: u64,
+    pub bytes: u64,
+    pub lost: u64,
+}
+
+/// A frame on its way from one node to another.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct InFlight {
+    at: u64,
+    /// Keeps frames arriving at the same moment in send order.
+    seq: u64,
+    to: usize,
+    from: usize,
+    data: Vec<u8>,
+}
+
+pub struct Simulation {
+    nodes: Vec<Node>,
+    links: BTreeSet<(usize, usize)>,
+    config: SimConfig,
+    rng: StdRng,
+    now: u64,
+    next_tick: u64,
+    seq: u64,
+    in_flight: BinaryHeap<Reverse<InFlight>>,
+    delivered: Vec<Vec<Packet>>,
+    events: Vec<Vec<MeshEvent>>,
+    stats: SimStats,
+}
+
+fn link_id(to: usize) -> String {
+    format!("{LINK_PREFIX}{to}")
+}
+
+impl Simulation {
+    /// `n` nodes with peer IDs `1..=n`, linked as `topology` says.
+    pub fn new(n: usize, topology: Topology, config: SimConfig) -> Self {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let nodes = (0..n)
+            .map(|i| Node::new(Self::peer_id_of(i), rng.gen()))
+            .collect();
+        let mut sim = Self {
+            nodes,
+            links: BTreeSet::new(),
+            config,
+            rng,
+            now: 0,
+            next_tick: TICK_MS,
+            seq: 0,
+            in_flight: BinaryHeap::new(),
+            delivered: vec![Vec::new(); n],
+            events: vec![Vec::new(); n],
+            stats: SimStats::default(),
+        };
+        for (a, b) in topology.edges(n) {
+            sim.connect(a, b);
+        }
+        sim
+    }
+
+    fn peer_id_of(i: usize) -> PeerId {
+        PeerId((i as u64 + 1).to_be_bytes())
+    }
+
+    pub fn peer_id(&self, i: usize) -> PeerId {
+        self.nodes[i].peer_id()
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn stats(&self) -> &SimStats {
+        &self.stats
+    }
+
+    pub fn node(&self, i: usize) -> &Node {
+        &self.nodes[i]
+    }
+
+    /// Packets node `i` has handed to its app so far.
+    pub fn delivered(&self, i: usize) -> &[Packet] {
+        &self.delivered[i]
+    }
+
+    /// Events node `i` has emitted so far.
+    pub fn events(&self, i: usize) -> &[MeshEvent] {
+        &self.events[i]
+    }
+
+    /// Bring `a` and `b` into range of each other.
+    pub fn connect(&mut self, a: usize, b: usize) {
+        if !self.links.insert((a.min(b), a.max(b))) {
+            return;
+        }
+        for (from, to) in [(a, b), (b, a)] {
+            let actions = self.nodes[from].link_up(&link_id(to), self.now);
+            self.perform(from, actions);
+        }
+    }
+
+    /// Take `a` and `b` out of range. Frames already in flight between
+    /// them are lost.
+    pub fn disconnect(&mut self, a: usize, b: usize) {
+        if !self.links.remove(&(a.min(b), a.max(b))) {
+            return;
+        }
+        self.nodes[a].link_down(&link_id(b));
+        self.nodes[b].link_down(&link_id(a));
+    }
+
+    fn linked(&self, a: usize, b: usize) -> bool {
+        self.links.contains(&(a.min(b), a.max(b)))
+    }
+
+    /// Have every node announce itself under `node<i>`.
+    pub fn announce_all(&mut self) {
+        for i in 0..self.nodes.len() {
+            let announcement = Announcement {
+                nickname: format!("node{i}"),
+                noise_public_key: None,
+                signing_public_key: None,
+            };
+            let actions = self.nodes[i].set_announcement(announcement, self.now);
+            self.perform(i, actions);
+        }
+    }
+
+    /// Run `f` against node `i` at the current time and carry out the
+    /// actions it returns, e.g. `sim.act(0, |node, now| node.originate(&p, now))`.
+    pub fn act(&mut self, i: usize, f: impl FnOnce(&mut Node, u64) -> Vec<Action>) {
+        let actions = f(&mut self.nodes[i], self.now);
+        self.perform(i, actions);
+    }
+
+    /// Advance simulated time by `duration_ms`, delivering frames and
+    /// ticking nodes as they fall due.
+    pub fn run_for(&mut self, duration_ms: u64) {
+        let end = self.now + duration_ms;
+        loop {
+            let next_frame = self.in_flight.peek().map(|Reverse(f)| f.at);
+            let next = match next_frame {
+                Some(at) if at <= self.next_tick => at,
+                _ => self.next_tick,
+            };
+            if next > end {
+                break;
+            }
+            self.now = next;
+            if next_frame == Some(next) {
+                let Reverse(frame) = self.in_flight.pop().unwrap();
+                if self.linked(frame.from, frame.to) {
+                    let actions =
+                        self.nodes[frame.to].handle_frame(&link_id(frame.from), &frame.data, next);
+                    self.perform(frame.to, actions);
+                }
+            } else {
+                self.next_tick += TICK_MS;
+                for i in 0..self.nodes.len() {
+                    let actions = self.nodes[i].tick(next);
+                    self.perform(i, actions);
+                }
+            }
+        }
+        self.now = end;
+    }
+
+    fn perform(&mut self, from: usize, actions: Vec<Action>) {
+        for action in actions {
+            match action {
+                Action::Deliver { packet, .. } => self.delivered[from].push(packet),
+                Action::Emit(event) => self.events[from].push(event),
+                Action::Broadcast {
+                    data,
+                    exclude,
+                    delay_ms,
+                    pad,
+                } => {
+                    let data = if pad { packet::pad(data) } else { data };
+                    let neighbors: Vec<usize> = (0..self.nodes.len())
+                        .filter(|&to| to != from && self.linked(from, to))
+                        .filter(|&to| exclude.as_deref() != Some(link_id(to).as_str()))
+                        .collect();
+                    for to in neighbors {
+                        self.transmit(from, to, data.clone(), delay_ms);
+                    }
+                }
+                Action::Send { link_id, data, pad } => {
+                    let data = if pad { packet::pad(data) } else { data };
+                    let to = link_id
+                        .strip_prefix(LINK_PREFIX)
+                        .and_then(|to| to.parse().ok())
+                        .filter(|&to| self.linked(from, to));
+                    if let Some(to) = to {
+                        self.transmit(from, to, data, 0);
+                    }
+                }
+            }
+        }
+    }
+
+    fn transmit(&mut self, from: usize, to: usize, data: Vec<u8>, delay_ms: u64) {
+        self.stats.frames += 1;
+        self.stats.bytes += data.len() as u64;
+        if self.rng.gen_bool(self.config.loss) {
+            self.stats.lost += 1;
+            return;
+        }
+        let latency = self.rng.gen_range(self.config.latency_ms.clone());
+        self.seq += 1;
+        self.in_flight.push(Reverse(InFlight {
+            at: self.now + delay_ms + latency,
+            seq: self.seq,
+            to,
+            from,
+            data,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::packet::MessageType;
+
+    fn public(sim: &Simulation, from: usize, text: &str) -> Packet {
+        Packet::new(
+            MessageType::Message,
+            sim.peer_id(from),
+            None,
+            text.as_bytes().to_vec(),
+            sim.now(),
+        )
+    }
+
+    fn received(sim: &Simulation, i: usize, payload: &[u8]) -> usize {
+        sim.delivered(i)
+            .iter()
+            .filter(|p| p.payload == payload)
+            .count()
+    }
+
+    #[test]
+    fn topologies_link_the_expected_pairs() {
+        assert_eq!(Topology::Ring.edges(3), [(0, 1), (1, 2), (2, 0)]);
+        assert_eq!(Topology::Full.edges(3), [(0, 1), (0, 2), (1, 2)]);
+        let sim = Simulation::new(3, Topology::Edges(vec![(2, 0)]), SimConfig::default());
+        assert!(sim.linked(0, 2) && !sim.linked(0, 1));
+    }
+
+    #[test]
+    fn broadcasts_cross_a_line_once_per_node() {
+        let mut sim = Simulation::new(6, Topology::Line, SimConfig::default());
+        let packet = public(&sim, 0, "hello");
+        sim.act(0, |node, now| node.originate(&packet, now));
+        sim.run_for(1_000);
+        for i in 1..6 {
+            assert_eq!(received(&sim, i, b"hello"), 1, "node {i}");
+        }
+        assert_eq!(received(&sim, 0, b"hello"), 0);
+    }
+
+    #[test]
+    fn ttl_limits_how_far_broadcasts_go() {
+        let mut sim = Simulation::new(5, Topology::Line, SimConfig::default());
+        let mut packet = public(&sim, 0, "short");
+        packet.ttl = 2;
+        sim.act(0, |node, now| node.originate(&packet, now));
+        sim.run_for(1_000);
+        let reached: Vec<usize> = (1..5).map(|i| received(&sim, i, b"short")).collect();
+        assert_eq!(reached, [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn private_messages_wait_for_peers_that_left() {
+        let mut sim = Simulation::new(3, Topology::Line, SimConfig::default());
+        sim.announce_all();
+        sim.run_for(1_000);
+        assert_eq!(sim.node(1).peers().len(), 2);
+        let discovered = |sim: &Simulation, i| {
+            sim.events(i)
+                .iter()
+                .filter(|e| matches!(e, MeshEvent::PeerDiscovered { .. }))
+                .count()
+        };
+        assert_eq!(discovered(&sim, 2), 2);
+
+        // Node 2 wanders off; node 1 keeps what node 0 sends it meanwhile.
+        sim.disconnect(1, 2);
+        sim.run_for(61_000);
+        let to = sim.peer_id(2);
+        sim.act(0, |node, now| node.send_private(to, b"later".to_vec(), now));
+        sim.run_for(1_000);
+        assert_eq!(received(&sim, 2, b"later"), 0);
+        assert_eq!(sim.node(1).stats().cached, 1);
+
+        sim.connect(1, 2);
+        sim.run_for(super::super::node::ANNOUNCE_INTERVAL_MS + TICK_MS);
+        assert_eq!(received(&sim, 2, b"later"), 1);
+        assert_eq!(sim.node(1).stats().forwarded_from_cache, 1);
+    }
+
+    #[test]
+    fn fragmented_private_messages_wait_for_peers_that_left() {
+        let mut sim = Simulation::new(3, Topology::Line, SimConfig::default());
+        sim.announce_all();
+        sim.run_for(1_000);
+
+        sim.disconnect(1, 2);
+        sim.run_for(61_000);
+        let to = sim.peer_id(2);
+        let payload: Vec<u8> = (0..3000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let sent = payload.clone();
+        sim.act(0, |node, now| node.send_private(to, sent, now));
+        sim.run_for(1_000);
+        assert_eq!(received(&sim, 2, &payload), 0);
+        let fragments = sim.node(1).stats().cached;
+        assert!(fragments > 1);
+
+        sim.connect(1, 2);
+        sim.run_for(super::super::node::ANNOUNCE_INTERVAL_MS + TICK_MS);
+        assert_eq!(received(&sim, 2, &payload), 1);
+        assert_eq!(sim.node(1).stats().forwarded_from_cache, fragments);
+    }
+
+    #[test]
+    fn lossy_links_recover_fragments_and_replay_exactly() {
+        fn run() -> (Simulation, Vec<u8>) {
+            let config = SimConfig {
+                seed: 7,
+                loss: 0.2,
+                ..SimConfig::default()
+            };
+            let mut sim = Simulation::new(2, Topology::Full, config);
+            let payload: Vec<u8> = (0..3000u32)
+                .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+                .collect();
+            let to = sim.peer_id(1);
+            let sent = payload.clone();
+            sim.act(0, |node, now| node.send_private(to, sent, now));
+            sim.run_for(30_000);
+            (sim, payload)
+        }
+
+        let (sim, payload) = run();
+        assert!(sim.stats().lost > 0);
+        assert_eq!(received(&sim, 1, &payload), 1);
+        assert!(sim.node(0).stats().fragments_resent > 0);
+
+        let (again, _) = run();
+        assert_eq!(again.stats(), sim.stats());
+    }
+}
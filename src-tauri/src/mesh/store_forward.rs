@@ -0,0 +1,178 @@
+//! Cache of private packets for peers that are out of range.
+//!
+//! When we relay a packet for a peer we've seen recently but can't reach
+//! right now, a copy is kept here and sent again when that peer announces
+//! itself. Favorites get a longer retention and a bigger share of the cache,
+//! as in the native apps.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::packet::{Packet, PeerId};
+
+pub const RETENTION_MS: u64 = 12 * 60 * 60 * 1000;
+pub const FAVORITE_RETENTION_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+const MAX_PER_PEER: usize = 100;
+const MAX_PER_FAVORITE: usize = 1000;
+const MAX_TOTAL: usize = 5000;
+
+struct Cached {
+    packet: Packet,
+    stored_at: u64,
+}
+
+#[derive(Default)]
+pub struct StoreForward {
+    queues: HashMap<PeerId, VecDeque<Cached>>,
+    favorites: HashSet<PeerId>,
+    total: usize,
+}
+
+impl StoreForward {
+    pub fn set_favorite(&mut self, peer_id: PeerId, favorite: bool) {
+        if favorite {
+            self.favorites.insert(peer_id);
+        } else {
+            self.favorites.remove(&peer_id);
+        }
+    }
+
+    pub fn is_favorite(&self, peer_id: &PeerId) -> bool {
+        self.favorites.contains(peer_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.total
+    }
+
+    /// Keep `packet` for its recipient, evicting the oldest packets when the
+    /// peer's queue or the whole cache is full.
+    pub fn store(&mut self, packet: Packet, now: u64) {
+        let Some(recipient) = packet.recipient_id else {
+            return;
+        };
+        self.expire(now);
+        if self.total >= MAX_TOTAL {
+            self.evict_oldest_regular();
+        }
+        let limit = if self.is_favorite(&recipient) {
+            MAX_PER_FAVORITE
+        } else {
+            MAX_PER_PEER
+        };
+        let queue = self.queues.entry(recipient).or_default();
+        if queue.len() >= limit {
+            queue.pop_front();
+            self.total -= 1;
+        }
+        queue.push_back(Cached {
+            packet,
+            stored_at: now,
+        });
+        self.total += 1;
+    }
+
+    /// Remove and return everything held for `peer_id`, oldest first.
+    pub fn take(&mut self, peer_id: &PeerId, now: u64) -> Vec<Packet> {
+        self.expire(now);
+        let queue = self.queues.remove(peer_id).unwrap_or_default();
+        self.total -= queue.len();
+        queue.into_iter().map(|c| c.packet).collect()
+    }
+
+    pub fn expire(&mut self, now: u64) {
+        let favorites = &self.favorites;
+        let mut removed = 0;
+        self.queues.retain(|peer_id, queue| {
+            let retention = if favorites.contains(peer_id) {
+                FAVORITE_RETENTION_MS
+            } else {
+                RETENTION_MS
+            };
+            let before = queue.len();
+            queue.retain(|c| now.saturating_sub(c.stored_at) < retention);
+            removed += before - queue.len();
+            !queue.is_empty()
+        });
+        self.total -= removed;
+    }
+
+    /// Drop the oldest packet held for a non-favorite, or failing that for
+    /// anyone.
+    fn evict_oldest_regular(&mut self) {
+        let oldest = |regular_only: bool| {
+            self.queues
+                .iter()
+                .filter(|(id, _)| !regular_only || !self.favorites.contains(id))
+                .filter_map(|(id, q)| q.front().map(|c| (*id, c.stored_at)))
+                .min_by_key(|(_, at)| *at)
+                .map(|(id, _)| id)
+        };
+        let Some(peer_id) = oldest(true).or_else(|| oldest(false)) else {
+            return;
+        };
+        if let Some(queue) = self.queues.get_mut(&peer_id) {
+            queue.pop_front();
+            self.total -= 1;
+            if queue.is_empty() {
+                self.queues.remove(&peer_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::packet::MessageType;
+
+    fn packet(to: u8, n: u8) -> Packet {
+        Packet {
+            version: 1,
+            message_type: MessageType::NoiseEncrypted,
+            ttl: 3,
+            timestamp: n as u64,
+            sender_id: PeerId([0; 8]),
+            recipient_id: Some(PeerId([to; 8])),
+            payload: vec![n],
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn holds_packets_until_taken() {
+        let mut cache = StoreForward::default();
+        cache.store(packet(1, 1), 0);
+        cache.store(packet(1, 2), 0);
+        cache.store(packet(2, 3), 0);
+        let taken = cache.take(&PeerId([1; 8]), 10);
+        assert_eq!(
+            taken.iter().map(|p| p.payload[0]).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn favorites_are_kept_longer() {
+        let mut cache = StoreForward::default();
+        cache.set_favorite(PeerId([1; 8]), true);
+        cache.store(packet(1, 1), 0);
+        cache.store(packet(2, 2), 0);
+        cache.expire(RETENTION_MS);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.take(&PeerId([1; 8]), RETENTION_MS).len(), 1);
+    }
+
+    #[test]
+    fn per_peer_queue_is_bounded() {
+        let mut cache = StoreForward::default();
+        for n in 0..=MAX_PER_PEER as u64 {
+            let mut p = packet(1, 0);
+            p.timestamp = n;
+            cache.store(p, 0);
+        }
+        let taken = cache.take(&PeerId([1; 8]), 0);
+        assert_eq!(taken.len(), MAX_PER_PEER);
+        assert_eq!(taken[0].timestamp, 1);
+    }
+}
@@ -0,0 +1,266 @@
+//! Protocol version negotiation with directly connected peers.
+//!
+//! When a link comes up we send a `VersionHello` listing the protocol
+//! versions and optional features we support. The peer answers with a
+//! `VersionAck` naming the highest version both sides support and the
+//! features both can use at that version, or no version if there is none.
+//! Either side may say hello; the last answer wins. Both packets travel a
+//! single hop (TTL 1). Their payloads are TLV-encoded like announces:
+//!
+//! ```text
+//! hello: 0x01 versions..  0x02 features:1
+//! ack:   0x03 version:1   0x02 features:1   (no 0x03 = rejected)
+//! ```
+//!
+//! Links that haven't negotiated (older native clients never say hello)
+//! get every feature, since all current clients understand them.
+
+use std::collections::HashMap;
+
+use super::packet::{PeerId, VERSION};
+use crate::error::{AppError, AppResult, ErrorCode};
+
+/// Protocol versions we speak, oldest first.
+pub const SUPPORTED_VERSIONS: &[u8] = &[VERSION];
+
+const TLV_VERSIONS: u8 = 0x01;
+const TLV_FEATURES: u8 = 0x02;
+const TLV_AGREED: u8 = 0x03;
+
+/// Optional wire features, as a bit set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features(u8);
+
+impl Features {
+    pub const NONE: Features = Features(0);
+    /// LZ4-compressed payloads.
+    pub const COMPRESSION: Features = Features(0x01);
+    /// Frames padded to size buckets.
+    pub const PADDING: Features = Features(0x02);
+    pub const ALL: Features = Features(0x03);
+
+    /// Features available at protocol `version`.
+    pub fn of_version(version: u8) -> Features {
+        match version {
+            1.. => Features::ALL,
+            0 => Features::NONE,
+        }
+    }
+
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersect(self, other: Features) -> Features {
+        Features(self.0 & other.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    pub versions: Vec<u8>,
+    pub features: Features,
+}
+
+impl Hello {
+    pub fn ours() -> Self {
+        Self {
+            versions: SUPPORTED_VERSIONS.to_vec(),
+            features: Features::ALL,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![TLV_VERSIONS, self.versions.len() as u8];
+        out.extend_from_slice(&self.versions);
+        out.extend_from_slice(&[TLV_FEATURES, 1, self.features.0]);
+        out
+    }
+
+    pub fn parse(payload: &[u8]) -> AppResult<Self> {
+        let fields = tlv(payload)?;
+        Ok(Self {
+            versions: fields.get(&TLV_VERSIONS).ok_or_else(malformed)?.to_vec(),
+            features: features(&fields),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ack {
+    /// `None` if no version is supported by both sides.
+    pub version: Option<u8>,
+    pub features: Features,
+}
+
+impl Ack {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if let Some(version) = self.version {
+            out.extend_from_slice(&[TLV_AGREED, 1, version]);
+        }
+        out.extend_from_slice(&[TLV_FEATURES, 1, self.features.0]);
+        out
+    }
+
+    pub fn parse(payload: &[u8]) -> AppResult<Self> {
+        let fields = tlv(payload)?;
+        let version = match fields.get(&TLV_AGREED).copied() {
+            Some(&[version]) => Some(version),
+            Some(_) => return Err(malformed()),
+            None => None,
+        };
+        Ok(Self {
+            version,
+            features: features(&fields),
+        })
+    }
+}
+
+fn malformed() -> AppError {
+    AppError::new(ErrorCode::MalformedPacket).with("reason", "version")
+}
+
+fn tlv(mut payload: &[u8]) -> AppResult<HashMap<u8, &[u8]>> {
+    let mut fields = HashMap::new();
+    while !payload.is_empty() {
+        let [kind, len, rest @ ..] = payload else {
+            return Err(malformed());
+        };
+        let value = rest.get(..*len as usize).ok_or_else(malformed)?;
+        fields.insert(*kind, value);
+        payload = &rest[*len as usize..];
+    }
+    Ok(fields)
+}
+
+fn features(fields: &HashMap<u8, &[u8]>) -> Features {
+    match fields.get(&TLV_FEATURES).copied() {
+        Some(&[bits]) => Features(bits),
+        _ => Features::NONE,
+    }
+}
+
+/// Answer `hello`: the highest version we both support, and the features
+/// we both have at that version.
+pub fn answer(hello: &Hello) -> Ack {
+    let version = SUPPORTED_VERSIONS
+        .iter()
+        .rev()
+        .find(|v| hello.versions.contains(v))
+        .copied();
+    Ack {
+        version,
+        features: version.map_or(Features::NONE, |v| {
+            Features::of_version(v).intersect(hello.features)
+        }),
+    }
+}
+
+struct Negotiated {
+    peer_id: PeerId,
+    version: Option<u8>,
+    features: Features,
+}
+
+/// Negotiation results per link.
+#[derive(Default)]
+pub struct Versions {
+    links: HashMap<String, Negotiated>,
+}
+
+impl Versions {
+    /// Record the outcome of a hello or ack exchanged with `peer_id` on
+    /// `link_id`.
+    pub fn agreed(&mut self, link_id: &str, peer_id: PeerId, ack: &Ack) {
+        let features = ack.version.map_or(Features::NONE, |v| {
+            Features::of_version(v).intersect(ack.features)
+        });
+        self.links.insert(
+            link_id.to_string(),
+            Negotiated {
+                peer_id,
+                version: ack.version,
+                features,
+            },
+        );
+    }
+
+    pub fn link_down(&mut self, link_id: &str) {
+        self.links.remove(link_id);
+    }
+
+    /// The version negotiated with `peer_id`, if it's a direct neighbor
+    /// that has negotiated.
+    pub fn version(&self, peer_id: &PeerId) -> Option<u8> {
+        self.links
+            .values()
+            .filter(|n| n.peer_id == *peer_id)
+            .find_map(|n| n.version)
+    }
+
+    /// Features usable on `link_id`, or on every link if `None`. Links
+    /// with no common version can't read our frames anyway, so they don't
+    /// hold the others back.
+    pub fn features(&self, link_id: Option<&str>) -> Features {
+        match link_id {
+            Some(link_id) => self
+                .links
+                .get(link_id)
+                .map_or(Features::ALL, |n| n.features),
+            None => self
+                .links
+                .values()
+                .filter(|n| n.version.is_some())
+                .fold(Features::ALL, |all, n| all.intersect(n.features)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_highest_common_version() {
+        let hello = Hello {
+            versions: vec![0, VERSION, VERSION + 1],
+            features: Features::COMPRESSION,
+        };
+        assert_eq!(Hello::parse(&hello.encode()).unwrap(), hello);
+        let ack = answer(&hello);
+        assert_eq!(ack.version, Some(VERSION));
+        assert_eq!(ack.features, Features::COMPRESSION);
+        assert_eq!(Ack::parse(&ack.encode()).unwrap(), ack);
+
+        let rejected = answer(&Hello {
+            versions: vec![VERSION + 1],
+            features: Features::ALL,
+        });
+        assert_eq!(rejected.version, None);
+        assert_eq!(Ack::parse(&rejected.encode()).unwrap(), rejected);
+        assert!(Hello::parse(&[TLV_VERSIONS, 5, 1]).is_err());
+    }
+
+    #[test]
+    fn broadcasts_use_features_every_link_has() {
+        let mut versions = Versions::default();
+        assert_eq!(versions.features(None), Features::ALL);
+        versions.agreed("a", PeerId([1; 8]), &answer(&Hello::ours()));
+        versions.agreed(
+            "b",
+            PeerId([2; 8]),
+            &Ack {
+                version: Some(VERSION),
+                features: Features::COMPRESSION,
+            },
+        );
+        assert_eq!(versions.features(Some("a")), Features::ALL);
+        assert_eq!(versions.features(Some("c")), Features::ALL);
+        assert_eq!(versions.features(None), Features::COMPRESSION);
+        assert_eq!(versions.version(&PeerId([2; 8])), Some(VERSION));
+
+        versions.link_down("b");
+        assert_eq!(versions.features(None), Features::ALL);
+    }
+}
@@ -0,0 +1,7 @@
+//! Nostr helpers that run in the backend.
+//!
+//! Relay connections and event handling still live in the frontend
+//! (`src/transport/nostr`); this module holds the pieces that are easier or
+//! safer to do natively.
+
+pub mod relay_info;
@@ -0,0 +1,171 @@
+//! NIP-11 relay information documents.
+//!
+//! Fetched over HTTP(S) from the relay URL with the
+//! `Accept: application/nostr+json` header and cached for an hour, so the
+//! relay manager can check supported NIPs and limits before a relay is added.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::State;
+
+use crate::error::{AppError, AppResult, ErrorCode};
+
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// NIP-16 predates ephemeral events being folded into NIP-01.
+const NIP_EPHEMERAL: u32 = 16;
+const NIP_GIFT_WRAP: u32 = 59;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct RelayLimitation {
+    pub max_message_length: Option<u64>,
+    pub max_subscriptions: Option<u64>,
+    pub max_filters: Option<u64>,
+    pub max_limit: Option<u64>,
+    pub max_subid_length: Option<u64>,
+    pub max_event_tags: Option<u64>,
+    pub max_content_length: Option<u64>,
+    pub min_pow_difficulty: Option<u32>,
+    pub auth_required: Option<bool>,
+    pub payment_required: Option<bool>,
+    pub restricted_writes: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct RelayInformation {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub pubkey: Option<String>,
+    pub contact: Option<String>,
+    pub supported_nips: Vec<u32>,
+    pub software: Option<String>,
+    pub version: Option<String>,
+    pub limitation: Option<RelayLimitation>,
+    pub payments_url: Option<String>,
+}
+
+impl RelayInformation {
+    pub fn supports(&self, nip: u32) -> bool {
+        self.supported_nips.contains(&nip)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct RelayDocument {
+    pub url: String,
+    pub info: RelayInformation,
+    pub supports_ephemeral: bool,
+    pub supports_gift_wrap: bool,
+    pub payment_required: bool,
+    /// Unix seconds.
+    pub fetched_at: u64,
+}
+
+#[derive(Default)]
+pub struct RelayInfoCache(Mutex<HashMap<String, (Instant, RelayDocument)>>);
+
+impl RelayInfoCache {
+    fn get(&self, url: &str) -> Option<RelayDocument> {
+        let cache = self.0.lock().unwrap();
+        cache
+            .get(url)
+            .filter(|(at, _)| at.elapsed() < CACHE_TTL)
+            .map(|(_, doc)| doc.clone())
+    }
+
+    fn insert(&self, doc: RelayDocument) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(doc.url.clone(), (Instant::now(), doc));
+    }
+}
+
+/// Map a relay websocket URL to the HTTP URL serving its NIP-11 document.
+fn http_url(relay_url: &str) -> AppResult<String> {
+    if let Some(rest) = relay_url.strip_prefix("wss://") {
+        Ok(format!("https://{}", rest))
+    } else if let Some(rest) = relay_url.strip_prefix("ws://") {
+        Ok(format!("http://{}", rest))
+    } else {
+        Err(AppError::new(ErrorCode::InvalidRelayUrl).with("url", relay_url))
+    }
+}
+
+pub async fn fetch(relay_url: &str) -> AppResult<RelayDocument> {
+    let client = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .map_err(AppError::platform)?;
+    let unreachable = |e: reqwest::Error| {
+        let code = if e.is_timeout() {
+            ErrorCode::RelayTimeout
+        } else {
+            ErrorCode::RelayUnreachable
+        };
+        AppError::new(code)
+            .with("url", relay_url)
+            .with("message", e)
+    };
+
+    let info: RelayInformation = client
+        .get(http_url(relay_url)?)
+        .header(reqwest::header::ACCEPT, "application/nostr+json")
+        .send()
+        .await
+        .map_err(unreachable)?
+        .error_for_status()
+        .map_err(unreachable)?
+        .json()
+        .await
+        .map_err(|e| {
+            AppError::new(ErrorCode::RelayInvalidResponse)
+                .with("url", relay_url)
+                .with("message", e)
+        })?;
+
+    let payment_required = info
+        .limitation
+        .as_ref()
+        .and_then(|l| l.payment_required)
+        .unwrap_or(false);
+    let fetched_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    Ok(RelayDocument {
+        url: relay_url.to_string(),
+        supports_ephemeral: info.supports(NIP_EPHEMERAL),
+        supports_gift_wrap: info.supports(NIP_GIFT_WRAP),
+        payment_required,
+        fetched_at,
+        info,
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn nostr_get_relay_info(
+    url: String,
+    cache: State<'_, RelayInfoCache>,
+) -> AppResult<RelayDocument> {
+    let url = url.trim_end_matches('/').to_string();
+    if let Some(doc) = cache.get(&url) {
+        return Ok(doc);
+    }
+
+    let doc = fetch(&url).await.map_err(|e| {
+        tracing::warn!(relay = %url, "{}", e);
+        e
+    })?;
+    cache.insert(doc.clone());
+    Ok(doc)
+}
@@ -0,0 +1,250 @@
+//! Native OS notifications for incoming messages.
+//!
+//! The frontend reports each incoming DM or mention through
+//! `notifications_notify`; whether it actually reaches the OS is decided
+//! here, against per-conversation mutes and the quiet-hours schedule from
+//! the notification settings.
+
+use std::collections::HashSet;
+
+use chrono::{Local, Timelike};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{AppHandle, Manager, State};
+use tauri_plugin_notification::NotificationExt;
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::settings::SettingsState;
+use crate::storage::{self, StorageState};
+use crate::windows;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    DirectMessage,
+    Mention,
+}
+
+/// Daily window, in minutes after local midnight, during which nothing is
+/// shown. `start > end` wraps past midnight (e.g. 22:00 to 07:00).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type)]
+pub struct QuietHours {
+    pub start_minute: u16,
+    pub end_minute: u16,
+}
+
+impl QuietHours {
+    pub fn contains(&self, minute: u16) -> bool {
+        if self.start_minute <= self.end_minute {
+            minute >= self.start_minute && minute < self.end_minute
+        } else {
+            minute >= self.start_minute || minute < self.end_minute
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct NotificationPrefs {
+    pub enabled: bool,
+    pub direct_messages: bool,
+    pub mentions: bool,
+    pub muted_conversations: HashSet<String>,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+impl Default for NotificationPrefs {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            direct_messages: true,
+            mentions: true,
+            muted_conversations: HashSet::new(),
+            quiet_hours: None,
+        }
+    }
+}
+
+impl NotificationPrefs {
+    pub fn validate(&self) -> AppResult<()> {
+        if let Some(q) = self.quiet_hours {
+            if q.start_minute >= 24 * 60 || q.end_minute >= 24 * 60 {
+                return Err(AppError::new(ErrorCode::InvalidSettings).with("field", "quiet_hours"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn allows(&self, conversation_id: &str, kind: NotificationKind) -> bool {
+        if !self.enabled || self.muted_conversations.contains(conversation_id) {
+            return false;
+        }
+        let kind_enabled = match kind {
+            NotificationKind::DirectMessage => self.direct_messages,
+            NotificationKind::Mention => self.mentions,
+        };
+        if !kind_enabled {
+            return false;
+        }
+
+        let now = Local::now();
+        let minute = (now.hour() * 60 + now.minute()) as u16;
+        !self.quiet_hours.is_some_and(|q| q.contains(minute))
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn notifications_get_prefs(settings: State<'_, SettingsState>) -> NotificationPrefs {
+    settings.get().notifications
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn notifications_set_prefs(
+    app: AppHandle,
+    prefs: NotificationPrefs,
+    settings: State<'_, SettingsState>,
+) -> AppResult<()> {
+    settings.update(&app, |s| s.notifications = prefs)?;
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn notifications_mute_conversation(
+    app: AppHandle,
+    conversation_id: String,
+    muted: bool,
+    settings: State<'_, SettingsState>,
+) -> AppResult<()> {
+    settings.update(&app, |s| {
+        let muted_conversations = &mut s.notifications.muted_conversations;
+        if muted {
+            muted_conversations.insert(conversation_id);
+        } else {
+            muted_conversations.remove(&conversation_id);
+        }
+    })?;
+    Ok(())
+}
+
+/// Show a notification unless prefs suppress it or the window showing the
+/// conversation already has focus. Returns whether one was shown.
+#[tauri::command]
+#[specta::specta]
+pub fn notifications_notify(
+    app: AppHandle,
+    conversation_id: String,
+    kind: NotificationKind,
+    title: String,
+    body: String,
+    settings: State<'_, SettingsState>,
+) -> AppResult<bool> {
+    if !settings.get().notifications.allows(&conversation_id, kind) {
+        return Ok(false);
+    }
+    let focused = app
+        .get_webview_window(&windows::label_for_conversation(&app, &conversation_id))
+        .and_then(|w| w.is_focused().ok())
+        .unwrap_or(false);
+    if focused {
+        return Ok(false);
+    }
+
+    app.notification()
+        .builder()
+        .title(title)
+        .body(body)
+        .show()
+        .map_err(AppError::platform)?;
+    Ok(true)
+}
+
+/// Show `unread` on the macOS dock / Linux launcher badge, or as a taskbar
+/// overlay dot on Windows. Zero clears it.
+pub fn set_badge(app: &AppHandle, unread: u32) {
+    let Some(window) = app.get_webview_window(windows::MAIN_WINDOW) else {
+        return;
+    };
+
+    #[cfg(target_os = "windows")]
+    let result = window.set_overlay_icon((unread > 0).then(unread_overlay));
+    #[cfg(not(target_os = "windows"))]
+    let result = window.set_badge_count((unread > 0).then_some(unread as i64));
+
+    if let Err(e) = result {
+        tracing::debug!("failed to update unread badge: {}", e);
+    }
+}
+
+/// Windows overlay icons can't carry a number, so draw a plain red dot.
+#[cfg(target_os = "windows")]
+fn unread_overlay() -> tauri::image::Image<'static> {
+    const SIZE: u32 = 16;
+    let center = (SIZE as f32 - 1.0) / 2.0;
+    let mut rgba = Vec::with_capacity((SIZE * SIZE * 4) as usize);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let (dx, dy) = (x as f32 - center, y as f32 - center);
+            let inside = dx * dx + dy * dy <= center * center;
+            rgba.extend_from_slice(if inside {
+                &[0xE5, 0x3E, 0x3E, 0xFF]
+            } else {
+                &[0, 0, 0, 0]
+            });
+        }
+    }
+    tauri::image::Image::new_owned(rgba, SIZE, SIZE)
+}
+
+/// Mark every message read, which clears the badge.
+#[tauri::command]
+#[specta::specta]
+pub fn notifications_clear_badge(app: AppHandle, state: State<'_, StorageState>) -> AppResult<()> {
+    state.with(|store| store.mark_read(None))?;
+    storage::refresh_badge(&app);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiet(start: u16, end: u16) -> QuietHours {
+        QuietHours {
+            start_minute: start,
+            end_minute: end,
+        }
+    }
+
+    #[test]
+    fn same_day_window() {
+        let hours = quiet(13 * 60, 14 * 60);
+        assert!(!hours.contains(13 * 60 - 1));
+        assert!(hours.contains(13 * 60));
+        assert!(hours.contains(14 * 60 - 1));
+        assert!(!hours.contains(14 * 60));
+        assert!(!hours.contains(0));
+    }
+
+    #[test]
+    fn overnight_window_wraps_past_midnight() {
+        let hours = quiet(22 * 60, 7 * 60);
+        assert!(!hours.contains(22 * 60 - 1));
+        assert!(hours.contains(22 * 60));
+        assert!(hours.contains(24 * 60 - 1));
+        assert!(hours.contains(0));
+        assert!(hours.contains(7 * 60 - 1));
+        assert!(!hours.contains(7 * 60));
+        assert!(!hours.contains(12 * 60));
+    }
+
+    #[test]
+    fn equal_start_and_end_is_never_quiet() {
+        let hours = quiet(8 * 60, 8 * 60);
+        for minute in [0, 8 * 60 - 1, 8 * 60, 8 * 60 + 1, 24 * 60 - 1] {
+            assert!(!hours.contains(minute), "{}", minute);
+        }
+    }
+}
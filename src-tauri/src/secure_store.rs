@@ -0,0 +1,59 @@
+//! Secret storage backed by the OS keyring (Keychain, Credential Manager,
+//! Secret Service).
+//!
+//! Gives the frontend somewhere other than localStorage to keep the nsec,
+//! pinned keys and similar values.
+
+use keyring::Entry;
+
+use crate::error::{AppError, AppResult, ErrorCode};
+
+const SERVICE: &str = "com.bitchat.app";
+
+fn unavailable(err: keyring::Error) -> AppError {
+    AppError::new(ErrorCode::SecureStoreUnavailable).with("message", err)
+}
+
+fn entry(key: &str) -> AppResult<Entry> {
+    if key.is_empty() {
+        return Err(AppError::new(ErrorCode::InvalidArgument).with("field", "key"));
+    }
+    Entry::new(SERVICE, key).map_err(unavailable)
+}
+
+pub fn set(key: &str, value: &str) -> AppResult<()> {
+    entry(key)?.set_password(value).map_err(unavailable)
+}
+
+pub fn get(key: &str) -> AppResult<Option<String>> {
+    match entry(key)?.get_password() {
+        Ok(value) => Ok(Some(value)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(unavailable(e)),
+    }
+}
+
+pub fn delete(key: &str) -> AppResult<()> {
+    match entry(key)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(unavailable(e)),
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn secure_store_set(key: String, value: String) -> AppResult<()> {
+    set(&key, &value)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn secure_store_get(key: String) -> AppResult<Option<String>> {
+    get(&key)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn secure_store_delete(key: String) -> AppResult<()> {
+    delete(&key)
+}
@@ -0,0 +1,427 @@
+//! Persistent application settings.
+//!
+//! Stored as `settings.json` in the app data directory and loaded once at
+//! startup. Backend subsystems read the current value through
+//! [`SettingsState::get`]; every change is persisted and announced to the
+//! webview as a [`SettingsEvent::Changed`].
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{AppHandle, State};
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events::{self, BackendEvent};
+use crate::geo;
+use crate::geoprivacy::GeoPrivacyConfig;
+use crate::notifications::NotificationPrefs;
+use crate::shortcuts;
+use crate::transport::scan::PowerMode;
+
+pub const FILE_NAME: &str = "settings.json";
+
+const DEFAULT_RELAYS: &[&str] = &[
+    "wss://relay.damus.io",
+    "wss://nos.lol",
+    "wss://relay.nostr.band",
+    "wss://nostr.wine",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct RelayConfig {
+    pub url: String,
+    pub read: bool,
+    pub write: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct CryptoPolicy {
+    /// Only treat a peer as trusted once its fingerprint has been verified.
+    pub require_verified_peers: bool,
+    /// Start a new handshake after this many messages on one session.
+    pub rekey_after_messages: u64,
+}
+
+impl Default for CryptoPolicy {
+    fn default() -> Self {
+        Self {
+            require_verified_peers: false,
+            rekey_after_messages: 10_000,
+        }
+    }
+}
+
+/// Longest send delay [`TrafficSettings::max_send_jitter_ms`] may ask for.
+const MAX_SEND_JITTER_MS: u64 = 5_000;
+
+/// How mesh frames are shaped to resist traffic analysis over the air.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct TrafficSettings {
+    /// Pad frames to standard sizes.
+    pub pad_packets: bool,
+    /// Hold each outgoing frame back a random 0..=this many milliseconds.
+    pub max_send_jitter_ms: u64,
+}
+
+impl Default for TrafficSettings {
+    fn default() -> Self {
+        Self {
+            pad_packets: true,
+            max_send_jitter_ms: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct PrivacySettings {
+    pub location: GeoPrivacyConfig,
+    pub traffic: TrafficSettings,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct AppSettings {
+    /// Keep running in the tray when the main window is closed.
+    pub background_mode: bool,
+    pub launch_at_login: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct ShortcutSettings {
+    /// Global accelerator for the quick compose window; `None` disables it.
+    pub quick_compose: Option<String>,
+}
+
+impl Default for ShortcutSettings {
+    fn default() -> Self {
+        Self {
+            quick_compose: Some(shortcuts::DEFAULT_QUICK_COMPOSE.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct DeliverySettings {
+    /// Give up on messages that haven't gone out after this long.
+    pub expire_after_secs: u64,
+}
+
+impl Default for DeliverySettings {
+    fn default() -> Self {
+        Self {
+            expire_after_secs: 60 * 60,
+        }
+    }
+}
+
+/// Mesh-to-Nostr bridging; see [`crate::mesh::bridge`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct BridgeSettings {
+    pub enabled: bool,
+    /// Geohash channel to bridge the mesh with.
+    pub geohash: Option<String>,
+}
+
+/// Bluetooth radio use; see [`crate::transport::scan`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct BleSettings {
+    pub power_mode: PowerMode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct Settings {
+    pub app: AppSettings,
+    pub relays: Vec<RelayConfig>,
+    pub crypto: CryptoPolicy,
+    pub privacy: PrivacySettings,
+    pub notifications: NotificationPrefs,
+    pub shortcuts: ShortcutSettings,
+    pub delivery: DeliverySettings,
+    pub bridge: BridgeSettings,
+    pub ble: BleSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            app: AppSettings::default(),
+            relays: DEFAULT_RELAYS
+                .iter()
+                .map(|url| RelayConfig {
+                    url: url.to_string(),
+                    read: true,
+                    write: true,
+                })
+                .collect(),
+            crypto: CryptoPolicy::default(),
+            privacy: PrivacySettings::default(),
+            notifications: NotificationPrefs::default(),
+            shortcuts: ShortcutSettings::default(),
+            delivery: DeliverySettings::default(),
+            bridge: BridgeSettings::default(),
+            ble: BleSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> AppResult<()> {
+        for relay in &self.relays {
+            if !relay.url.starts_with("wss://") && !relay.url.starts_with("ws://") {
+                return Err(AppError::new(ErrorCode::InvalidRelayUrl).with("url", &relay.url));
+            }
+        }
+        if self.crypto.rekey_after_messages == 0 {
+            return Err(AppError::new(ErrorCode::InvalidSettings)
+                .with("field", "crypto.rekey_after_messages"));
+        }
+        if self.delivery.expire_after_secs == 0 {
+            return Err(AppError::new(ErrorCode::InvalidSettings)
+                .with("field", "delivery.expire_after_secs"));
+        }
+        if self.privacy.traffic.max_send_jitter_ms > MAX_SEND_JITTER_MS {
+            return Err(AppError::new(ErrorCode::InvalidSettings)
+                .with("field", "privacy.traffic.max_send_jitter_ms"));
+        }
+        if self.bridge.enabled && self.bridge.geohash.is_none() {
+            return Err(AppError::new(ErrorCode::InvalidSettings).with("field", "bridge.geohash"));
+        }
+        if let Some(hash) = &self.bridge.geohash {
+            geo::decode(hash)?;
+        }
+        if let Some(accelerator) = &self.shortcuts.quick_compose {
+            shortcuts::parse(accelerator)?;
+        }
+        self.privacy.location.validate()?;
+        self.notifications.validate()
+    }
+
+    /// Fail if `self` changes a field that only its own command may change
+    /// from `current`, naming the field and the command.
+    fn check_owned_fields(&self, current: &Settings) -> AppResult<()> {
+        let owned = |field: &str, command: &str| {
+            AppError::new(ErrorCode::InvalidSettings)
+                .with("field", field)
+                .with("command", command)
+        };
+        if self.shortcuts.quick_compose != current.shortcuts.quick_compose {
+            return Err(owned(
+                "shortcuts.quick_compose",
+                "shortcut_set_quick_compose",
+            ));
+        }
+        if self.app.launch_at_login != current.app.launch_at_login {
+            return Err(owned("app.launch_at_login", "app_set_launch_at_login"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SettingsEvent {
+    Changed { settings: Settings },
+}
+
+impl From<SettingsEvent> for BackendEvent {
+    fn from(event: SettingsEvent) -> Self {
+        BackendEvent::Settings(event)
+    }
+}
+
+pub struct SettingsState {
+    path: PathBuf,
+    settings: RwLock<Settings>,
+    /// Serializes read-modify-write cycles so concurrent updates don't drop
+    /// each other's changes.
+    write_lock: Mutex<()>,
+}
+
+impl SettingsState {
+    /// Load settings from `path`, falling back to defaults if the file is
+    /// missing or unreadable.
+    pub fn load(path: PathBuf) -> Self {
+        let settings = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str::<Settings>(&json)
+                .map_err(|e| AppError::new(ErrorCode::InvalidSettings).with("message", e))
+                .and_then(|s| s.validate().map(|_| s))
+                .unwrap_or_else(|e| {
+                    tracing::warn!("ignoring invalid settings file: {}", e);
+                    Settings::default()
+                }),
+            Err(_) => Settings::default(),
+        };
+        Self {
+            path,
+            settings: RwLock::new(settings),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.read().unwrap().clone()
+    }
+
+    /// Apply `f` to a copy of the current settings, then validate, persist
+    /// and publish the result.
+    pub fn update(&self, app: &AppHandle, f: impl FnOnce(&mut Settings)) -> AppResult<Settings> {
+        let _guard = self.write_lock.lock().unwrap();
+        let mut next = self.get();
+        f(&mut next);
+        next.validate()?;
+        self.save(&next)?;
+        *self.settings.write().unwrap() = next.clone();
+        events::emit(
+            app,
+            SettingsEvent::Changed {
+                settings: next.clone(),
+            },
+        );
+        Ok(next)
+    }
+
+    fn save(&self, settings: &Settings) -> AppResult<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(settings)
+            .map_err(|e| AppError::new(ErrorCode::InvalidSettings).with("message", e))?;
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        Ok(fs::rename(&tmp, &self.path)?)
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn settings_get(state: State<'_, SettingsState>) -> Settings {
+    state.get()
+}
+
+/// Replace the settings. Fields whose change needs more than saving (the
+/// OS shortcut and login item) have their own commands and can't be
+/// changed here.
+#[tauri::command]
+#[specta::specta]
+pub fn settings_set(
+    app: AppHandle,
+    settings: Settings,
+    state: State<'_, SettingsState>,
+) -> AppResult<Settings> {
+    settings.check_owned_fields(&state.get())?;
+    state.update(&app, |s| {
+        // Keep them even if their commands ran since `current` was read.
+        let shortcuts = s.shortcuts.clone();
+        let launch_at_login = s.app.launch_at_login;
+        *s = settings;
+        s.shortcuts = shortcuts;
+        s.app.launch_at_login = launch_at_login;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::QuietHours;
+
+    fn invalid_field(settings: &Settings) -> Option<String> {
+        let error = settings.validate().err()?;
+        assert_eq!(error.code, ErrorCode::InvalidSettings);
+        error.params.get("field").cloned()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(Settings::default().validate().is_ok());
+    }
+
+    #[test]
+    fn validates_bounds() {
+        let mut settings = Settings::default();
+        settings.crypto.rekey_after_messages = 0;
+        assert_eq!(
+            invalid_field(&settings).as_deref(),
+            Some("crypto.rekey_after_messages")
+        );
+
+        let mut settings = Settings::default();
+        settings.delivery.expire_after_secs = 0;
+        assert_eq!(
+            invalid_field(&settings).as_deref(),
+            Some("delivery.expire_after_secs")
+        );
+
+        let mut settings = Settings::default();
+        settings.privacy.traffic.max_send_jitter_ms = MAX_SEND_JITTER_MS;
+        assert!(settings.validate().is_ok());
+        settings.privacy.traffic.max_send_jitter_ms = MAX_SEND_JITTER_MS + 1;
+        assert_eq!(
+            invalid_field(&settings).as_deref(),
+            Some("privacy.traffic.max_send_jitter_ms")
+        );
+
+        let mut settings = Settings::default();
+        settings.bridge.enabled = true;
+        assert_eq!(invalid_field(&settings).as_deref(), Some("bridge.geohash"));
+        settings.bridge.geohash = Some("u4pru".to_string());
+        assert!(settings.validate().is_ok());
+        settings.bridge.geohash = Some("not a geohash".to_string());
+        assert_eq!(
+            settings.validate().unwrap_err().code,
+            ErrorCode::InvalidGeohash
+        );
+
+        let mut settings = Settings::default();
+        settings.relays[0].url = "https://relay.damus.io".to_string();
+        assert_eq!(
+            settings.validate().unwrap_err().code,
+            ErrorCode::InvalidRelayUrl
+        );
+
+        let mut settings = Settings::default();
+        settings.privacy.location.max_precision = 0;
+        assert_eq!(
+            settings.validate().unwrap_err().code,
+            ErrorCode::InvalidPrecision
+        );
+
+        let mut settings = Settings::default();
+        settings.notifications.quiet_hours = Some(QuietHours {
+            start_minute: 22 * 60,
+            end_minute: 24 * 60,
+        });
+        assert_eq!(invalid_field(&settings).as_deref(), Some("quiet_hours"));
+    }
+
+    #[test]
+    fn owned_fields_need_their_own_commands() {
+        let current = Settings::default();
+        let mut settings = current.clone();
+        settings.relays.pop();
+        assert!(settings.check_owned_fields(&current).is_ok());
+
+        let mut settings = current.clone();
+        settings.shortcuts.quick_compose = None;
+        let error = settings.check_owned_fields(&current).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidSettings);
+        assert_eq!(error.params["field"], "shortcuts.quick_compose");
+        assert_eq!(error.params["command"], "shortcut_set_quick_compose");
+
+        let mut settings = current.clone();
+        settings.app.launch_at_login = !current.app.launch_at_login;
+        let error = settings.check_owned_fields(&current).unwrap_err();
+        assert_eq!(error.params["field"], "app.launch_at_login");
+        assert_eq!(error.params["command"], "app_set_launch_at_login");
+    }
+}
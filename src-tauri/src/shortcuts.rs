@@ -0,0 +1,107 @@
+//! Global quick-compose shortcut.
+//!
+//! Pressing the configured accelerator anywhere in the OS opens a small
+//! always-on-top compose window. What it submits is handed to the window
+//! showing that conversation as an [`AppEvent::ComposeSubmitted`] so it goes
+//! through the normal send path.
+
+use tauri::plugin::TauriPlugin;
+use tauri::{AppHandle, Manager, State, WebviewUrl, WebviewWindowBuilder, Wry};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events::{self, AppEvent};
+use crate::settings::SettingsState;
+use crate::windows;
+
+pub const COMPOSE_WINDOW: &str = "compose";
+pub const DEFAULT_QUICK_COMPOSE: &str = "CommandOrControl+Shift+Space";
+
+pub fn plugin() -> TauriPlugin<Wry> {
+    tauri_plugin_global_shortcut::Builder::new()
+        .with_handler(|app, _shortcut, event| {
+            if event.state() == ShortcutState::Pressed {
+                if let Err(e) = open_compose_window(app) {
+                    tracing::warn!("failed to open compose window: {}", e);
+                }
+            }
+        })
+        .build()
+}
+
+pub fn parse(accelerator: &str) -> AppResult<Shortcut> {
+    accelerator.parse::<Shortcut>().map_err(|e| {
+        AppError::new(ErrorCode::InvalidShortcut)
+            .with("shortcut", accelerator)
+            .with("message", e)
+    })
+}
+
+fn register(app: &AppHandle, accelerator: Option<&str>) -> AppResult<()> {
+    let shortcuts = app.global_shortcut();
+    shortcuts.unregister_all().map_err(AppError::platform)?;
+    if let Some(accelerator) = accelerator {
+        shortcuts
+            .register(parse(accelerator)?)
+            .map_err(AppError::platform)?;
+    }
+    Ok(())
+}
+
+/// Register the shortcut from the saved settings. Called once at startup.
+pub fn register_from_settings(app: &AppHandle) {
+    let accelerator = app.state::<SettingsState>().get().shortcuts.quick_compose;
+    if let Err(e) = register(app, accelerator.as_deref()) {
+        tracing::warn!("failed to register quick compose shortcut: {}", e);
+    }
+}
+
+pub fn open_compose_window(app: &AppHandle) -> tauri::Result<()> {
+    if let Some(window) = app.get_webview_window(COMPOSE_WINDOW) {
+        window.show()?;
+        return window.set_focus();
+    }
+
+    WebviewWindowBuilder::new(app, COMPOSE_WINDOW, WebviewUrl::App("compose".into()))
+        .title("Quick compose")
+        .inner_size(420.0, 180.0)
+        .resizable(false)
+        .always_on_top(true)
+        .center()
+        .build()?;
+    Ok(())
+}
+
+/// Change (or with `None`, disable) the quick compose shortcut.
+#[tauri::command]
+#[specta::specta]
+pub fn shortcut_set_quick_compose(
+    app: AppHandle,
+    accelerator: Option<String>,
+    settings: State<'_, SettingsState>,
+) -> AppResult<()> {
+    let previous = settings.get().shortcuts.quick_compose;
+    // Only save a shortcut the OS accepted; otherwise put the old one back.
+    let result = register(&app, accelerator.as_deref())
+        .and_then(|()| settings.update(&app, |s| s.shortcuts.quick_compose = accelerator.clone()));
+    if let Err(e) = result {
+        if let Err(restore) = register(&app, previous.as_deref()) {
+            tracing::warn!("failed to restore quick compose shortcut: {}", restore);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Called by the compose window; forwards the message to the window showing
+/// `target` (or the main window) and closes the compose window.
+#[tauri::command]
+#[specta::specta]
+pub fn compose_submit(app: AppHandle, target: String, content: String) -> AppResult<()> {
+    let label = windows::label_for_conversation(&app, &target);
+    events::emit_to(&app, &label, AppEvent::ComposeSubmitted { target, content });
+    if let Some(window) = app.get_webview_window(COMPOSE_WINDOW) {
+        window.close().map_err(AppError::platform)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,641 @@
+//! Contacts: the people we know, by Noise fingerprint.
+//!
+//! A contact ties together what we know about one person across
+//! transports: their Noise static key (and its fingerprint, the SHA-256 of
+//! the key, as shown in the native apps), their Nostr key, the nickname
+//! they last announced on the mesh and the name in their Nostr profile.
+//! The user adds their own petname, notes and trust level on top.
+//!
+//! Contacts are created by the user; announces, favorite notifications and
+//! kind 0 profiles then keep the fields they carry up to date. Those
+//! automatic changes are published as [`ContactsEvent::Changed`]. A Nostr
+//! key from the network only fills in a missing one, though: it never
+//! replaces a key the user set or verified.
+//!
+//! Nostr keys are kept as npubs, like in [`crate::favorites`]; the
+//! frontend, which talks to relays, converts profile keys before handing
+//! them over with [`contacts_apply_profile`].
+
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use specta::Type;
+use tauri::{AppHandle, Manager, State};
+
+use super::{StorageState, Store};
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events::{self, BackendEvent};
+use crate::favorites::{self, FavoritesState};
+use crate::mesh::peers::PeerInfo;
+use crate::mesh::{self, now_ms};
+
+pub(super) const SCHEMA: &str = "
+CREATE TABLE contacts (
+    fingerprint TEXT PRIMARY KEY,
+    noise_public_key TEXT NOT NULL,
+    nostr_pubkey TEXT,
+    petname TEXT,
+    nickname TEXT,
+    profile_name TEXT,
+    nip05 TEXT,
+    notes TEXT NOT NULL DEFAULT '',
+    favorite INTEGER NOT NULL DEFAULT 0,
+    trust TEXT NOT NULL DEFAULT 'unknown',
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE INDEX contacts_by_nostr_pubkey ON contacts (nostr_pubkey);
+";
+
+/// How far the user trusts a contact to be who they say.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustLevel {
+    #[default]
+    Unknown,
+    Casual,
+    Trusted,
+    /// The fingerprint was compared in person.
+    Verified,
+}
+
+impl TrustLevel {
+    fn as_str(self) -> &'static str {
+        match self {
+            TrustLevel::Unknown => "unknown",
+            TrustLevel::Casual => "casual",
+            TrustLevel::Trusted => "trusted",
+            TrustLevel::Verified => "verified",
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        match s {
+            "casual" => TrustLevel::Casual,
+            "trusted" => TrustLevel::Trusted,
+            "verified" => TrustLevel::Verified,
+            _ => TrustLevel::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct Contact {
+    /// Hex SHA-256 of the Noise static public key.
+    pub fingerprint: String,
+    /// Hex-encoded Noise static public key.
+    pub noise_public_key: String,
+    /// npub, once we've learned it.
+    pub nostr_pubkey: Option<String>,
+    /// The name the user gave them.
+    pub petname: Option<String>,
+    /// The nickname they last announced on the mesh.
+    pub nickname: Option<String>,
+    /// `display_name` (or `name`) from their Nostr profile.
+    pub profile_name: Option<String>,
+    pub nip05: Option<String>,
+    pub notes: String,
+    pub favorite: bool,
+    pub trust: TrustLevel,
+    /// Milliseconds since the epoch.
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// Changes to a contact's user-assigned fields. Missing fields are left
+/// alone; an empty string clears `petname` or `nostr_pubkey`.
+#[derive(Debug, Clone, Default, Deserialize, Type)]
+#[serde(default)]
+pub struct ContactUpdate {
+    pub petname: Option<String>,
+    pub nostr_pubkey: Option<String>,
+    pub notes: Option<String>,
+    pub favorite: Option<bool>,
+    pub trust: Option<TrustLevel>,
+}
+
+/// A kind 0 profile, with its author's key as an npub.
+#[derive(Debug, Clone, Deserialize, Type)]
+pub struct NostrProfile {
+    pub npub: String,
+    pub name: Option<String>,
+    pub display_name: Option<String>,
+    pub nip05: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContactsEvent {
+    /// `contact` was updated from the network.
+    Changed { contact: Contact },
+    /// The peer behind `contact` shared an npub other than the one on
+    /// record, which was kept. The user can switch with `contacts_update`.
+    NostrKeyConflict { contact: Contact, npub: String },
+}
+
+/// What [`Store::share_nostr_key`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SharedNostrKey {
+    /// The contact had no npub and now has this one.
+    Added(Contact),
+    /// The contact has a different npub, which was kept.
+    Conflict(Contact),
+    /// There's no such contact, or it already has this npub.
+    Unchanged,
+}
+
+impl From<ContactsEvent> for BackendEvent {
+    fn from(event: ContactsEvent) -> Self {
+        BackendEvent::Contacts(event)
+    }
+}
+
+/// The fingerprint of a hex Noise public key, or `None` if it isn't one.
+pub fn fingerprint(noise_public_key: &str) -> Option<String> {
+    let mut key = [0u8; 32];
+    hex::decode_to_slice(noise_public_key, &mut key).ok()?;
+    Some(hex::encode(Sha256::digest(key)))
+}
+
+fn contact_from_row(row: &Row<'_>) -> rusqlite::Result<Contact> {
+    Ok(Contact {
+        fingerprint: row.get("fingerprint")?,
+        noise_public_key: row.get("noise_public_key")?,
+        nostr_pubkey: row.get("nostr_pubkey")?,
+        petname: row.get("petname")?,
+        nickname: row.get("nickname")?,
+        profile_name: row.get("profile_name")?,
+        nip05: row.get("nip05")?,
+        notes: row.get("notes")?,
+        favorite: row.get("favorite")?,
+        trust: TrustLevel::parse(&row.get::<_, String>("trust")?),
+        created_at: row.get::<_, i64>("created_at")? as u64,
+        updated_at: row.get::<_, i64>("updated_at")? as u64,
+    })
+}
+
+impl Store {
+    /// Every contact, by fingerprint.
+    pub fn contacts(&self) -> rusqlite::Result<Vec<Contact>> {
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT * FROM contacts ORDER BY fingerprint")?;
+        let rows = statement.query_map([], contact_from_row)?;
+        rows.collect()
+    }
+
+    pub fn contact(&self, fingerprint: &str) -> rusqlite::Result<Option<Contact>> {
+        self.conn
+            .query_row(
+                "SELECT * FROM contacts WHERE fingerprint = ?1",
+                [fingerprint],
+                contact_from_row,
+            )
+            .optional()
+    }
+
+    pub fn contacts_with_nostr_pubkey(&self, npub: &str) -> rusqlite::Result<Vec<Contact>> {
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT * FROM contacts WHERE nostr_pubkey = ?1")?;
+        let rows = statement.query_map([npub], contact_from_row)?;
+        rows.collect()
+    }
+
+    /// Save `contact`, replacing any with the same fingerprint.
+    pub fn save_contact(&self, contact: &Contact) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO contacts
+                 (fingerprint, noise_public_key, nostr_pubkey, petname, nickname,
+                  profile_name, nip05, notes, favorite, trust, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                contact.fingerprint,
+                contact.noise_public_key,
+                contact.nostr_pubkey,
+                contact.petname,
+                contact.nickname,
+                contact.profile_name,
+                contact.nip05,
+                contact.notes,
+                contact.favorite,
+                contact.trust.as_str(),
+                contact.created_at as i64,
+                contact.updated_at as i64,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Returns whether there was such a contact.
+    pub fn delete_contact(&self, fingerprint: &str) -> rusqlite::Result<bool> {
+        Ok(self
+            .conn
+            .execute("DELETE FROM contacts WHERE fingerprint = ?1", [fingerprint])?
+            > 0)
+    }
+
+    /// Apply `f` to the contact with `fingerprint`, if there is one, and
+    /// save it if `f` returns true. Returns the saved contact.
+    pub fn enrich_contact(
+        &self,
+        fingerprint: &str,
+        f: impl FnOnce(&mut Contact) -> bool,
+    ) -> rusqlite::Result<Option<Contact>> {
+        let Some(mut contact) = self.contact(fingerprint)? else {
+            return Ok(None);
+        };
+        if !f(&mut contact) {
+            return Ok(None);
+        }
+        contact.updated_at = now_ms();
+        self.save_contact(&contact)?;
+        Ok(Some(contact))
+    }
+
+    /// Record an npub the peer behind `fingerprint` shared, if the contact
+    /// doesn't have one yet. A different one is never overwritten, since
+    /// the user may have set or verified it.
+    pub fn share_nostr_key(
+        &self,
+        fingerprint: &str,
+        npub: &str,
+    ) -> rusqlite::Result<SharedNostrKey> {
+        let mut conflict = None;
+        let added = self.enrich_contact(fingerprint, |contact| match &contact.nostr_pubkey {
+            None => {
+                contact.nostr_pubkey = Some(npub.to_string());
+                true
+            }
+            Some(known) if known == npub => false,
+            Some(_) => {
+                conflict = Some(contact.clone());
+                false
+            }
+        })?;
+        Ok(match (added, conflict) {
+            (Some(contact), _) => SharedNostrKey::Added(contact),
+            (None, Some(contact)) => SharedNostrKey::Conflict(contact),
+            (None, None) => SharedNostrKey::Unchanged,
+        })
+    }
+
+    /// Update the contacts using `profile`'s key with its name and NIP-05
+    /// address. Returns the contacts that changed.
+    pub fn apply_profile(&self, profile: &NostrProfile) -> rusqlite::Result<Vec<Contact>> {
+        let name = profile
+            .display_name
+            .clone()
+            .and_then(non_empty)
+            .or_else(|| profile.name.clone().and_then(non_empty));
+        let nip05 = profile.nip05.clone().and_then(non_empty);
+        let mut changed = Vec::new();
+        for mut contact in self.contacts_with_nostr_pubkey(&profile.npub)? {
+            let renamed = replace(&mut contact.profile_name, name.clone());
+            let reverified = replace(&mut contact.nip05, nip05.clone());
+            if renamed || reverified {
+                contact.updated_at = now_ms();
+                self.save_contact(&contact)?;
+                changed.push(contact);
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// Apply `f` to the contact with `fingerprint`, if there is one. Saves and
+/// publishes the result when `f` returns true.
+fn enrich(app: &AppHandle, fingerprint: &str, f: impl FnOnce(&mut Contact) -> bool) {
+    let result = app
+        .state::<StorageState>()
+        .with(|store| store.enrich_contact(fingerprint, f));
+    match result {
+        Ok(Some(contact)) => events::emit(app, ContactsEvent::Changed { contact }),
+        Ok(None) => {}
+        Err(e) => tracing::debug!("couldn't update contact {}: {}", fingerprint, e),
+    }
+}
+
+/// Set `field` to `value`, returning whether it changed.
+fn replace<T: PartialEq>(field: &mut T, value: T) -> bool {
+    if *field == value {
+        return false;
+    }
+    *field = value;
+    true
+}
+
+/// Pick up the nickname a contact has just announced.
+pub fn peer_discovered(app: &AppHandle, peer: &PeerInfo) {
+    let Some(fingerprint) = peer.noise_public_key.as_deref().and_then(fingerprint) else {
+        return;
+    };
+    enrich(app, &fingerprint, |contact| {
+        replace(&mut contact.nickname, Some(peer.nickname.clone()))
+    });
+}
+
+/// Record the npub a peer shared with a favorite notification, as
+/// [`Store::share_nostr_key`] does. A conflict with the one on record is
+/// reported as a [`ContactsEvent::NostrKeyConflict`].
+pub fn nostr_key_shared(app: &AppHandle, noise_public_key: &str, npub: &str) {
+    let Some(fingerprint) = fingerprint(noise_public_key) else {
+        return;
+    };
+    let result = app
+        .state::<StorageState>()
+        .with(|store| store.share_nostr_key(&fingerprint, npub));
+    match result {
+        Ok(SharedNostrKey::Added(contact)) => events::emit(app, ContactsEvent::Changed { contact }),
+        Ok(SharedNostrKey::Conflict(contact)) => events::emit(
+            app,
+            ContactsEvent::NostrKeyConflict {
+                contact,
+                npub: npub.to_string(),
+            },
+        ),
+        Ok(SharedNostrKey::Unchanged) => {}
+        Err(e) => tracing::debug!("couldn't update contact {}: {}", fingerprint, e),
+    }
+}
+
+/// Mirror a change made with [`favorites::favorites_set`].
+pub fn favorite_changed(app: &AppHandle, noise_public_key: &str, favorite: bool) {
+    let Some(fingerprint) = fingerprint(noise_public_key) else {
+        return;
+    };
+    enrich(app, &fingerprint, |contact| {
+        replace(&mut contact.favorite, favorite)
+    });
+}
+
+fn unknown_contact(fingerprint: &str) -> AppError {
+    AppError::new(ErrorCode::UnknownContact).with("fingerprint", fingerprint)
+}
+
+/// An empty string clears an optional field.
+fn non_empty(value: String) -> Option<String> {
+    Some(value).filter(|v| !v.is_empty())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn contacts_list(state: State<'_, StorageState>) -> AppResult<Vec<Contact>> {
+    state.with(Store::contacts)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn contacts_get(
+    fingerprint: String,
+    state: State<'_, StorageState>,
+) -> AppResult<Option<Contact>> {
+    state.with(|store| store.contact(&fingerprint))
+}
+
+/// Add a contact for `noise_public_key`, or return the existing one. New
+/// contacts start with what the mesh and favorites already know.
+#[tauri::command]
+#[specta::specta]
+pub fn contacts_create(
+    app: AppHandle,
+    noise_public_key: String,
+    state: State<'_, StorageState>,
+) -> AppResult<Contact> {
+    let noise_public_key = noise_public_key.to_lowercase();
+    let mut key = [0u8; 32];
+    hex::decode_to_slice(&noise_public_key, &mut key)
+        .map_err(|_| AppError::new(ErrorCode::InvalidArgument).with("field", "noise_public_key"))?;
+    let fingerprint = hex::encode(Sha256::digest(key));
+    if let Some(contact) = state.with(|store| store.contact(&fingerprint))? {
+        return Ok(contact);
+    }
+    let favorite = app
+        .state::<FavoritesState>()
+        .list()
+        .into_iter()
+        .find(|f| f.noise_public_key == noise_public_key);
+    let nickname = mesh::find_peer(&app, &key)
+        .and_then(|id| mesh::peer(&app, &id))
+        .map(|peer| peer.nickname)
+        .or_else(|| favorite.as_ref().map(|f| f.nickname.clone()))
+        .and_then(non_empty);
+    let now = now_ms();
+    let contact = Contact {
+        fingerprint,
+        noise_public_key,
+        nostr_pubkey: favorite.as_ref().and_then(|f| f.npub.clone()),
+        petname: None,
+        nickname,
+        profile_name: None,
+        nip05: None,
+        notes: String::new(),
+        favorite: favorite.is_some_and(|f| f.is_favorite),
+        trust: TrustLevel::Unknown,
+        created_at: now,
+        updated_at: now,
+    };
+    state.with(|store| store.save_contact(&contact))?;
+    Ok(contact)
+}
+
+/// Change a contact's user-assigned fields. Setting `favorite` here
+/// doesn't notify the peer; [`favorites::favorites_set`] does, and keeps
+/// the contact in step.
+#[tauri::command]
+#[specta::specta]
+pub fn contacts_update(
+    fingerprint: String,
+    update: ContactUpdate,
+    state: State<'_, StorageState>,
+) -> AppResult<Contact> {
+    if let Some(npub) = &update.nostr_pubkey {
+        if !npub.is_empty() && !favorites::is_npub(npub) {
+            return Err(AppError::new(ErrorCode::InvalidArgument).with("field", "nostr_pubkey"));
+        }
+    }
+    let contact = state.with(|store| {
+        let Some(mut contact) = store.contact(&fingerprint)? else {
+            return Ok(None);
+        };
+        if let Some(petname) = update.petname {
+            contact.petname = non_empty(petname);
+        }
+        if let Some(npub) = update.nostr_pubkey {
+            contact.nostr_pubkey = non_empty(npub);
+        }
+        if let Some(notes) = update.notes {
+            contact.notes = notes;
+        }
+        if let Some(favorite) = update.favorite {
+            contact.favorite = favorite;
+        }
+        if let Some(trust) = update.trust {
+            contact.trust = trust;
+        }
+        contact.updated_at = now_ms();
+        store.save_contact(&contact)?;
+        Ok(Some(contact))
+    })?;
+    contact.ok_or_else(|| unknown_contact(&fingerprint))
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn contacts_delete(fingerprint: String, state: State<'_, StorageState>) -> AppResult<()> {
+    if state.with(|store| store.delete_contact(&fingerprint))? {
+        Ok(())
+    } else {
+        Err(unknown_contact(&fingerprint))
+    }
+}
+
+/// Update the contacts using `profile`'s key from a kind 0 event the
+/// frontend fetched. Returns the contacts that changed.
+#[tauri::command]
+#[specta::specta]
+pub fn contacts_apply_profile(
+    app: AppHandle,
+    profile: NostrProfile,
+    state: State<'_, StorageState>,
+) -> AppResult<Vec<Contact>> {
+    if !favorites::is_npub(&profile.npub) {
+        return Err(AppError::new(ErrorCode::InvalidArgument).with("field", "npub"));
+    }
+    let changed = state.with(|store| store.apply_profile(&profile))?;
+    for contact in &changed {
+        events::emit(
+            &app,
+            ContactsEvent::Changed {
+                contact: contact.clone(),
+            },
+        );
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    #[test]
+    fn saves_and_finds_contacts() {
+        let store = Store::init(Connection::open_in_memory().unwrap()).unwrap();
+        let noise_public_key = hex::encode([7u8; 32]);
+        let contact = Contact {
+            fingerprint: fingerprint(&noise_public_key).unwrap(),
+            noise_public_key,
+            nostr_pubkey: Some(format!("npub1{}", "q".repeat(58))),
+            petname: Some("Al".to_string()),
+            nickname: Some("alice".to_string()),
+            profile_name: None,
+            nip05: None,
+            notes: "met at the meetup".to_string(),
+            favorite: true,
+            trust: TrustLevel::Verified,
+            created_at: 1,
+            updated_at: 2,
+        };
+        assert!(fingerprint("not hex").is_none());
+        store.save_contact(&contact).unwrap();
+
+        assert_eq!(
+            store.contact(&contact.fingerprint).unwrap(),
+            Some(contact.clone())
+        );
+        let npub = contact.nostr_pubkey.as_deref().unwrap();
+        assert_eq!(store.contacts_with_nostr_pubkey(npub).unwrap().len(), 1);
+        assert_eq!(store.contacts().unwrap(), [contact.clone()]);
+
+        assert!(store.delete_contact(&contact.fingerprint).unwrap());
+        assert!(!store.delete_contact(&contact.fingerprint).unwrap());
+        assert!(store.contact(&contact.fingerprint).unwrap().is_none());
+    }
+
+    fn npub(c: char) -> String {
+        format!("npub1{}", c.to_string().repeat(58))
+    }
+
+    fn save(store: &Store, seed: u8, nostr_pubkey: Option<String>) -> Contact {
+        let noise_public_key = hex::encode([seed; 32]);
+        let contact = Contact {
+            fingerprint: fingerprint(&noise_public_key).unwrap(),
+            noise_public_key,
+            nostr_pubkey,
+            petname: None,
+            nickname: None,
+            profile_name: None,
+            nip05: None,
+            notes: String::new(),
+            favorite: false,
+            trust: TrustLevel::Unknown,
+            created_at: 1,
+            updated_at: 1,
+        };
+        store.save_contact(&contact).unwrap();
+        contact
+    }
+
+    #[test]
+    fn shared_nostr_keys_only_fill_in_missing_ones() {
+        let store = Store::init(Connection::open_in_memory().unwrap()).unwrap();
+        let (a, b) = (npub('q'), npub('p'));
+        let contact = save(&store, 1, None);
+
+        let SharedNostrKey::Added(added) = store.share_nostr_key(&contact.fingerprint, &a).unwrap()
+        else {
+            panic!("npub not added");
+        };
+        assert_eq!(added.nostr_pubkey.as_deref(), Some(a.as_str()));
+        assert_eq!(
+            store.contact(&contact.fingerprint).unwrap(),
+            Some(added.clone())
+        );
+
+        assert_eq!(
+            store.share_nostr_key(&contact.fingerprint, &a).unwrap(),
+            SharedNostrKey::Unchanged
+        );
+        assert_eq!(
+            store.share_nostr_key(&contact.fingerprint, &b).unwrap(),
+            SharedNostrKey::Conflict(added.clone())
+        );
+        assert_eq!(store.contact(&contact.fingerprint).unwrap(), Some(added));
+
+        let stranger = fingerprint(&hex::encode([2u8; 32])).unwrap();
+        assert_eq!(
+            store.share_nostr_key(&stranger, &a).unwrap(),
+            SharedNostrKey::Unchanged
+        );
+        assert!(store.contact(&stranger).unwrap().is_none());
+    }
+
+    #[test]
+    fn profiles_update_only_contacts_with_their_key() {
+        let store = Store::init(Connection::open_in_memory().unwrap()).unwrap();
+        let alice = save(&store, 1, Some(npub('q')));
+        let bob = save(&store, 2, Some(npub('p')));
+        let carol = save(&store, 3, None);
+        let profile = NostrProfile {
+            npub: npub('q'),
+            name: Some("alice".to_string()),
+            display_name: Some(String::new()),
+            nip05: Some("alice@example.com".to_string()),
+        };
+
+        let changed = store.apply_profile(&profile).unwrap();
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].fingerprint, alice.fingerprint);
+        assert_eq!(changed[0].profile_name.as_deref(), Some("alice"));
+        assert_eq!(changed[0].nip05.as_deref(), Some("alice@example.com"));
+        assert_eq!(
+            store.contact(&alice.fingerprint).unwrap(),
+            Some(changed[0].clone())
+        );
+        assert_eq!(store.contact(&bob.fingerprint).unwrap(), Some(bob));
+        assert_eq!(store.contact(&carol.fingerprint).unwrap(), Some(carol));
+
+        assert!(store.apply_profile(&profile).unwrap().is_empty());
+    }
+}
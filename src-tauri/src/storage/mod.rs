@@ -0,0 +1,750 @@
+//! Message history, kept in an encrypted SQLite database.
+//!
+//! The database (`messages.db` in the app data directory) is a SQLCipher
+//! file whose 256-bit key is generated on first run and kept in the OS
+//! keyring, so the history can't be read off disk without the user's
+//! login. Messages the backend decrypts itself (channel, public mesh and
+//! bridged chat) are recorded as they arrive or go out; private messages
+//! are decrypted by the frontend, which stores them with
+//! [`messages_save`].
+//!
+//! Messages are grouped into [`Conversation`]s and paged newest first:
+//! pass the ID of the oldest message already loaded as `before` to get the
+//! page preceding it.
+//!
+//! Incoming messages start out unread, until [`messages_mark_read`]; the
+//! unread count drives the tray and dock badge. Outgoing ones carry how
+//! far they got ([`Delivery`]), as receipts for them come in.
+//!
+//! Message text is indexed with FTS5 for [`messages_search`], which ranks
+//! matches by BM25 and returns a snippet around them.
+//!
+//! The same database holds the user's [`contacts`].
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{AppHandle, Manager, State};
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::secure_store;
+use crate::tray;
+
+pub mod contacts;
+
+pub const FILE_NAME: &str = "messages.db";
+const KEY_ENTRY: &str = "storage_key";
+/// Largest page `messages_list` returns.
+pub const MAX_PAGE: u32 = 500;
+/// Schema changes, in order; `PRAGMA user_version` counts those applied.
+const MIGRATIONS: &[&str] = &[
+    "
+CREATE TABLE messages (
+    id TEXT PRIMARY KEY,
+    conversation TEXT NOT NULL,
+    sender_id TEXT NOT NULL,
+    nickname TEXT,
+    content TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    outgoing INTEGER NOT NULL
+);
+CREATE INDEX messages_by_conversation ON messages (conversation, timestamp, id);
+",
+    contacts::SCHEMA,
+    // Saves are upserts, so the update trigger sees replaced messages.
+    "
+CREATE VIRTUAL TABLE messages_fts USING fts5(content, content = 'messages');
+CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts (rowid, content) VALUES (new.rowid, new.content);
+END;
+CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, content)
+        VALUES ('delete', old.rowid, old.content);
+END;
+CREATE TRIGGER messages_fts_update AFTER UPDATE OF content ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, content)
+        VALUES ('delete', old.rowid, old.content);
+    INSERT INTO messages_fts (rowid, content) VALUES (new.rowid, new.content);
+END;
+INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');
+",
+    // History from before read tracking counts as read.
+    "
+ALTER TABLE messages ADD COLUMN read INTEGER NOT NULL DEFAULT 0;
+UPDATE messages SET read = 1;
+CREATE INDEX messages_unread ON messages (conversation) WHERE NOT read;
+",
+    "ALTER TABLE messages ADD COLUMN delivery TEXT;",
+];
+
+/// Around the matched terms in a [`SearchResult`] snippet. Control
+/// characters, so they can't clash with message text.
+pub const MATCH_START: &str = "\u{2}";
+pub const MATCH_END: &str = "\u{3}";
+/// Tokens of context a snippet shows.
+const SNIPPET_TOKENS: u32 = 16;
+
+/// Where a message was said.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Type)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Conversation {
+    /// Private messages with one peer, by mesh peer ID, Noise key or npub.
+    Direct { peer: String },
+    /// A password-protected mesh channel.
+    Channel { name: String },
+    /// Public chat on the local mesh.
+    Mesh,
+    /// A Nostr geohash channel.
+    Geohash { geohash: String },
+}
+
+impl Conversation {
+    /// How the conversation is stored.
+    fn key(&self) -> String {
+        match self {
+            Conversation::Direct { peer } => format!("direct:{peer}"),
+            Conversation::Channel { name } => format!("channel:{name}"),
+            Conversation::Mesh => "mesh".to_string(),
+            Conversation::Geohash { geohash } => format!("geohash:{geohash}"),
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        if key == "mesh" {
+            return Some(Conversation::Mesh);
+        }
+        let (kind, value) = key.split_once(':')?;
+        let value = value.to_string();
+        match kind {
+            "direct" => Some(Conversation::Direct { peer: value }),
+            "channel" => Some(Conversation::Channel { name: value }),
+            "geohash" => Some(Conversation::Geohash { geohash: value }),
+            _ => None,
+        }
+    }
+}
+
+/// The last word on an outgoing message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum Delivery {
+    Delivered,
+    Read,
+    /// Never acknowledged.
+    Failed,
+}
+
+impl Delivery {
+    fn key(self) -> &'static str {
+        match self {
+            Delivery::Delivered => "delivered",
+            Delivery::Read => "read",
+            Delivery::Failed => "failed",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "delivered" => Some(Delivery::Delivered),
+            "read" => Some(Delivery::Read),
+            "failed" => Some(Delivery::Failed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct StoredMessage {
+    /// Unique across conversations: a packet or Nostr event ID, or one the
+    /// frontend picked.
+    pub id: String,
+    pub conversation: Conversation,
+    pub sender_id: String,
+    pub nickname: Option<String>,
+    pub content: String,
+    /// Milliseconds since the epoch.
+    pub timestamp: u64,
+    /// Sent by us.
+    pub outgoing: bool,
+    /// For outgoing messages, once known. Set from receipts; saving a
+    /// message leaves it alone.
+    #[serde(default)]
+    pub delivery: Option<Delivery>,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct ConversationSummary {
+    pub conversation: Conversation,
+    pub message_count: u32,
+    pub unread_count: u32,
+    /// Timestamp of the newest message.
+    pub last_timestamp: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct SearchResult {
+    pub message: StoredMessage,
+    /// The matching part of the content, with matches between
+    /// [`MATCH_START`] and [`MATCH_END`].
+    pub snippet: String,
+}
+
+/// `query` as an FTS5 query matching messages with all its words, each
+/// quoted so that punctuation in it isn't query syntax. The last word also
+/// matches as a prefix, for search as you type.
+fn match_expression(query: &str) -> Option<String> {
+    let mut words: Vec<String> = query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+    words.last_mut()?.push('*');
+    Some(words.join(" "))
+}
+
+fn storage_error(err: rusqlite::Error) -> AppError {
+    AppError::new(ErrorCode::Storage).with("message", err)
+}
+
+fn message_from_row(row: &Row<'_>) -> rusqlite::Result<Option<StoredMessage>> {
+    let conversation: String = row.get("conversation")?;
+    let Some(conversation) = Conversation::from_key(&conversation) else {
+        return Ok(None);
+    };
+    Ok(Some(StoredMessage {
+        id: row.get("id")?,
+        conversation,
+        sender_id: row.get("sender_id")?,
+        nickname: row.get("nickname")?,
+        content: row.get("content")?,
+        timestamp: row.get::<_, i64>("timestamp")? as u64,
+        outgoing: row.get("outgoing")?,
+        delivery: row
+            .get::<_, Option<String>>("delivery")?
+            .as_deref()
+            .and_then(Delivery::from_key),
+    }))
+}
+
+/// The history database, open and unlocked.
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// Open the database at `path` with `key`, creating it if needed.
+    pub fn open(path: &Path, key: &[u8; 32]) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(&format!("PRAGMA key = \"x'{}'\";", hex::encode(key)))?;
+        Self::init(conn)
+    }
+
+    fn init(conn: Connection) -> rusqlite::Result<Self> {
+        // Fails here, not later, if the key is wrong.
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.unchecked_transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+        }
+        Ok(Self { conn })
+    }
+
+    /// Save `message`, replacing any with the same ID. New incoming
+    /// messages are unread; replacing one keeps its read state.
+    pub fn save(&self, message: &StoredMessage) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO messages
+                 (id, conversation, sender_id, nickname, content, timestamp, outgoing, read)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)
+             ON CONFLICT (id) DO UPDATE SET
+                 conversation = excluded.conversation,
+                 sender_id = excluded.sender_id,
+                 nickname = excluded.nickname,
+                 content = excluded.content,
+                 timestamp = excluded.timestamp,
+                 outgoing = excluded.outgoing",
+            params![
+                message.id,
+                message.conversation.key(),
+                message.sender_id,
+                message.nickname,
+                message.content,
+                message.timestamp as i64,
+                message.outgoing,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Up to `limit` messages in `conversation` older than message
+    /// `before` (or the newest, without it), oldest first.
+    pub fn list(
+        &self,
+        conversation: &Conversation,
+        before: Option<&str>,
+        limit: u32,
+    ) -> rusqlite::Result<Vec<StoredMessage>> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT * FROM messages
+             WHERE conversation = ?1
+               AND (?2 IS NULL OR (timestamp, id) <
+                    (SELECT timestamp, id FROM messages WHERE id = ?2))
+             ORDER BY timestamp DESC, id DESC
+             LIMIT ?3",
+        )?;
+        let rows =
+            statement.query_map(params![conversation.key(), before, limit], message_from_row)?;
+        let mut messages = rows
+            .filter_map(Result::transpose)
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    /// Up to `limit` messages matching `query` (see [`match_expression`]),
+    /// in `conversation` or anywhere, best match first.
+    pub fn search(
+        &self,
+        query: &str,
+        conversation: Option<&Conversation>,
+        limit: u32,
+    ) -> rusqlite::Result<Vec<SearchResult>> {
+        let Some(expression) = match_expression(query) else {
+            return Ok(Vec::new());
+        };
+        let mut statement = self.conn.prepare_cached(
+            "SELECT messages.*,
+                    snippet(messages_fts, 0, ?4, ?5, '…', ?6) AS snippet
+             FROM messages_fts JOIN messages ON messages.rowid = messages_fts.rowid
+             WHERE messages_fts MATCH ?1
+               AND (?2 IS NULL OR messages.conversation = ?2)
+             ORDER BY bm25(messages_fts)
+             LIMIT ?3",
+        )?;
+        let rows = statement.query_map(
+            params![
+                expression,
+                conversation.map(Conversation::key),
+                limit,
+                MATCH_START,
+                MATCH_END,
+                SNIPPET_TOKENS,
+            ],
+            |row| {
+                let snippet: String = row.get("snippet")?;
+                Ok(message_from_row(row)?.map(|message| SearchResult { message, snippet }))
+            },
+        )?;
+        rows.filter_map(Result::transpose).collect()
+    }
+
+    /// Returns whether there was such a message.
+    pub fn delete(&self, id: &str) -> rusqlite::Result<bool> {
+        Ok(self
+            .conn
+            .execute("DELETE FROM messages WHERE id = ?1", [id])?
+            > 0)
+    }
+
+    /// Delete every message in `conversation`. Returns how many there were.
+    pub fn delete_conversation(&self, conversation: &Conversation) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "DELETE FROM messages WHERE conversation = ?1",
+            [conversation.key()],
+        )
+    }
+
+    /// Mark the messages in `conversation`, or everywhere, read. Returns
+    /// how many were unread.
+    pub fn mark_read(&self, conversation: Option<&Conversation>) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "UPDATE messages SET read = 1
+             WHERE NOT read AND (?1 IS NULL OR conversation = ?1)",
+            [conversation.map(Conversation::key)],
+        )
+    }
+
+    /// Record how far outgoing message `id` got. A read message stays
+    /// read, and a delivered one can only become read. Returns whether
+    /// anything changed.
+    pub fn set_delivery(&self, id: &str, delivery: Delivery) -> rusqlite::Result<bool> {
+        Ok(self.conn.execute(
+            "UPDATE messages SET delivery = ?2
+             WHERE id = ?1 AND outgoing AND delivery IS NOT ?2
+               AND (delivery IS NULL OR delivery = 'failed'
+                    OR (delivery = 'delivered' AND ?2 = 'read'))",
+            params![id, delivery.key()],
+        )? > 0)
+    }
+
+    pub fn delivery(&self, id: &str) -> rusqlite::Result<Option<Delivery>> {
+        let key: Option<Option<String>> = self
+            .conn
+            .query_row("SELECT delivery FROM messages WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(key.flatten().as_deref().and_then(Delivery::from_key))
+    }
+
+    pub fn unread_count(&self) -> rusqlite::Result<u32> {
+        self.conn
+            .query_row("SELECT count(*) FROM messages WHERE NOT read", [], |row| {
+                row.get(0)
+            })
+    }
+
+    /// Every conversation with messages, most recently active first.
+    pub fn conversations(&self) -> rusqlite::Result<Vec<ConversationSummary>> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT conversation, count(*), sum(NOT read), max(timestamp) FROM messages
+             GROUP BY conversation
+             ORDER BY max(timestamp) DESC",
+        )?;
+        let rows = statement.query_map([], |row| {
+            let key: String = row.get(0)?;
+            let Some(conversation) = Conversation::from_key(&key) else {
+                return Ok(None);
+            };
+            Ok(Some(ConversationSummary {
+                conversation,
+                message_count: row.get(1)?,
+                unread_count: row.get(2)?,
+                last_timestamp: row.get::<_, i64>(3)? as u64,
+            }))
+        })?;
+        rows.filter_map(Result::transpose).collect()
+    }
+}
+
+/// The database key from the keyring, created on first use.
+fn database_key() -> AppResult<[u8; 32]> {
+    let mut key = [0u8; 32];
+    match secure_store::get(KEY_ENTRY)? {
+        Some(stored) => hex::decode_to_slice(stored, &mut key).map_err(|_| {
+            AppError::new(ErrorCode::SecureStoreUnavailable)
+                .with("message", "stored database key is corrupt")
+        })?,
+        None => {
+            key = rand::random();
+            secure_store::set(KEY_ENTRY, &hex::encode(key))?;
+        }
+    }
+    Ok(key)
+}
+
+/// The message store, if it could be opened. The app runs without history
+/// when the keyring or the database is unavailable.
+pub struct StorageState(Mutex<Option<Store>>);
+
+impl StorageState {
+    pub fn open(path: PathBuf) -> Self {
+        let store = database_key().and_then(|key| {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            Store::open(&path, &key).map_err(storage_error)
+        });
+        match store {
+            Ok(store) => Self(Mutex::new(Some(store))),
+            Err(e) => {
+                tracing::warn!("message history unavailable: {}", e);
+                Self(Mutex::new(None))
+            }
+        }
+    }
+
+    pub fn with<T>(&self, f: impl FnOnce(&Store) -> rusqlite::Result<T>) -> AppResult<T> {
+        let store = self.0.lock().unwrap();
+        let store = store
+            .as_ref()
+            .ok_or_else(|| AppError::new(ErrorCode::Storage).with("message", "unavailable"))?;
+        f(store).map_err(storage_error)
+    }
+}
+
+/// Record a message the backend sent or decrypted. Failures are logged;
+/// history is best effort.
+pub fn record(app: &AppHandle, message: StoredMessage) {
+    if let Err(e) = app
+        .state::<StorageState>()
+        .with(|store| store.save(&message))
+    {
+        tracing::debug!("couldn't store message {}: {}", message.id, e);
+    }
+    refresh_badge(app);
+}
+
+/// Show the current unread count on the tray and dock badge.
+pub fn refresh_badge(app: &AppHandle) {
+    let unread = match app.state::<StorageState>().with(Store::unread_count) {
+        Ok(unread) => unread,
+        Err(e) => {
+            tracing::debug!("can't count unread messages: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = tray::update_status(app, |s| s.unread = unread) {
+        tracing::debug!("can't update the unread badge: {}", e);
+    }
+}
+
+/// Store a message the frontend decrypted or sent, e.g. a private message.
+#[tauri::command]
+#[specta::specta]
+pub fn messages_save(
+    app: AppHandle,
+    message: StoredMessage,
+    state: State<'_, StorageState>,
+) -> AppResult<()> {
+    if message.id.is_empty() {
+        return Err(AppError::new(ErrorCode::InvalidArgument).with("field", "id"));
+    }
+    state.with(|store| store.save(&message))?;
+    refresh_badge(&app);
+    Ok(())
+}
+
+/// Mark `conversation` read, or every conversation without it. Returns how
+/// many messages were unread.
+#[tauri::command]
+#[specta::specta]
+pub fn messages_mark_read(
+    app: AppHandle,
+    conversation: Option<Conversation>,
+    state: State<'_, StorageState>,
+) -> AppResult<u32> {
+    let marked = state.with(|store| store.mark_read(conversation.as_ref()))?;
+    refresh_badge(&app);
+    Ok(marked as u32)
+}
+
+/// A page of `conversation`, oldest first; see the module docs.
+#[tauri::command]
+#[specta::specta]
+pub fn messages_list(
+    conversation: Conversation,
+    before: Option<String>,
+    limit: u32,
+    state: State<'_, StorageState>,
+) -> AppResult<Vec<StoredMessage>> {
+    if limit == 0 || limit > MAX_PAGE {
+        return Err(AppError::new(ErrorCode::InvalidArgument)
+            .with("field", "limit")
+            .with("max", MAX_PAGE));
+    }
+    state.with(|store| store.list(&conversation, before.as_deref(), limit))
+}
+
+/// Search message text, in one conversation or all of them. Results are
+/// ranked best first; see [`SearchResult`] for the snippets.
+#[tauri::command]
+#[specta::specta]
+pub fn messages_search(
+    query: String,
+    conversation: Option<Conversation>,
+    limit: u32,
+    state: State<'_, StorageState>,
+) -> AppResult<Vec<SearchResult>> {
+    if query.trim().is_empty() {
+        return Err(AppError::new(ErrorCode::InvalidArgument).with("field", "query"));
+    }
+    if limit == 0 || limit > MAX_PAGE {
+        return Err(AppError::new(ErrorCode::InvalidArgument)
+            .with("field", "limit")
+            .with("max", MAX_PAGE));
+    }
+    state.with(|store| store.search(&query, conversation.as_ref(), limit))
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn messages_conversations(
+    state: State<'_, StorageState>,
+) -> AppResult<Vec<ConversationSummary>> {
+    state.with(Store::conversations)
+}
+
+/// Delete one message. Returns false if there was none with that ID.
+#[tauri::command]
+#[specta::specta]
+pub fn messages_delete(
+    app: AppHandle,
+    id: String,
+    state: State<'_, StorageState>,
+) -> AppResult<bool> {
+    let deleted = state.with(|store| store.delete(&id))?;
+    refresh_badge(&app);
+    Ok(deleted)
+}
+
+/// Delete a whole conversation. Returns how many messages went.
+#[tauri::command]
+#[specta::specta]
+pub fn messages_delete_conversation(
+    app: AppHandle,
+    conversation: Conversation,
+    state: State<'_, StorageState>,
+) -> AppResult<u32> {
+    let deleted = state.with(|store| store.delete_conversation(&conversation))?;
+    refresh_badge(&app);
+    Ok(deleted as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, conversation: Conversation, timestamp: u64) -> StoredMessage {
+        StoredMessage {
+            id: id.to_string(),
+            conversation,
+            sender_id: "0102030405060708".to_string(),
+            nickname: Some("alice".to_string()),
+            content: format!("message {id}"),
+            timestamp,
+            outgoing: false,
+            delivery: None,
+        }
+    }
+
+    #[test]
+    fn pages_conversations_newest_first() {
+        let store = Store::init(Connection::open_in_memory().unwrap()).unwrap();
+        let channel = Conversation::Channel {
+            name: "#bitchat".to_string(),
+        };
+        for (id, timestamp) in [("a", 1), ("b", 2), ("c", 2), ("d", 3)] {
+            store
+                .save(&message(id, channel.clone(), timestamp))
+                .unwrap();
+        }
+        store.save(&message("m", Conversation::Mesh, 5)).unwrap();
+
+        let ids = |page: Vec<StoredMessage>| page.into_iter().map(|m| m.id).collect::<Vec<_>>();
+        assert_eq!(ids(store.list(&channel, None, 2).unwrap()), ["c", "d"]);
+        assert_eq!(ids(store.list(&channel, Some("c"), 2).unwrap()), ["a", "b"]);
+        assert!(store.list(&channel, Some("a"), 2).unwrap().is_empty());
+
+        let summaries = store.conversations().unwrap();
+        assert_eq!(summaries[0].conversation, Conversation::Mesh);
+        assert_eq!(summaries[1].message_count, 4);
+
+        assert!(store.delete("b").unwrap());
+        assert!(!store.delete("b").unwrap());
+        assert_eq!(
+            ids(store.list(&channel, None, 10).unwrap()),
+            ["a", "c", "d"]
+        );
+        assert_eq!(store.delete_conversation(&channel).unwrap(), 3);
+        assert_eq!(store.conversations().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn tracks_unread_messages() {
+        let store = Store::init(Connection::open_in_memory().unwrap()).unwrap();
+        let channel = Conversation::Channel {
+            name: "#bitchat".to_string(),
+        };
+        store.save(&message("a", channel.clone(), 1)).unwrap();
+        store.save(&message("b", Conversation::Mesh, 2)).unwrap();
+        let mut sent = message("c", Conversation::Mesh, 3);
+        sent.outgoing = true;
+        store.save(&sent).unwrap();
+        assert_eq!(store.unread_count().unwrap(), 2);
+
+        assert_eq!(store.mark_read(Some(&channel)).unwrap(), 1);
+        // Saving a message again doesn't make it unread.
+        store.save(&message("a", channel.clone(), 1)).unwrap();
+        assert_eq!(store.unread_count().unwrap(), 1);
+        let summaries = store.conversations().unwrap();
+        assert_eq!(summaries[0].conversation, Conversation::Mesh);
+        assert_eq!(summaries[0].unread_count, 1);
+
+        assert_eq!(store.mark_read(None).unwrap(), 1);
+        assert_eq!(store.unread_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn keeps_the_furthest_delivery_state() {
+        let store = Store::init(Connection::open_in_memory().unwrap()).unwrap();
+        let mut sent = message("a", Conversation::Mesh, 1);
+        sent.outgoing = true;
+        store.save(&sent).unwrap();
+        store.save(&message("b", Conversation::Mesh, 2)).unwrap();
+
+        assert!(store.set_delivery("a", Delivery::Failed).unwrap());
+        // A late ack still counts.
+        assert!(store.set_delivery("a", Delivery::Delivered).unwrap());
+        assert!(store.set_delivery("a", Delivery::Read).unwrap());
+        assert!(!store.set_delivery("a", Delivery::Delivered).unwrap());
+        assert!(!store.set_delivery("a", Delivery::Failed).unwrap());
+        // Saving the message again keeps it.
+        store.save(&sent).unwrap();
+        assert_eq!(store.delivery("a").unwrap(), Some(Delivery::Read));
+        assert_eq!(
+            store.list(&Conversation::Mesh, Some("b"), 1).unwrap()[0].delivery,
+            Some(Delivery::Read)
+        );
+
+        // Only our own messages have a delivery state.
+        assert!(!store.set_delivery("b", Delivery::Read).unwrap());
+        assert!(!store.set_delivery("unknown", Delivery::Read).unwrap());
+        assert_eq!(store.delivery("b").unwrap(), None);
+    }
+
+    #[test]
+    fn searches_message_text() {
+        let store = Store::init(Connection::open_in_memory().unwrap()).unwrap();
+        let channel = Conversation::Channel {
+            name: "#bitchat".to_string(),
+        };
+        let said = |id: &str, conversation: &Conversation, content: &str| {
+            let mut m = message(id, conversation.clone(), 1);
+            m.content = content.to_string();
+            store.save(&m).unwrap();
+        };
+        said("a", &channel, "meet at the bridge at noon");
+        said("b", &channel, "the bridge is closed, meet at the station");
+        said("c", &Conversation::Mesh, "anyone near the bridge?");
+        said("d", &Conversation::Mesh, "bridges everywhere \"quoted\"");
+
+        let ids = |results: Vec<SearchResult>| {
+            let mut ids: Vec<_> = results.into_iter().map(|r| r.message.id).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(
+            ids(store.search("bridge", None, 10).unwrap()),
+            ["a", "b", "c", "d"]
+        );
+        assert_eq!(
+            ids(store.search("meet bridge", None, 10).unwrap()),
+            ["a", "b"]
+        );
+        assert_eq!(
+            ids(store
+                .search("bridge", Some(&Conversation::Mesh), 10)
+                .unwrap()),
+            ["c", "d"]
+        );
+        // Punctuation isn't query syntax.
+        assert_eq!(ids(store.search("\"quoted", None, 10).unwrap()), ["d"]);
+        assert!(store.search("   ", None, 10).unwrap().is_empty());
+
+        let result = &store.search("noon", None, 10).unwrap()[0];
+        assert!(result
+            .snippet
+            .contains(&format!("{MATCH_START}noon{MATCH_END}")));
+
+        // Edits and deletions reach the index.
+        said("a", &channel, "meet at the station at noon");
+        assert_eq!(ids(store.search("meet bridge", None, 10).unwrap()), ["b"]);
+        assert!(store.delete("b").unwrap());
+        assert!(store.search("closed", None, 10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,403 @@
+//! BLE central compatible with the native bitchat apps.
+//!
+//! Scans for peripherals advertising the bitchat service, connects, and
+//! subscribes to its single characteristic. Each connected peripheral is a
+//! link: notifications arrive as inbound frames, and outbound frames are
+//! written without response, as the mobile apps do.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use base64::Engine;
+use btleplug::api::{
+    Central, CentralEvent, Characteristic, Manager as _, Peripheral as _, ScanFilter, WriteType,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral, PeripheralId};
+use futures::StreamExt;
+use serde::Serialize;
+use specta::Type;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, State};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::scan::{self, DutyCycle};
+use super::Inbound;
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::mesh;
+
+/// Service and characteristic UUIDs used by the iOS and Android apps.
+pub const SERVICE_UUID: Uuid = Uuid::from_u128(0xf47b5e2d_4a9e_4c5a_9b3f_8e1d2c3a4b5c);
+pub const CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0xa1b2c3d4_e5f6_4a5b_8c9d_0e1f2a3b4c5d);
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct BleLink {
+    pub link_id: String,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+}
+
+struct Link {
+    peripheral: Peripheral,
+    characteristic: Characteristic,
+    info: BleLink,
+    reader: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct Links {
+    connected: HashMap<String, Link>,
+    connecting: HashSet<String>,
+}
+
+struct Shared {
+    adapter: Adapter,
+    links: Mutex<Links>,
+    inbound: mpsc::UnboundedSender<Inbound>,
+    /// When a bitchat peripheral was last discovered (or the scan started).
+    last_seen: Mutex<Instant>,
+}
+
+pub struct BleCentral {
+    shared: Arc<Shared>,
+    scanner: JoinHandle<()>,
+    duty: JoinHandle<()>,
+}
+
+fn unavailable(err: btleplug::Error) -> AppError {
+    AppError::new(ErrorCode::BluetoothUnavailable).with("message", err)
+}
+
+/// Whether there is an adapter to scan with.
+pub async fn supported() -> bool {
+    match Manager::new().await {
+        Ok(manager) => manager.adapters().await.is_ok_and(|a| !a.is_empty()),
+        Err(_) => false,
+    }
+}
+
+impl BleCentral {
+    /// Start scanning on the first adapter and connect to every bitchat
+    /// peripheral found. Links and frames are reported to `inbound`.
+    /// `schedule` picks the scan duty cycle, given whether peers were seen
+    /// recently; it's asked again at the end of every cycle.
+    pub async fn start(
+        inbound: mpsc::UnboundedSender<Inbound>,
+        schedule: impl Fn(bool) -> DutyCycle + Send + 'static,
+    ) -> AppResult<Self> {
+        let manager = Manager::new().await.map_err(unavailable)?;
+        let adapter = manager
+            .adapters()
+            .await
+            .map_err(unavailable)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| AppError::new(ErrorCode::BluetoothUnavailable))?;
+        let mut central_events = adapter.events().await.map_err(unavailable)?;
+        adapter
+            .start_scan(ScanFilter {
+                services: vec![SERVICE_UUID],
+            })
+            .await
+            .map_err(unavailable)?;
+
+        let shared = Arc::new(Shared {
+            adapter,
+            links: Mutex::new(Links::default()),
+            inbound,
+            last_seen: Mutex::new(Instant::now()),
+        });
+        let scanner = tauri::async_runtime::spawn({
+            let shared = shared.clone();
+            async move {
+                while let Some(event) = central_events.next().await {
+                    match event {
+                        CentralEvent::DeviceDiscovered(id)
+                        | CentralEvent::ServicesAdvertisement { id, .. } => {
+                            *shared.last_seen.lock().unwrap() = Instant::now();
+                            shared.clone().connect(id)
+                        }
+                        CentralEvent::DeviceUpdated(id) => shared.clone().report_rssi(id),
+                        CentralEvent::DeviceDisconnected(id) => shared.drop_link(&id.to_string()),
+                        _ => {}
+                    }
+                }
+            }
+        });
+
+        let duty = tauri::async_runtime::spawn(shared.clone().duty_cycle(schedule));
+
+        Ok(Self {
+            shared,
+            scanner,
+            duty,
+        })
+    }
+
+    pub async fn stop(self) {
+        self.duty.abort();
+        self.scanner.abort();
+        if let Err(e) = self.shared.adapter.stop_scan().await {
+            tracing::debug!("failed to stop BLE scan: {}", e);
+        }
+        let links: Vec<Link> = {
+            let mut links = self.shared.links.lock().unwrap();
+            links.connected.drain().map(|(_, link)| link).collect()
+        };
+        for link in links {
+            link.reader.abort();
+            let _ = link.peripheral.disconnect().await;
+            let _ = self.shared.inbound.send(Inbound::LinkDown {
+                link_id: link.info.link_id,
+            });
+        }
+    }
+
+    pub fn links(&self) -> Vec<BleLink> {
+        self.shared
+            .links
+            .lock()
+            .unwrap()
+            .connected
+            .values()
+            .map(|link| link.info.clone())
+            .collect()
+    }
+
+    pub async fn send(&self, link_id: &str, data: &[u8]) -> AppResult<()> {
+        let (peripheral, characteristic) = {
+            let links = self.shared.links.lock().unwrap();
+            let link = links
+                .connected
+                .get(link_id)
+                .ok_or_else(|| AppError::new(ErrorCode::UnknownLink).with("link_id", link_id))?;
+            (link.peripheral.clone(), link.characteristic.clone())
+        };
+        peripheral
+            .write(&characteristic, data, WriteType::WithoutResponse)
+            .await
+            .map_err(unavailable)
+    }
+
+    /// Write `data` to every connected link except `exclude`.
+    pub async fn broadcast(&self, data: &[u8], exclude: Option<&str>) {
+        let link_ids: Vec<String> = self
+            .shared
+            .links
+            .lock()
+            .unwrap()
+            .connected
+            .keys()
+            .filter(|id| Some(id.as_str()) != exclude)
+            .cloned()
+            .collect();
+        for link_id in link_ids {
+            if let Err(e) = self.send(&link_id, data).await {
+                tracing::debug!("BLE write to {} failed: {}", link_id, e);
+            }
+        }
+    }
+}
+
+impl Shared {
+    /// Pause and resume the scan (already running on entry) as `schedule`
+    /// says. Connected links are unaffected.
+    async fn duty_cycle(self: Arc<Self>, schedule: impl Fn(bool) -> DutyCycle) {
+        let mut scanning = true;
+        loop {
+            let cycle = schedule(self.recently_active());
+            if !scanning {
+                let filter = ScanFilter {
+                    services: vec![SERVICE_UUID],
+                };
+                match self.adapter.start_scan(filter).await {
+                    Ok(()) => scanning = true,
+                    Err(e) => tracing::debug!("failed to resume BLE scan: {}", e),
+                }
+            }
+            tokio::time::sleep(cycle.on).await;
+            if cycle.off.is_zero() {
+                continue;
+            }
+            if let Err(e) = self.adapter.stop_scan().await {
+                tracing::debug!("failed to pause BLE scan: {}", e);
+            }
+            scanning = false;
+            tokio::time::sleep(cycle.off).await;
+        }
+    }
+
+    /// Whether a peer is connected or was seen within
+    /// [`scan::RECENT_ACTIVITY`].
+    fn recently_active(&self) -> bool {
+        !self.links.lock().unwrap().connected.is_empty()
+            || self.last_seen.lock().unwrap().elapsed() < scan::RECENT_ACTIVITY
+    }
+
+    fn connect(self: Arc<Self>, id: PeripheralId) {
+        let link_id = id.to_string();
+        {
+            let mut links = self.links.lock().unwrap();
+            if links.connected.contains_key(&link_id) || !links.connecting.insert(link_id.clone()) {
+                return;
+            }
+        }
+        tauri::async_runtime::spawn(async move {
+            let result = self.open(&id, &link_id).await;
+            let mut links = self.links.lock().unwrap();
+            links.connecting.remove(&link_id);
+            match result {
+                Ok(Some(link)) => {
+                    let _ = self.inbound.send(Inbound::LinkUp {
+                        link_id: link_id.clone(),
+                        name: link.info.name.clone(),
+                    });
+                    links.connected.insert(link_id, link);
+                }
+                Ok(None) => {}
+                Err(e) => tracing::debug!("BLE connect to {} failed: {}", link_id, e),
+            }
+        });
+    }
+
+    /// Connect and subscribe, or `None` if the peripheral isn't a bitchat node.
+    async fn open(&self, id: &PeripheralId, link_id: &str) -> btleplug::Result<Option<Link>> {
+        let peripheral = self.adapter.peripheral(id).await?;
+        let Some(properties) = peripheral.properties().await? else {
+            return Ok(None);
+        };
+        if !properties.services.contains(&SERVICE_UUID) {
+            return Ok(None);
+        }
+        if !peripheral.is_connected().await? {
+            peripheral.connect().await?;
+        }
+        peripheral.discover_services().await?;
+        let Some(characteristic) = peripheral
+            .characteristics()
+            .into_iter()
+            .find(|c| c.uuid == CHARACTERISTIC_UUID)
+        else {
+            peripheral.disconnect().await?;
+            return Ok(None);
+        };
+        peripheral.subscribe(&characteristic).await?;
+
+        let mut notifications = peripheral.notifications().await?;
+        let inbound = self.inbound.clone();
+        let reader_link_id = link_id.to_string();
+        let reader = tauri::async_runtime::spawn(async move {
+            while let Some(notification) = notifications.next().await {
+                if notification.uuid != CHARACTERISTIC_UUID {
+                    continue;
+                }
+                let frame = Inbound::Frame {
+                    link_id: reader_link_id.clone(),
+                    data: notification.value,
+                };
+                if inbound.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Some(Link {
+            peripheral,
+            characteristic,
+            info: BleLink {
+                link_id: link_id.to_string(),
+                name: properties.local_name,
+                rssi: properties.rssi,
+            },
+            reader,
+        }))
+    }
+
+    /// Pass on the RSSI of a connected peripheral after an update.
+    fn report_rssi(self: Arc<Self>, id: PeripheralId) {
+        let link_id = id.to_string();
+        let peripheral = match self.links.lock().unwrap().connected.get(&link_id) {
+            Some(link) => link.peripheral.clone(),
+            None => return,
+        };
+        tauri::async_runtime::spawn(async move {
+            let Ok(Some(properties)) = peripheral.properties().await else {
+                return;
+            };
+            let Some(rssi) = properties.rssi else {
+                return;
+            };
+            if let Some(link) = self.links.lock().unwrap().connected.get_mut(&link_id) {
+                link.info.rssi = Some(rssi);
+            }
+            let _ = self.inbound.send(Inbound::Rssi { link_id, rssi });
+        });
+    }
+
+    fn drop_link(&self, link_id: &str) {
+        let Some(link) = self.links.lock().unwrap().connected.remove(link_id) else {
+            return;
+        };
+        link.reader.abort();
+        let _ = self.inbound.send(Inbound::LinkDown {
+            link_id: link_id.to_string(),
+        });
+    }
+}
+
+#[derive(Default)]
+pub struct BleState(pub(crate) tokio::sync::Mutex<Option<BleCentral>>);
+
+#[tauri::command]
+#[specta::specta]
+pub async fn ble_start(app: AppHandle, state: State<'_, BleState>) -> AppResult<()> {
+    let mut central = state.0.lock().await;
+    if central.is_some() {
+        return Ok(());
+    }
+    let schedule = {
+        let app = app.clone();
+        move |peers_seen| scan::current(&app, peers_seen)
+    };
+    *central = Some(BleCentral::start(mesh::inbound(&app), schedule).await?);
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn ble_stop(state: State<'_, BleState>) -> AppResult<()> {
+    if let Some(central) = state.0.lock().await.take() {
+        central.stop().await;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn ble_get_links(state: State<'_, BleState>) -> AppResult<Vec<BleLink>> {
+    Ok(state
+        .0
+        .lock()
+        .await
+        .as_ref()
+        .map(BleCentral::links)
+        .unwrap_or_default())
+}
+
+/// Write a base64-encoded frame to one central link, or to every link in
+/// both roles.
+#[tauri::command]
+#[specta::specta]
+pub async fn ble_send(app: AppHandle, link_id: Option<String>, data: String) -> AppResult<()> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|_| AppError::new(ErrorCode::InvalidArgument).with("field", "data"))?;
+    match link_id {
+        Some(link_id) => super::send_to(&app, &link_id, &data).await,
+        None => {
+            super::broadcast(&app, &data, None).await;
+            Ok(())
+        }
+    }
+}
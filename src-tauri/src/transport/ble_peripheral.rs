@@ -0,0 +1,352 @@
+//! BLE peripheral mode: advertise the bitchat service so phones can connect
+//! to the desktop.
+//!
+//! btleplug only speaks the central role, so advertising goes through BlueZ
+//! (`bluer`) and is Linux-only for now. Elsewhere `ble_start_advertising`
+//! fails with `Unsupported`; `ble_get_capabilities` tells the UI up front.
+//!
+//! Each central that connects to us is a link of its own, from its first
+//! write or subscription until it disconnects, with an ID starting with
+//! [`LINK_PREFIX`] so replies are routed back through this transport.
+
+use serde::Serialize;
+use specta::Type;
+use tauri::{AppHandle, State};
+
+use crate::error::AppResult;
+use crate::mesh;
+
+/// Link IDs of centrals connected to us start with this, to tell them from
+/// the peripherals we connect to.
+pub const LINK_PREFIX: &str = "ble-peripheral:";
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct BleCapabilities {
+    /// An adapter is present that can scan and connect.
+    pub central: bool,
+    /// This OS and adapter can advertise and serve the bitchat service.
+    pub peripheral: bool,
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use bluer::adv::{Advertisement, AdvertisementHandle};
+    use bluer::gatt::local::{
+        characteristic_control, Application, ApplicationHandle, Characteristic,
+        CharacteristicControlEvent, CharacteristicNotify, CharacteristicNotifyMethod,
+        CharacteristicWrite, CharacteristicWriteMethod, Service,
+    };
+    use bluer::gatt::CharacteristicWriter;
+    use bluer::{Adapter, Address, DeviceEvent, DeviceProperty};
+    use futures::StreamExt;
+    use tauri::async_runtime::JoinHandle;
+    use tokio::sync::{mpsc, Mutex};
+
+    use super::super::ble::{CHARACTERISTIC_UUID, SERVICE_UUID};
+    use super::super::Inbound;
+    use super::LINK_PREFIX;
+    use crate::error::{AppError, AppResult, ErrorCode};
+
+    const LOCAL_NAME: &str = "bitchat";
+
+    fn unavailable(err: bluer::Error) -> AppError {
+        AppError::new(ErrorCode::BluetoothUnavailable).with("message", err)
+    }
+
+    fn unknown_link(link_id: &str) -> AppError {
+        AppError::new(ErrorCode::UnknownLink).with("link_id", link_id)
+    }
+
+    /// A central connected to us.
+    struct Central {
+        /// Its notify session, once it has subscribed.
+        notifier: Option<CharacteristicWriter>,
+        /// Watches for it to disconnect.
+        watch: JoinHandle<()>,
+    }
+
+    struct Shared {
+        adapter: Adapter,
+        /// Link ID -> central.
+        centrals: Mutex<HashMap<String, Central>>,
+        inbound: mpsc::UnboundedSender<Inbound>,
+    }
+
+    pub struct BlePeripheral {
+        _advertisement: AdvertisementHandle,
+        _application: ApplicationHandle,
+        shared: Arc<Shared>,
+        control: JoinHandle<()>,
+    }
+
+    pub async fn supported() -> bool {
+        let Ok(session) = bluer::Session::new().await else {
+            return false;
+        };
+        let Ok(adapter) = session.default_adapter().await else {
+            return false;
+        };
+        adapter
+            .supported_advertising_instances()
+            .await
+            .is_ok_and(|n| n > 0)
+    }
+
+    impl BlePeripheral {
+        pub async fn start(inbound: mpsc::UnboundedSender<Inbound>) -> AppResult<Self> {
+            let session = bluer::Session::new().await.map_err(unavailable)?;
+            let adapter = session.default_adapter().await.map_err(unavailable)?;
+            adapter.set_powered(true).await.map_err(unavailable)?;
+
+            let shared = Arc::new(Shared {
+                adapter: adapter.clone(),
+                centrals: Mutex::new(HashMap::new()),
+                inbound,
+            });
+            let writes = shared.clone();
+            let (mut control, control_handle) = characteristic_control();
+            let application = Application {
+                services: vec![Service {
+                    uuid: SERVICE_UUID,
+                    primary: true,
+                    characteristics: vec![Characteristic {
+                        uuid: CHARACTERISTIC_UUID,
+                        write: Some(CharacteristicWrite {
+                            write: true,
+                            write_without_response: true,
+                            method: CharacteristicWriteMethod::Fun(Box::new(move |data, req| {
+                                let shared = writes.clone();
+                                Box::pin(async move {
+                                    shared.frame(req.device_address, data).await;
+                                    Ok(())
+                                })
+                            })),
+                            ..Default::default()
+                        }),
+                        // Acquired notify sessions are per central, unlike
+                        // the shared one `Fun` gets.
+                        notify: Some(CharacteristicNotify {
+                            notify: true,
+                            method: CharacteristicNotifyMethod::Io,
+                            ..Default::default()
+                        }),
+                        control_handle,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+            let application = adapter
+                .serve_gatt_application(application)
+                .await
+                .map_err(unavailable)?;
+
+            let advertisement = adapter
+                .advertise(Advertisement {
+                    service_uuids: [SERVICE_UUID].into_iter().collect(),
+                    discoverable: Some(true),
+                    local_name: Some(LOCAL_NAME.to_string()),
+                    ..Default::default()
+                })
+                .await
+                .map_err(unavailable)?;
+
+            let subscriptions = shared.clone();
+            let control = tauri::async_runtime::spawn(async move {
+                while let Some(event) = control.next().await {
+                    if let CharacteristicControlEvent::Notify(notifier) = event {
+                        subscriptions.subscribed(notifier).await;
+                    }
+                }
+            });
+
+            Ok(Self {
+                _advertisement: advertisement,
+                _application: application,
+                shared,
+                control,
+            })
+        }
+
+        pub async fn send(&self, link_id: &str, data: &[u8]) -> AppResult<()> {
+            let mut centrals = self.shared.centrals.lock().await;
+            let central = centrals
+                .get_mut(link_id)
+                .ok_or_else(|| unknown_link(link_id))?;
+            let notifier = central
+                .notifier
+                .as_ref()
+                .ok_or_else(|| unknown_link(link_id))?;
+            if notifier.send(data).await.is_err() {
+                // It unsubscribed; it may still write to us.
+                central.notifier = None;
+                return Err(unknown_link(link_id));
+            }
+            Ok(())
+        }
+
+        /// Notify every subscribed central except `exclude`.
+        pub async fn broadcast(&self, data: &[u8], exclude: Option<&str>) {
+            let mut centrals = self.shared.centrals.lock().await;
+            for (link_id, central) in centrals.iter_mut() {
+                if Some(link_id.as_str()) == exclude {
+                    continue;
+                }
+                if let Some(notifier) = &central.notifier {
+                    if notifier.send(data).await.is_err() {
+                        central.notifier = None;
+                    }
+                }
+            }
+        }
+    }
+
+    impl Drop for BlePeripheral {
+        fn drop(&mut self) {
+            self.control.abort();
+            let shared = self.shared.clone();
+            tauri::async_runtime::spawn(async move {
+                let centrals: Vec<String> = shared.centrals.lock().await.keys().cloned().collect();
+                for link_id in centrals {
+                    shared.disconnected(&link_id).await;
+                }
+            });
+        }
+    }
+
+    impl Shared {
+        /// Bring up the link for `address` if it's new. Returns its ID.
+        async fn connected(self: &Arc<Self>, address: Address) -> String {
+            let link_id = format!("{}{}", LINK_PREFIX, address);
+            let mut centrals = self.centrals.lock().await;
+            if centrals.contains_key(&link_id) {
+                return link_id;
+            }
+            let _ = self.inbound.send(Inbound::LinkUp {
+                link_id: link_id.clone(),
+                name: None,
+            });
+            let watch = tauri::async_runtime::spawn({
+                let shared = self.clone();
+                let link_id = link_id.clone();
+                async move {
+                    shared.wait_for_disconnect(address).await;
+                    shared.disconnected(&link_id).await;
+                }
+            });
+            centrals.insert(
+                link_id.clone(),
+                Central {
+                    notifier: None,
+                    watch,
+                },
+            );
+            link_id
+        }
+
+        async fn wait_for_disconnect(&self, address: Address) {
+            let Ok(device) = self.adapter.device(address) else {
+                return;
+            };
+            let Ok(mut events) = device.events().await else {
+                return;
+            };
+            while let Some(event) = events.next().await {
+                if matches!(
+                    event,
+                    DeviceEvent::PropertyChanged(DeviceProperty::Connected(false))
+                ) {
+                    return;
+                }
+            }
+        }
+
+        async fn disconnected(&self, link_id: &str) {
+            let Some(central) = self.centrals.lock().await.remove(link_id) else {
+                return;
+            };
+            central.watch.abort();
+            let _ = self.inbound.send(Inbound::LinkDown {
+                link_id: link_id.to_string(),
+            });
+        }
+
+        async fn frame(self: &Arc<Self>, address: Address, data: Vec<u8>) {
+            let link_id = self.connected(address).await;
+            let _ = self.inbound.send(Inbound::Frame { link_id, data });
+        }
+
+        async fn subscribed(self: &Arc<Self>, notifier: CharacteristicWriter) {
+            let link_id = self.connected(notifier.device_address()).await;
+            if let Some(central) = self.centrals.lock().await.get_mut(&link_id) {
+                central.notifier = Some(notifier);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use tokio::sync::mpsc;
+
+    use super::super::Inbound;
+    use crate::error::{AppError, AppResult, ErrorCode};
+
+    pub struct BlePeripheral;
+
+    pub async fn supported() -> bool {
+        false
+    }
+
+    impl BlePeripheral {
+        pub async fn start(_inbound: mpsc::UnboundedSender<Inbound>) -> AppResult<Self> {
+            Err(AppError::new(ErrorCode::Unsupported).with("feature", "ble_peripheral"))
+        }
+
+        pub async fn send(&self, link_id: &str, _data: &[u8]) -> AppResult<()> {
+            Err(AppError::new(ErrorCode::UnknownLink).with("link_id", link_id))
+        }
+
+        pub async fn broadcast(&self, _data: &[u8], _exclude: Option<&str>) {}
+    }
+}
+
+pub use imp::BlePeripheral;
+
+#[derive(Default)]
+pub struct PeripheralState(pub(crate) tokio::sync::Mutex<Option<BlePeripheral>>);
+
+#[tauri::command]
+#[specta::specta]
+pub async fn ble_get_capabilities() -> AppResult<BleCapabilities> {
+    Ok(BleCapabilities {
+        central: super::ble::supported().await,
+        peripheral: imp::supported().await,
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn ble_start_advertising(
+    app: AppHandle,
+    state: State<'_, PeripheralState>,
+) -> AppResult<()> {
+    let mut peripheral = state.0.lock().await;
+    if peripheral.is_some() {
+        return Ok(());
+    }
+    *peripheral = Some(BlePeripheral::start(mesh::inbound(&app)).await?);
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn ble_stop_advertising(state: State<'_, PeripheralState>) -> AppResult<()> {
+    // Dropping the handles unregisters the advertisement and GATT service.
+    state.0.lock().await.take();
+    Ok(())
+}
@@ -0,0 +1,489 @@
+//! Outgoing private messages and their delivery state.
+//!
+//! Every message from [`transport_send_message`] is tracked here until its
+//! window (`settings.delivery.expire_after_secs`) runs out. A message no
+//! transport could take stays queued and is retried; a transport whose
+//! send fails is backed off exponentially for that message while the
+//! others are tried. Transports are best effort, so a sent message that
+//! isn't acknowledged within [`ACK_TIMEOUT_MS`] is sent again, and one
+//! still unacknowledged when its window runs out has failed. Status
+//! changes go to the frontend as
+//! [`TransportEvent::DeliveryStatusChanged`] so it can render ticks.
+//!
+//! Delivered messages stay tracked too, so a later read receipt can still
+//! be matched to them. Receipts come back over the mesh (see
+//! [`crate::mesh::receipts`]) or Nostr, decrypted by the frontend, which
+//! reports them through [`transport_mark_delivered`] and
+//! [`transport_mark_read`].
+//!
+//! How far a message got is also written to its stored copy (the
+//! frontend saves it under the ID [`transport_send_message`] returns), so
+//! it outlives the window and restarts, and receipts arriving after the
+//! window still count.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Serialize;
+use specta::Type;
+use tauri::{AppHandle, Manager, State};
+
+use super::router::{OutgoingMessage, Recipient, Router, TransportKind};
+use super::TransportEvent;
+use crate::error::AppResult;
+use crate::events;
+use crate::mesh::now_ms;
+use crate::settings::SettingsState;
+use crate::storage::{Delivery, StorageState};
+
+/// How often queued messages are retried.
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+pub const INITIAL_BACKOFF_MS: u64 = 1_000;
+pub const MAX_BACKOFF_MS: u64 = 5 * 60_000;
+/// How long a sent message may go unacknowledged before it's resent.
+pub const ACK_TIMEOUT_MS: u64 = 30_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Type)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Waiting for a transport that can reach the peer.
+    Queued,
+    /// Handed to a transport; not yet confirmed by the peer.
+    Sent {
+        transport: TransportKind,
+    },
+    Delivered,
+    /// The peer has seen it.
+    Read,
+    /// Not acknowledged within the delivery window.
+    Failed,
+}
+
+impl From<Delivery> for DeliveryStatus {
+    fn from(delivery: Delivery) -> Self {
+        match delivery {
+            Delivery::Delivered => DeliveryStatus::Delivered,
+            Delivery::Read => DeliveryStatus::Read,
+            Delivery::Failed => DeliveryStatus::Failed,
+        }
+    }
+}
+
+struct Entry {
+    recipient: Recipient,
+    content: String,
+    status: DeliveryStatus,
+    created_at: u64,
+    /// When it was last handed to a transport.
+    sent_at: Option<u64>,
+    /// Transport -> (consecutive failures, don't retry before).
+    backoff: HashMap<TransportKind, (u32, u64)>,
+}
+
+/// A queued message that's due for another try.
+pub struct Attempt {
+    pub message: OutgoingMessage,
+    /// Transports still backing off for this message.
+    pub blocked: Vec<TransportKind>,
+}
+
+pub struct DeliveryQueue {
+    entries: BTreeMap<String, Entry>,
+    window_ms: u64,
+}
+
+impl DeliveryQueue {
+    pub fn new(window_ms: u64) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            window_ms,
+        }
+    }
+
+    pub fn set_window(&mut self, window_ms: u64) {
+        self.window_ms = window_ms;
+    }
+
+    pub fn enqueue(&mut self, message: &OutgoingMessage, now: u64) {
+        self.entries.insert(
+            message.id.clone(),
+            Entry {
+                recipient: message.recipient,
+                content: message.content.clone(),
+                status: DeliveryStatus::Queued,
+                created_at: now,
+                sent_at: None,
+                backoff: HashMap::new(),
+            },
+        );
+    }
+
+    pub fn status(&self, id: &str) -> Option<DeliveryStatus> {
+        self.entries.get(id).map(|e| e.status)
+    }
+
+    /// Queued messages, and sent ones whose ack is overdue, with the
+    /// transports each may not use yet.
+    pub fn due(&self, now: u64) -> Vec<Attempt> {
+        self.entries
+            .iter()
+            .filter(|(_, e)| match e.status {
+                DeliveryStatus::Queued => true,
+                DeliveryStatus::Sent { .. } => e
+                    .sent_at
+                    .is_some_and(|at| now.saturating_sub(at) >= ACK_TIMEOUT_MS),
+                _ => false,
+            })
+            .map(|(id, e)| Attempt {
+                message: OutgoingMessage {
+                    id: id.clone(),
+                    recipient: e.recipient,
+                    content: e.content.clone(),
+                },
+                blocked: e
+                    .backoff
+                    .iter()
+                    .filter(|(_, (_, until))| now < *until)
+                    .map(|(kind, _)| *kind)
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Record the outcome of an attempt. Returns the new status if it
+    /// changed.
+    pub fn attempted(
+        &mut self,
+        id: &str,
+        sent: Option<TransportKind>,
+        failed: &[TransportKind],
+        now: u64,
+    ) -> Option<DeliveryStatus> {
+        let entry = self.entries.get_mut(id)?;
+        for kind in failed {
+            let (failures, until) = entry.backoff.entry(*kind).or_insert((0, 0));
+            *failures += 1;
+            *until = now + backoff(*failures);
+        }
+        let transport = sent?;
+        entry.backoff.remove(&transport);
+        entry.sent_at = Some(now);
+        let status = DeliveryStatus::Sent { transport };
+        if entry.status == status {
+            return None;
+        }
+        entry.status = status;
+        Some(status)
+    }
+
+    /// The peer confirmed `id`. Returns the new status if it changed.
+    pub fn delivered(&mut self, id: &str) -> Option<DeliveryStatus> {
+        let entry = self.entries.get_mut(id)?;
+        if matches!(
+            entry.status,
+            DeliveryStatus::Delivered | DeliveryStatus::Read
+        ) {
+            return None;
+        }
+        entry.status = DeliveryStatus::Delivered;
+        entry.backoff.clear();
+        Some(entry.status)
+    }
+
+    /// The peer read `id`, which implies it was delivered. Returns the new
+    /// status if it changed.
+    pub fn read(&mut self, id: &str) -> Option<DeliveryStatus> {
+        let entry = self.entries.get_mut(id)?;
+        if entry.status == DeliveryStatus::Read {
+            return None;
+        }
+        entry.status = DeliveryStatus::Read;
+        entry.backoff.clear();
+        Some(entry.status)
+    }
+
+    /// Stop tracking messages older than the window. Returns the ones that
+    /// were never acknowledged, which have now failed.
+    pub fn expire(&mut self, now: u64) -> Vec<String> {
+        let mut failed = Vec::new();
+        let window_ms = self.window_ms;
+        self.entries.retain(|id, e| {
+            let keep = now.saturating_sub(e.created_at) < window_ms;
+            let acked = matches!(e.status, DeliveryStatus::Delivered | DeliveryStatus::Read);
+            if !keep && !acked {
+                failed.push(id.clone());
+            }
+            keep
+        });
+        failed
+    }
+}
+
+/// Wait after `failures` consecutive failures on one transport.
+fn backoff(failures: u32) -> u64 {
+    INITIAL_BACKOFF_MS
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(MAX_BACKOFF_MS)
+}
+
+pub struct DeliveryState(Mutex<DeliveryQueue>);
+
+fn window_ms(app: &AppHandle) -> u64 {
+    app.state::<SettingsState>()
+        .get()
+        .delivery
+        .expire_after_secs
+        .saturating_mul(1000)
+}
+
+/// Start the retry loop. Needs the settings to be loaded.
+pub fn start(app: &AppHandle) {
+    let queue = DeliveryQueue::new(window_ms(app));
+    app.manage(DeliveryState(Mutex::new(queue)));
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(RETRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            retry(&app);
+        }
+    });
+}
+
+fn emit_status(app: &AppHandle, message_id: &str, status: DeliveryStatus) {
+    events::emit(
+        app,
+        TransportEvent::DeliveryStatusChanged {
+            message_id: message_id.to_string(),
+            status,
+        },
+    );
+}
+
+/// Record how far `message_id` got on its stored copy, and tell the
+/// frontend if that or the tracked status (`tracked`) changed.
+fn settle(app: &AppHandle, message_id: &str, delivery: Delivery, tracked: Option<DeliveryStatus>) {
+    let stored = app
+        .state::<StorageState>()
+        .with(|store| store.set_delivery(message_id, delivery))
+        .unwrap_or_else(|e| {
+            tracing::debug!("couldn't store delivery of {}: {}", message_id, e);
+            false
+        });
+    if tracked.is_some() || stored {
+        emit_status(app, message_id, delivery.into());
+    }
+}
+
+/// Expire old messages and try the queued and unacknowledged ones again.
+pub fn retry(app: &AppHandle) {
+    let now = now_ms();
+    let state = app.state::<DeliveryState>();
+    let (failed, due) = {
+        let mut queue = state.0.lock().unwrap();
+        queue.set_window(window_ms(app));
+        (queue.expire(now), queue.due(now))
+    };
+    for id in failed {
+        settle(app, &id, Delivery::Failed, Some(DeliveryStatus::Failed));
+    }
+    for attempt in due {
+        try_send(app, &attempt.message, &attempt.blocked, now);
+    }
+}
+
+fn try_send(app: &AppHandle, message: &OutgoingMessage, blocked: &[TransportKind], now: u64) {
+    let (sent, failed) = app.state::<Router>().attempt(app, message, blocked);
+    let state = app.state::<DeliveryState>();
+    let changed = state
+        .0
+        .lock()
+        .unwrap()
+        .attempted(&message.id, sent, &failed, now);
+    if let Some(status) = changed {
+        emit_status(app, &message.id, status);
+    }
+}
+
+/// Mark `message_id` delivered, e.g. on an acknowledgement.
+pub fn mark_delivered(app: &AppHandle, message_id: &str) {
+    let changed = app
+        .state::<DeliveryState>()
+        .0
+        .lock()
+        .unwrap()
+        .delivered(message_id);
+    settle(app, message_id, Delivery::Delivered, changed);
+}
+
+/// Mark `message_id` read, on a read receipt.
+pub fn mark_read(app: &AppHandle, message_id: &str) {
+    let changed = app
+        .state::<DeliveryState>()
+        .0
+        .lock()
+        .unwrap()
+        .read(message_id);
+    settle(app, message_id, Delivery::Read, changed);
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct SentMessage {
+    pub message_id: String,
+    pub status: DeliveryStatus,
+}
+
+/// Send a private message to `peer_id` over the best transport available,
+/// queueing it if none can reach the peer right now. `peer_id` is a mesh
+/// peer ID or a Noise public key (see [`Recipient`]).
+#[tauri::command]
+#[specta::specta]
+pub fn transport_send_message(
+    app: AppHandle,
+    peer_id: String,
+    content: String,
+    state: State<'_, DeliveryState>,
+) -> AppResult<SentMessage> {
+    let message = OutgoingMessage {
+        id: hex::encode(rand::random::<[u8; 16]>()),
+        recipient: peer_id.parse()?,
+        content,
+    };
+    let now = now_ms();
+    state.0.lock().unwrap().enqueue(&message, now);
+    try_send(&app, &message, &[], now);
+    let status = state
+        .0
+        .lock()
+        .unwrap()
+        .status(&message.id)
+        .unwrap_or(DeliveryStatus::Queued);
+    Ok(SentMessage {
+        message_id: message.id,
+        status,
+    })
+}
+
+/// Mark a message delivered on an acknowledgement the frontend decrypted
+/// (from the mesh or Nostr).
+#[tauri::command]
+#[specta::specta]
+pub fn transport_mark_delivered(app: AppHandle, message_id: String) {
+    mark_delivered(&app, &message_id);
+}
+
+/// Mark a message read on a read receipt the frontend decrypted.
+#[tauri::command]
+#[specta::specta]
+pub fn transport_mark_read(app: AppHandle, message_id: String) {
+    mark_read(&app, &message_id);
+}
+
+/// The delivery state of a message, if known: tracked while it's within
+/// its window, and from its stored copy after.
+#[tauri::command]
+#[specta::specta]
+pub fn transport_get_status(
+    message_id: String,
+    state: State<'_, DeliveryState>,
+    storage: State<'_, StorageState>,
+) -> Option<DeliveryStatus> {
+    if let Some(status) = state.0.lock().unwrap().status(&message_id) {
+        return Some(status);
+    }
+    let delivery = storage.with(|store| store.delivery(&message_id)).ok()??;
+    Some(delivery.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::packet::PeerId;
+
+    fn message(id: &str) -> OutgoingMessage {
+        OutgoingMessage {
+            id: id.to_string(),
+            recipient: Recipient::Peer(PeerId([1; 8])),
+            content: "hi".to_string(),
+        }
+    }
+
+    #[test]
+    fn failing_transports_back_off_per_message() {
+        let mut queue = DeliveryQueue::new(60_000);
+        queue.enqueue(&message("a"), 0);
+        assert_eq!(queue.attempted("a", None, &[TransportKind::Mesh], 0), None);
+        assert_eq!(queue.due(0)[0].blocked, [TransportKind::Mesh]);
+        assert!(queue.due(INITIAL_BACKOFF_MS)[0].blocked.is_empty());
+
+        queue.attempted("a", None, &[TransportKind::Mesh], 0);
+        assert_eq!(
+            queue.due(INITIAL_BACKOFF_MS)[0].blocked,
+            [TransportKind::Mesh]
+        );
+
+        let sent = DeliveryStatus::Sent {
+            transport: TransportKind::Nostr,
+        };
+        assert_eq!(
+            queue.attempted("a", Some(TransportKind::Nostr), &[], 0),
+            Some(sent)
+        );
+        assert!(queue.due(0).is_empty());
+        assert_eq!(queue.delivered("a"), Some(DeliveryStatus::Delivered));
+        assert_eq!(queue.delivered("a"), None);
+    }
+
+    #[test]
+    fn read_receipts_outrank_delivery_acks() {
+        let mut queue = DeliveryQueue::new(60_000);
+        queue.enqueue(&message("a"), 0);
+        queue.attempted("a", Some(TransportKind::Mesh), &[], 0);
+        assert_eq!(queue.delivered("a"), Some(DeliveryStatus::Delivered));
+        assert_eq!(queue.read("a"), Some(DeliveryStatus::Read));
+        // A late ack doesn't take the message back to delivered.
+        assert_eq!(queue.delivered("a"), None);
+        assert_eq!(queue.read("a"), None);
+        assert_eq!(queue.status("a"), Some(DeliveryStatus::Read));
+        assert_eq!(queue.read("unknown"), None);
+    }
+
+    #[test]
+    fn unacknowledged_messages_fail_after_the_window() {
+        let mut queue = DeliveryQueue::new(1_000);
+        queue.enqueue(&message("queued"), 0);
+        queue.enqueue(&message("sent"), 0);
+        queue.enqueue(&message("delivered"), 0);
+        queue.attempted("sent", Some(TransportKind::Mesh), &[], 0);
+        queue.attempted("delivered", Some(TransportKind::Mesh), &[], 0);
+        queue.delivered("delivered");
+        assert!(queue.expire(999).is_empty());
+        assert_eq!(queue.expire(1_000), ["queued", "sent"]);
+        assert_eq!(queue.status("delivered"), None);
+    }
+
+    #[test]
+    fn unacknowledged_messages_are_resent() {
+        let mut queue = DeliveryQueue::new(10 * ACK_TIMEOUT_MS);
+        queue.enqueue(&message("a"), 0);
+        let sent = DeliveryStatus::Sent {
+            transport: TransportKind::Mesh,
+        };
+        assert_eq!(
+            queue.attempted("a", Some(TransportKind::Mesh), &[], 0),
+            Some(sent)
+        );
+        assert!(queue.due(ACK_TIMEOUT_MS - 1).is_empty());
+        assert_eq!(queue.due(ACK_TIMEOUT_MS).len(), 1);
+
+        // Resending doesn't change the status, but restarts the clock.
+        assert_eq!(
+            queue.attempted("a", Some(TransportKind::Mesh), &[], ACK_TIMEOUT_MS),
+            None
+        );
+        assert!(queue.due(ACK_TIMEOUT_MS + 1).is_empty());
+        queue.delivered("a");
+        assert!(queue.due(3 * ACK_TIMEOUT_MS).is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+//! Local network transport for desktop nodes on the same LAN.
+//!
+//! Each node listens on a TCP port and advertises it over mDNS as
+//! `_bitchat._tcp`, with a random instance ID in the TXT record. When two
+//! nodes find each other, the one with the lower instance ID connects, so
+//! each pair ends up with a single link. Frames are the same packets the
+//! BLE links carry (Noise-encrypted where the frontend has a session),
+//! each prefixed with its length as a big-endian `u32`.
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+use specta::Type;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, State};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+use super::Inbound;
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::mesh;
+
+pub const SERVICE_TYPE: &str = "_bitchat._tcp.local.";
+/// Link IDs of LAN links start with this, to tell them from BLE ones.
+pub const LINK_PREFIX: &str = "lan:";
+/// Larger frames are treated as a broken stream.
+const MAX_FRAME: usize = 1 << 20;
+const TXT_ID: &str = "id";
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct LanLink {
+    pub link_id: String,
+    pub address: String,
+}
+
+struct Link {
+    info: LanLink,
+    outbound: mpsc::UnboundedSender<Vec<u8>>,
+    tasks: [JoinHandle<()>; 2],
+}
+
+struct Shared {
+    instance_id: String,
+    links: Mutex<HashMap<String, Link>>,
+    /// Instance IDs we've connected to or are connecting to.
+    peers: Mutex<HashSet<String>>,
+    inbound: mpsc::UnboundedSender<Inbound>,
+}
+
+pub struct LanTransport {
+    shared: Arc<Shared>,
+    mdns: ServiceDaemon,
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl LanTransport {
+    /// Listen, advertise ourselves and connect to the nodes we discover.
+    /// Links and frames are reported to `inbound`.
+    pub async fn start(inbound: mpsc::UnboundedSender<Inbound>) -> AppResult<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", 0)).await?;
+        let port = listener.local_addr()?.port();
+        let instance_id = hex::encode(rand::random::<[u8; 8]>());
+
+        let mdns = ServiceDaemon::new().map_err(AppError::platform)?;
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &instance_id,
+            &format!("bitchat-{}.local.", instance_id),
+            "",
+            port,
+            &[(TXT_ID, instance_id.as_str())][..],
+        )
+        .map_err(AppError::platform)?
+        .enable_addr_auto();
+        mdns.register(service).map_err(AppError::platform)?;
+        let browser = mdns.browse(SERVICE_TYPE).map_err(AppError::platform)?;
+
+        let shared = Arc::new(Shared {
+            instance_id,
+            links: Mutex::new(HashMap::new()),
+            peers: Mutex::new(HashSet::new()),
+            inbound,
+        });
+        let acceptor = tauri::async_runtime::spawn({
+            let shared = shared.clone();
+            async move {
+                while let Ok((stream, address)) = listener.accept().await {
+                    shared.clone().open(stream, address, None);
+                }
+            }
+        });
+        let discovery = tauri::async_runtime::spawn({
+            let shared = shared.clone();
+            async move {
+                while let Ok(event) = browser.recv_async().await {
+                    if let ServiceEvent::ServiceResolved(info) = event {
+                        shared.clone().discovered(&info);
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            shared,
+            mdns,
+            tasks: [acceptor, discovery],
+        })
+    }
+
+    pub async fn stop(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        if let Err(e) = self.mdns.shutdown() {
+            tracing::debug!("failed to stop mDNS: {}", e);
+        }
+        let link_ids: Vec<String> = self.shared.links.lock().unwrap().keys().cloned().collect();
+        for link_id in link_ids {
+            self.shared.drop_link(&link_id);
+        }
+    }
+
+    pub fn links(&self) -> Vec<LanLink> {
+        self.shared
+            .links
+            .lock()
+            .unwrap()
+            .values()
+            .map(|link| link.info.clone())
+            .collect()
+    }
+
+    pub fn send(&self, link_id: &str, data: &[u8]) -> AppResult<()> {
+        let links = self.shared.links.lock().unwrap();
+        let link = links
+            .get(link_id)
+            .ok_or_else(|| AppError::new(ErrorCode::UnknownLink).with("link_id", link_id))?;
+        link.outbound
+            .send(data.to_vec())
+            .map_err(|_| AppError::new(ErrorCode::UnknownLink).with("link_id", link_id))
+    }
+
+    /// Queue `data` on every link except `exclude`.
+    pub fn broadcast(&self, data: &[u8], exclude: Option<&str>) {
+        for (link_id, link) in self.shared.links.lock().unwrap().iter() {
+            if Some(link_id.as_str()) != exclude {
+                let _ = link.outbound.send(data.to_vec());
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn discovered(self: Arc<Self>, info: &ServiceInfo) {
+        let Some(peer) = info.get_property_val_str(TXT_ID).map(str::to_string) else {
+            return;
+        };
+        // The lower ID dials; the other side waits to be dialed.
+        if peer <= self.instance_id || !self.peers.lock().unwrap().insert(peer.clone()) {
+            return;
+        }
+        let addresses: Vec<IpAddr> = info.get_addresses().iter().copied().collect();
+        let port = info.get_port();
+        tauri::async_runtime::spawn(async move {
+            for ip in addresses {
+                let address = SocketAddr::new(ip, port);
+                match TcpStream::connect(address).await {
+                    Ok(stream) => {
+                        self.open(stream, address, Some(peer));
+                        return;
+                    }
+                    Err(e) => tracing::debug!("LAN connect to {} failed: {}", address, e),
+                }
+            }
+            self.peers.lock().unwrap().remove(&peer);
+        });
+    }
+
+    fn open(self: Arc<Self>, stream: TcpStream, address: SocketAddr, peer: Option<String>) {
+        let _ = stream.set_nodelay(true);
+        let link_id = format!("{}{}", LINK_PREFIX, address);
+        let (mut reader, mut writer) = stream.into_split();
+        let (outbound, mut queue) = mpsc::unbounded_channel::<Vec<u8>>();
+
+        let write_task = tauri::async_runtime::spawn(async move {
+            while let Some(data) = queue.recv().await {
+                let len = (data.len() as u32).to_be_bytes();
+                if writer.write_all(&len).await.is_err() || writer.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let _ = self.inbound.send(Inbound::LinkUp {
+            link_id: link_id.clone(),
+            name: None,
+        });
+        // Hold the lock until the link is in the map, so a connection that
+        // closes straight away still finds it there to drop.
+        let mut links = self.links.lock().unwrap();
+        let read_task = tauri::async_runtime::spawn({
+            let shared = self.clone();
+            let link_id = link_id.clone();
+            async move {
+                loop {
+                    let mut len = [0u8; 4];
+                    if reader.read_exact(&mut len).await.is_err() {
+                        break;
+                    }
+                    let len = u32::from_be_bytes(len) as usize;
+                    if len > MAX_FRAME {
+                        tracing::debug!("oversized LAN frame from {}", link_id);
+                        break;
+                    }
+                    let mut data = vec![0u8; len];
+                    if reader.read_exact(&mut data).await.is_err() {
+                        break;
+                    }
+                    let frame = Inbound::Frame {
+                        link_id: link_id.clone(),
+                        data,
+                    };
+                    if shared.inbound.send(frame).is_err() {
+                        break;
+                    }
+                }
+                shared.drop_link(&link_id);
+                if let Some(peer) = peer {
+                    shared.peers.lock().unwrap().remove(&peer);
+                }
+            }
+        });
+        links.insert(
+            link_id.clone(),
+            Link {
+                info: LanLink {
+                    link_id,
+                    address: address.to_string(),
+                },
+                outbound,
+                tasks: [read_task, write_task],
+            },
+        );
+    }
+
+    fn drop_link(&self, link_id: &str) {
+        let Some(link) = self.links.lock().unwrap().remove(link_id) else {
+            return;
+        };
+        for task in &link.tasks {
+            task.abort();
+        }
+        let _ = self.inbound.send(Inbound::LinkDown {
+            link_id: link_id.to_string(),
+        });
+    }
+}
+
+#[derive(Default)]
+pub struct LanState(pub(crate) tokio::sync::Mutex<Option<LanTransport>>);
+
+#[tauri::command]
+#[specta::specta]
+pub async fn lan_start(app: AppHandle, state: State<'_, LanState>) -> AppResult<()> {
+    let mut lan = state.0.lock().await;
+    if lan.is_some() {
+        return Ok(());
+    }
+    *lan = Some(LanTransport::start(mesh::inbound(&app)).await?);
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn lan_stop(state: State<'_, LanState>) -> AppResult<()> {
+    if let Some(lan) = state.0.lock().await.take() {
+        lan.stop().await;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn lan_get_links(state: State<'_, LanState>) -> AppResult<Vec<LanLink>> {
+    Ok(state
+        .0
+        .lock()
+        .await
+        .as_ref()
+        .map(LanTransport::links)
+        .unwrap_or_default())
+}
@@ -0,0 +1,144 @@
+//! Native transports for the offline mesh.
+//!
+//! Transports report link changes and raw frames as [`Inbound`] messages on
+//! the channel from [`crate::mesh::inbound`]; the mesh node decides what to
+//! do with them. Outbound frames go through [`broadcast`] and [`send_to`].
+//! Besides BLE, desktop nodes on the same network link up directly over
+//! [`lan`], and browser-only peers over [`webrtc`] data channels.
+//!
+//! Private messages are routed per peer by [`router`], which may also hand
+//! them to the frontend's Nostr relays, and tracked until delivered by
+//! [`delivery`].
+
+pub mod ble;
+pub mod ble_peripheral;
+pub mod delivery;
+pub mod lan;
+pub mod router;
+pub mod scan;
+pub mod webrtc;
+
+use serde::Serialize;
+use specta::Type;
+use tauri::{AppHandle, Manager};
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events::BackendEvent;
+use delivery::DeliveryStatus;
+
+#[derive(Debug, Clone)]
+pub enum Inbound {
+    LinkUp {
+        link_id: String,
+        name: Option<String>,
+    },
+    LinkDown {
+        link_id: String,
+    },
+    /// Fresh signal strength reading for a link, in dBm.
+    Rssi {
+        link_id: String,
+        rssi: i16,
+    },
+    /// Bytes received on a link, exactly as they came off the wire.
+    Frame {
+        link_id: String,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransportEvent {
+    LinkUp {
+        link_id: String,
+        name: Option<String>,
+    },
+    LinkDown {
+        link_id: String,
+    },
+    /// A private message routed over the mesh. The frontend encrypts it
+    /// with its Noise session with `peer_id` and sends the ciphertext with
+    /// `mesh_send_private`.
+    MeshSend {
+        message_id: String,
+        peer_id: String,
+        content: String,
+    },
+    /// A private message routed over Nostr. The frontend gift-wraps it for
+    /// `recipient_npub` and publishes it to its relays.
+    NostrSend {
+        message_id: String,
+        peer_id: String,
+        recipient_npub: String,
+        content: String,
+    },
+    DeliveryStatusChanged {
+        message_id: String,
+        status: DeliveryStatus,
+    },
+    /// A WebRTC offer or answer for `npub`. The frontend sends it over
+    /// Nostr; the reply goes to `webrtc_signal`.
+    WebrtcSignal {
+        link_id: String,
+        npub: String,
+        kind: webrtc::SignalKind,
+        sdp: String,
+    },
+}
+
+impl From<TransportEvent> for BackendEvent {
+    fn from(event: TransportEvent) -> Self {
+        BackendEvent::Transport(event)
+    }
+}
+
+/// Send a frame on every link of every running transport, except `exclude`.
+pub async fn broadcast(app: &AppHandle, data: &[u8], exclude: Option<&str>) {
+    if let Some(central) = app.state::<ble::BleState>().0.lock().await.as_ref() {
+        central.broadcast(data, exclude).await;
+    }
+    if let Some(peripheral) = app
+        .state::<ble_peripheral::PeripheralState>()
+        .0
+        .lock()
+        .await
+        .as_ref()
+    {
+        peripheral.broadcast(data, exclude).await;
+    }
+    if let Some(lan) = app.state::<lan::LanState>().0.lock().await.as_ref() {
+        lan.broadcast(data, exclude);
+    }
+    app.state::<webrtc::WebrtcState>()
+        .broadcast(data, exclude)
+        .await;
+}
+
+pub async fn send_to(app: &AppHandle, link_id: &str, data: &[u8]) -> AppResult<()> {
+    if link_id.starts_with(lan::LINK_PREFIX) {
+        return match app.state::<lan::LanState>().0.lock().await.as_ref() {
+            Some(lan) => lan.send(link_id, data),
+            None => Err(AppError::new(ErrorCode::UnknownLink).with("link_id", link_id)),
+        };
+    }
+    if link_id.starts_with(ble_peripheral::LINK_PREFIX) {
+        return match app
+            .state::<ble_peripheral::PeripheralState>()
+            .0
+            .lock()
+            .await
+            .as_ref()
+        {
+            Some(peripheral) => peripheral.send(link_id, data).await,
+            None => Err(AppError::new(ErrorCode::UnknownLink).with("link_id", link_id)),
+        };
+    }
+    if link_id.starts_with(webrtc::LINK_PREFIX) {
+        return app.state::<webrtc::WebrtcState>().send(link_id, data).await;
+    }
+    match app.state::<ble::BleState>().0.lock().await.as_ref() {
+        Some(central) => central.send(link_id, data).await,
+        None => Err(AppError::new(ErrorCode::UnknownLink).with("link_id", link_id)),
+    }
+}
@@ -0,0 +1,201 @@
+//! Picks a transport for each outgoing private message.
+//!
+//! Transports are tried in order of preference: the mesh whenever the peer
+//! is nearby and we have a Noise session with it, then Nostr if the peer is
+//! a mutual favorite whose npub we know (see [`crate::favorites`]).
+//! Messages are only ever encrypted in the frontend, which holds both the
+//! Noise sessions and the Nostr relays, so each transport hands the message
+//! over as an event: [`TransportEvent::MeshSend`] for it to seal with the
+//! session, [`TransportEvent::NostrSend`] for it to gift-wrap and publish.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Serialize;
+use specta::Type;
+use tauri::AppHandle;
+
+use super::TransportEvent;
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events;
+use crate::favorites;
+use crate::mesh::sessions::SessionStatus;
+use crate::mesh::{self, packet::PeerId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportKind {
+    Mesh,
+    Nostr,
+}
+
+/// Who a message is for: a mesh peer ID (16 hex digits) or, for peers that
+/// may be out of range, their Noise static public key (64 hex digits).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recipient {
+    Peer(PeerId),
+    NoiseKey([u8; 32]),
+}
+
+impl FromStr for Recipient {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> AppResult<Self> {
+        if s.len() == 64 {
+            let mut key = [0u8; 32];
+            hex::decode_to_slice(s, &mut key)
+                .map_err(|_| AppError::new(ErrorCode::InvalidPeerId).with("peer_id", s))?;
+            Ok(Recipient::NoiseKey(key))
+        } else {
+            s.parse().map(Recipient::Peer)
+        }
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recipient::Peer(peer_id) => peer_id.fmt(f),
+            Recipient::NoiseKey(key) => f.write_str(&hex::encode(key)),
+        }
+    }
+}
+
+pub struct OutgoingMessage {
+    pub id: String,
+    pub recipient: Recipient,
+    pub content: String,
+}
+
+pub trait Transport: Send + Sync {
+    fn kind(&self) -> TransportKind;
+    /// Whether `recipient` can be reached this way right now.
+    fn reachable(&self, app: &AppHandle, recipient: &Recipient) -> bool;
+    fn send(&self, app: &AppHandle, message: &OutgoingMessage) -> AppResult<()>;
+}
+
+pub struct MeshTransport;
+
+impl MeshTransport {
+    /// The mesh peer currently behind `recipient`, if it's nearby and its
+    /// Noise session is established.
+    fn peer_id(app: &AppHandle, recipient: &Recipient) -> Option<PeerId> {
+        let peer_id = match recipient {
+            Recipient::Peer(peer_id) => *peer_id,
+            Recipient::NoiseKey(key) => mesh::find_peer(app, key)?,
+        };
+        let peer = mesh::peer(app, &peer_id)?;
+        (peer.session == Some(SessionStatus::Ready)).then_some(peer_id)
+    }
+}
+
+impl Transport for MeshTransport {
+    fn kind(&self) -> TransportKind {
+        TransportKind::Mesh
+    }
+
+    fn reachable(&self, app: &AppHandle, recipient: &Recipient) -> bool {
+        Self::peer_id(app, recipient).is_some()
+    }
+
+    fn send(&self, app: &AppHandle, message: &OutgoingMessage) -> AppResult<()> {
+        let peer_id = Self::peer_id(app, &message.recipient).ok_or_else(|| {
+            AppError::new(ErrorCode::PeerUnreachable).with("peer_id", message.recipient)
+        })?;
+        events::emit(
+            app,
+            TransportEvent::MeshSend {
+                message_id: message.id.clone(),
+                peer_id: peer_id.to_string(),
+                content: message.content.clone(),
+            },
+        );
+        Ok(())
+    }
+}
+
+pub struct NostrTransport;
+
+impl Transport for NostrTransport {
+    fn kind(&self) -> TransportKind {
+        TransportKind::Nostr
+    }
+
+    fn reachable(&self, app: &AppHandle, recipient: &Recipient) -> bool {
+        favorites::nostr_route(app, recipient).is_some()
+    }
+
+    fn send(&self, app: &AppHandle, message: &OutgoingMessage) -> AppResult<()> {
+        let recipient_npub = favorites::nostr_route(app, &message.recipient).ok_or_else(|| {
+            AppError::new(ErrorCode::PeerUnreachable).with("peer_id", message.recipient)
+        })?;
+        events::emit(
+            app,
+            TransportEvent::NostrSend {
+                message_id: message.id.clone(),
+                peer_id: message.recipient.to_string(),
+                recipient_npub,
+                content: message.content.clone(),
+            },
+        );
+        Ok(())
+    }
+}
+
+pub struct Router {
+    /// In order of preference.
+    transports: Vec<Box<dyn Transport>>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self {
+            transports: vec![Box::new(MeshTransport), Box::new(NostrTransport)],
+        }
+    }
+}
+
+impl Router {
+    /// Offer `message` to each reachable transport in turn, skipping
+    /// `blocked`, until one takes it. Returns that transport and the ones
+    /// whose send failed.
+    pub fn attempt(
+        &self,
+        app: &AppHandle,
+        message: &OutgoingMessage,
+        blocked: &[TransportKind],
+    ) -> (Option<TransportKind>, Vec<TransportKind>) {
+        let mut failed = Vec::new();
+        for transport in &self.transports {
+            let kind = transport.kind();
+            if blocked.contains(&kind) || !transport.reachable(app, &message.recipient) {
+                continue;
+            }
+            match transport.send(app, message) {
+                Ok(()) => return (Some(kind), failed),
+                Err(e) => {
+                    tracing::debug!("{:?} send to {} failed: {}", kind, message.recipient, e);
+                    failed.push(kind);
+                }
+            }
+        }
+        (None, failed)
+    }
+
+    /// Send a one-off message without delivery tracking.
+    pub fn send(
+        &self,
+        app: &AppHandle,
+        recipient: Recipient,
+        content: String,
+    ) -> AppResult<TransportKind> {
+        let message = OutgoingMessage {
+            id: hex::encode(rand::random::<[u8; 16]>()),
+            recipient,
+            content,
+        };
+        self.attempt(app, &message, &[])
+            .0
+            .ok_or_else(|| AppError::new(ErrorCode::PeerUnreachable).with("peer_id", recipient))
+    }
+}
@@ -0,0 +1,118 @@
+//! When the BLE central scans.
+//!
+//! Scanning is the radio's biggest drain, so it's duty-cycled: continuous
+//! (or nearly) while the main window is in front or peers were seen in the
+//! last [`RECENT_ACTIVITY`], slow otherwise. How slow depends on the
+//! `ble.power_mode` setting. Connected links stay up while the scan is
+//! paused.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{AppHandle, Manager, Window, WindowEvent};
+
+use crate::settings::SettingsState;
+use crate::windows::MAIN_WINDOW;
+
+/// Peers seen this recently keep the scan aggressive.
+pub const RECENT_ACTIVITY: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerMode {
+    /// Find peers as fast as possible.
+    Performance,
+    #[default]
+    Balanced,
+    /// Scan sparingly, even in the foreground.
+    Saver,
+}
+
+/// Scan for `on`, then pause for `off`. A zero `off` means scan
+/// continuously.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DutyCycle {
+    pub on: Duration,
+    pub off: Duration,
+}
+
+const fn cycle(on_secs: u64, off_secs: u64) -> DutyCycle {
+    DutyCycle {
+        on: Duration::from_secs(on_secs),
+        off: Duration::from_secs(off_secs),
+    }
+}
+
+/// The duty cycle for `mode`. `active` means the app is in the foreground
+/// or peers were seen recently.
+pub fn duty_cycle(mode: PowerMode, active: bool) -> DutyCycle {
+    match (mode, active) {
+        (PowerMode::Performance, true) => cycle(10, 0),
+        (PowerMode::Performance, false) => cycle(10, 5),
+        (PowerMode::Balanced, true) => cycle(10, 0),
+        (PowerMode::Balanced, false) => cycle(5, 25),
+        (PowerMode::Saver, true) => cycle(5, 10),
+        (PowerMode::Saver, false) => cycle(3, 57),
+    }
+}
+
+/// Whether the main window has focus. Set by [`init`] once the window is
+/// shown or hidden at startup.
+#[derive(Default)]
+pub struct Foreground(AtomicBool);
+
+/// Start out in front only if the main window is showing; it's hidden when
+/// the app starts in background mode.
+pub fn init(app: &AppHandle) {
+    let visible = app
+        .get_webview_window(MAIN_WINDOW)
+        .and_then(|window| window.is_visible().ok())
+        .unwrap_or(false);
+    app.state::<Foreground>()
+        .0
+        .store(visible, Ordering::Relaxed);
+}
+
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    let WindowEvent::Focused(focused) = event else {
+        return;
+    };
+    if window.label() != MAIN_WINDOW {
+        return;
+    }
+    if let Some(foreground) = window.try_state::<Foreground>() {
+        foreground.0.store(*focused, Ordering::Relaxed);
+    }
+}
+
+/// The duty cycle to use now, given whether peers were seen recently.
+pub fn current(app: &AppHandle, peers_seen: bool) -> DutyCycle {
+    let mode = app.state::<SettingsState>().get().ble.power_mode;
+    let foreground = app.state::<Foreground>().0.load(Ordering::Relaxed);
+    duty_cycle(mode, foreground || peers_seen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_scanning_gets_slower_with_the_power_mode() {
+        let modes = [
+            PowerMode::Performance,
+            PowerMode::Balanced,
+            PowerMode::Saver,
+        ];
+        for pair in modes.windows(2) {
+            let (faster, slower) = (duty_cycle(pair[0], false), duty_cycle(pair[1], false));
+            let share = |c: DutyCycle| c.on.as_secs_f64() / (c.on + c.off).as_secs_f64();
+            assert!(share(faster) > share(slower));
+        }
+        for mode in modes {
+            assert!(duty_cycle(mode, true).off <= duty_cycle(mode, false).off);
+        }
+        assert_eq!(duty_cycle(PowerMode::Balanced, true).off, Duration::ZERO);
+    }
+}
@@ -0,0 +1,422 @@
+//! WebRTC data channel links to browser-only peers.
+//!
+//! A browser can't do BLE or open a TCP listener, but it can hold a data
+//! channel. The offer and answer travel over Nostr: the backend emits each
+//! as a [`TransportEvent::WebrtcSignal`] for the frontend to send to the
+//! peer's npub, and the frontend passes whatever it receives back to
+//! [`webrtc_signal`]. ICE candidates are gathered before the description
+//! goes out, so one message each way is enough.
+//!
+//! Once the channel is open it's an ordinary mesh link carrying the same
+//! packets as BLE, so private messages on it are Noise-encrypted end to end
+//! and relays only ever see the signaling.
+//!
+//! Every link costs a peer connection and STUN traffic, so offers are only
+//! taken from mutual favorites (the peers the router reaches over Nostr),
+//! at most [`MAX_PENDING_LINKS`] may be connecting at once, and a link whose
+//! channel isn't open within [`OPEN_TIMEOUT`] is dropped.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{AppHandle, Manager, State};
+use webrtc::api::APIBuilder;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use super::{Inbound, TransportEvent};
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::events;
+use crate::favorites;
+use crate::mesh;
+
+/// Link IDs of WebRTC links start with this, to tell them from BLE ones.
+pub const LINK_PREFIX: &str = "webrtc:";
+const CHANNEL_LABEL: &str = "bitchat";
+const STUN_SERVERS: &[&str] = &["stun:stun.l.google.com:19302"];
+/// Send whatever candidates we have after this long.
+const GATHER_TIMEOUT: Duration = Duration::from_secs(5);
+/// Links whose channel isn't open yet.
+pub const MAX_PENDING_LINKS: usize = 8;
+/// Drop a link whose channel isn't open after this long.
+pub const OPEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum SignalKind {
+    Offer,
+    Answer,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct WebrtcLink {
+    pub link_id: String,
+    pub npub: String,
+    /// Whether the data channel is open.
+    pub connected: bool,
+}
+
+struct Link {
+    npub: String,
+    connection: Arc<RTCPeerConnection>,
+    channel: Option<Arc<RTCDataChannel>>,
+    open: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+pub struct WebrtcState(Mutex<HashMap<String, Link>>);
+
+impl WebrtcState {
+    pub fn links(&self) -> Vec<WebrtcLink> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(link_id, link)| WebrtcLink {
+                link_id: link_id.clone(),
+                npub: link.npub.clone(),
+                connected: link.open.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    fn open_channels(&self, exclude: Option<&str>) -> Vec<Arc<RTCDataChannel>> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(link_id, link)| {
+                Some(link_id.as_str()) != exclude && link.open.load(Ordering::Relaxed)
+            })
+            .filter_map(|(_, link)| link.channel.clone())
+            .collect()
+    }
+
+    pub async fn send(&self, link_id: &str, data: &[u8]) -> AppResult<()> {
+        let channel = self
+            .0
+            .lock()
+            .unwrap()
+            .get(link_id)
+            .filter(|link| link.open.load(Ordering::Relaxed))
+            .and_then(|link| link.channel.clone())
+            .ok_or_else(|| AppError::new(ErrorCode::UnknownLink).with("link_id", link_id))?;
+        channel
+            .send(&Bytes::copy_from_slice(data))
+            .await
+            .map_err(AppError::platform)?;
+        Ok(())
+    }
+
+    /// Send `data` on every open channel except `exclude`.
+    pub async fn broadcast(&self, data: &[u8], exclude: Option<&str>) {
+        let data = Bytes::copy_from_slice(data);
+        for channel in self.open_channels(exclude) {
+            if let Err(e) = channel.send(&data).await {
+                tracing::debug!("WebRTC send failed: {}", e);
+            }
+        }
+    }
+}
+
+/// Create a peer connection for `link_id` and track it, unless too many
+/// links are connecting already.
+async fn connect(app: &AppHandle, link_id: &str, npub: &str) -> AppResult<Arc<RTCPeerConnection>> {
+    let config = RTCConfiguration {
+        ice_servers: vec![RTCIceServer {
+            urls: STUN_SERVERS.iter().map(|url| url.to_string()).collect(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let connection = APIBuilder::new()
+        .build()
+        .new_peer_connection(config)
+        .await
+        .map_err(AppError::platform)?;
+    let connection = Arc::new(connection);
+
+    // The answering side gets its channel from the offerer.
+    let (app_, link_id_) = (app.clone(), link_id.to_string());
+    connection.on_data_channel(Box::new(move |channel| {
+        attach(&app_, &link_id_, channel);
+        Box::pin(async {})
+    }));
+    let (app_, link_id_) = (app.clone(), link_id.to_string());
+    connection.on_peer_connection_state_change(Box::new(move |s| {
+        let (app, link_id) = (app_.clone(), link_id_.clone());
+        Box::pin(async move {
+            if matches!(
+                s,
+                RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed
+            ) {
+                close(&app, &link_id).await;
+            }
+        })
+    }));
+
+    let pending = {
+        let state = app.state::<WebrtcState>();
+        let mut links = state.0.lock().unwrap();
+        let pending = links
+            .values()
+            .filter(|link| !link.open.load(Ordering::Relaxed))
+            .count();
+        if pending < MAX_PENDING_LINKS {
+            links.insert(
+                link_id.to_string(),
+                Link {
+                    npub: npub.to_string(),
+                    connection: connection.clone(),
+                    channel: None,
+                    open: Arc::new(AtomicBool::new(false)),
+                },
+            );
+        }
+        pending
+    };
+    if pending >= MAX_PENDING_LINKS {
+        let _ = connection.close().await;
+        return Err(AppError::new(ErrorCode::TooManyPendingLinks).with("max", MAX_PENDING_LINKS));
+    }
+
+    let (app, link_id) = (app.clone(), link_id.to_string());
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(OPEN_TIMEOUT).await;
+        let open = app
+            .state::<WebrtcState>()
+            .0
+            .lock()
+            .unwrap()
+            .get(&link_id)
+            .map(|link| link.open.load(Ordering::Relaxed));
+        if open == Some(false) {
+            tracing::debug!("{} didn't open in time", link_id);
+            close(&app, &link_id).await;
+        }
+    });
+    Ok(connection)
+}
+
+/// Wire `channel` to the mesh as link `link_id`.
+fn attach(app: &AppHandle, link_id: &str, channel: Arc<RTCDataChannel>) {
+    let open = {
+        let state = app.state::<WebrtcState>();
+        let mut links = state.0.lock().unwrap();
+        let Some(link) = links.get_mut(link_id) else {
+            return;
+        };
+        link.channel = Some(channel.clone());
+        link.open.clone()
+    };
+    let inbound = mesh::inbound(app);
+
+    let (inbound_, link_id_, open_) = (inbound.clone(), link_id.to_string(), open.clone());
+    channel.on_open(Box::new(move || {
+        open_.store(true, Ordering::Relaxed);
+        let _ = inbound_.send(Inbound::LinkUp {
+            link_id: link_id_,
+            name: None,
+        });
+        Box::pin(async {})
+    }));
+    let (inbound_, link_id_) = (inbound.clone(), link_id.to_string());
+    channel.on_message(Box::new(move |message: DataChannelMessage| {
+        let _ = inbound_.send(Inbound::Frame {
+            link_id: link_id_.clone(),
+            data: message.data.to_vec(),
+        });
+        Box::pin(async {})
+    }));
+    let link_id = link_id.to_string();
+    channel.on_close(Box::new(move || {
+        if open.swap(false, Ordering::Relaxed) {
+            let _ = inbound.send(Inbound::LinkDown {
+                link_id: link_id.clone(),
+            });
+        }
+        Box::pin(async {})
+    }));
+}
+
+/// Apply `description` locally and return the SDP to send, candidates
+/// included.
+async fn describe(
+    connection: &RTCPeerConnection,
+    description: RTCSessionDescription,
+) -> AppResult<String> {
+    let mut gathered = connection.gathering_complete_promise().await;
+    connection
+        .set_local_description(description)
+        .await
+        .map_err(AppError::platform)?;
+    let _ = tokio::time::timeout(GATHER_TIMEOUT, gathered.recv()).await;
+    connection
+        .local_description()
+        .await
+        .map(|description| description.sdp)
+        .ok_or_else(|| AppError::platform("no local description"))
+}
+
+fn signal(app: &AppHandle, link_id: &str, npub: &str, kind: SignalKind, sdp: String) {
+    events::emit(
+        app,
+        TransportEvent::WebrtcSignal {
+            link_id: link_id.to_string(),
+            npub: npub.to_string(),
+            kind,
+            sdp,
+        },
+    );
+}
+
+async fn close(app: &AppHandle, link_id: &str) {
+    let link = app.state::<WebrtcState>().0.lock().unwrap().remove(link_id);
+    let Some(link) = link else {
+        return;
+    };
+    if link.open.swap(false, Ordering::Relaxed) {
+        let _ = mesh::inbound(app).send(Inbound::LinkDown {
+            link_id: link_id.to_string(),
+        });
+    }
+    if let Err(e) = link.connection.close().await {
+        tracing::debug!("failed to close {}: {}", link_id, e);
+    }
+}
+
+fn check_npub(npub: &str) -> AppResult<()> {
+    if favorites::is_npub(npub) {
+        Ok(())
+    } else {
+        Err(AppError::new(ErrorCode::InvalidArgument).with("npub", npub))
+    }
+}
+
+/// Offer a data channel to the Nostr user `npub`. The offer goes out as a
+/// [`TransportEvent::WebrtcSignal`]; returns the new link's ID.
+#[tauri::command]
+#[specta::specta]
+pub async fn webrtc_connect(app: AppHandle, npub: String) -> AppResult<String> {
+    check_npub(&npub)?;
+    let link_id = format!("{}{}", LINK_PREFIX, hex::encode(rand::random::<[u8; 8]>()));
+    let result = async {
+        let connection = connect(&app, &link_id, &npub).await?;
+        let channel = connection
+            .create_data_channel(CHANNEL_LABEL, None)
+            .await
+            .map_err(AppError::platform)?;
+        attach(&app, &link_id, channel);
+        let offer = connection
+            .create_offer(None)
+            .await
+            .map_err(AppError::platform)?;
+        describe(&connection, offer).await
+    }
+    .await;
+    match result {
+        Ok(sdp) => {
+            signal(&app, &link_id, &npub, SignalKind::Offer, sdp);
+            Ok(link_id)
+        }
+        Err(e) => {
+            close(&app, &link_id).await;
+            Err(e)
+        }
+    }
+}
+
+/// Hand over a signal that arrived from `npub` over Nostr. An offer from a
+/// mutual favorite opens a new link and emits the answer (others are
+/// ignored); an answer completes a link we offered.
+#[tauri::command]
+#[specta::specta]
+pub async fn webrtc_signal(
+    app: AppHandle,
+    link_id: String,
+    npub: String,
+    kind: SignalKind,
+    sdp: String,
+) -> AppResult<()> {
+    if !link_id.starts_with(LINK_PREFIX) {
+        return Err(AppError::new(ErrorCode::UnknownLink).with("link_id", link_id));
+    }
+    check_npub(&npub)?;
+    let existing = app
+        .state::<WebrtcState>()
+        .0
+        .lock()
+        .unwrap()
+        .get(&link_id)
+        .map(|link| (link.npub.clone(), link.connection.clone()));
+
+    match kind {
+        SignalKind::Offer => {
+            // Relays may deliver the same offer more than once.
+            if existing.is_some() {
+                return Ok(());
+            }
+            if !favorites::is_mutual_npub(&app, &npub) {
+                tracing::debug!("ignoring WebRTC offer from {}", npub);
+                return Ok(());
+            }
+            let result = async {
+                let connection = connect(&app, &link_id, &npub).await?;
+                let offer = RTCSessionDescription::offer(sdp).map_err(AppError::platform)?;
+                connection
+                    .set_remote_description(offer)
+                    .await
+                    .map_err(AppError::platform)?;
+                let answer = connection
+                    .create_answer(None)
+                    .await
+                    .map_err(AppError::platform)?;
+                describe(&connection, answer).await
+            }
+            .await;
+            match result {
+                Ok(sdp) => {
+                    signal(&app, &link_id, &npub, SignalKind::Answer, sdp);
+                    Ok(())
+                }
+                Err(e) => {
+                    close(&app, &link_id).await;
+                    Err(e)
+                }
+            }
+        }
+        SignalKind::Answer => {
+            let connection = existing
+                .filter(|(offered_to, _)| *offered_to == npub)
+                .map(|(_, connection)| connection)
+                .ok_or_else(|| AppError::new(ErrorCode::UnknownLink).with("link_id", &link_id))?;
+            let answer = RTCSessionDescription::answer(sdp).map_err(AppError::platform)?;
+            connection
+                .set_remote_description(answer)
+                .await
+                .map_err(AppError::platform)
+        }
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn webrtc_close(app: AppHandle, link_id: String) {
+    close(&app, &link_id).await;
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn webrtc_get_links(state: State<'_, WebrtcState>) -> Vec<WebrtcLink> {
+    state.links()
+}
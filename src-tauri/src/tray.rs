@@ -0,0 +1,145 @@
+//! System tray icon showing connection state and unread count.
+//!
+//! Relay connections are owned by the frontend, so it pushes connection
+//! changes through `tray_update`. The unread count comes from the message
+//! history ([`crate::storage`]), which refreshes it, and the dock/taskbar
+//! badge with it, as messages arrive or are read. Menu actions that need
+//! frontend work ("Go offline", "Panic wipe") are forwarded back as
+//! [`AppEvent`]s.
+
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::TrayIconBuilder;
+use tauri::{AppHandle, Manager, Wry};
+
+use crate::error::{AppError, AppResult};
+use crate::events::{self, AppEvent};
+use crate::notifications;
+use crate::windows;
+
+const TRAY_ID: &str = "main";
+
+const MENU_OPEN: &str = "open";
+const MENU_TOGGLE_ONLINE: &str = "toggle_online";
+const MENU_PANIC_WIPE: &str = "panic_wipe";
+const MENU_QUIT: &str = "quit";
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct TrayStatus {
+    pub connected: bool,
+    pub unread: u32,
+}
+
+pub struct TrayState {
+    status: Mutex<TrayStatus>,
+    toggle_item: MenuItem<Wry>,
+}
+
+pub fn setup(app: &AppHandle) -> tauri::Result<()> {
+    let open = MenuItem::with_id(app, MENU_OPEN, "Open", true, None::<&str>)?;
+    let toggle = MenuItem::with_id(app, MENU_TOGGLE_ONLINE, "Go online", true, None::<&str>)?;
+    let wipe = MenuItem::with_id(app, MENU_PANIC_WIPE, "Panic wipe", true, None::<&str>)?;
+    let quit = MenuItem::with_id(app, MENU_QUIT, "Quit", true, None::<&str>)?;
+    let menu = Menu::with_items(
+        app,
+        &[
+            &open,
+            &toggle,
+            &PredefinedMenuItem::separator(app)?,
+            &wipe,
+            &PredefinedMenuItem::separator(app)?,
+            &quit,
+        ],
+    )?;
+
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .menu(&menu)
+        .tooltip(tooltip(TrayStatus::default()))
+        .icon_as_template(true)
+        .on_menu_event(on_menu_event);
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app)?;
+
+    app.manage(TrayState {
+        status: Mutex::new(TrayStatus::default()),
+        toggle_item: toggle,
+    });
+    Ok(())
+}
+
+fn tooltip(status: TrayStatus) -> String {
+    let connection = if status.connected {
+        "Connected"
+    } else {
+        "Offline"
+    };
+    match status.unread {
+        0 => format!("BitChat - {}", connection),
+        n => format!("BitChat - {} - {} unread", connection, n),
+    }
+}
+
+pub fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window(windows::MAIN_WINDOW) {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+fn on_menu_event(app: &AppHandle, event: MenuEvent) {
+    match event.id().as_ref() {
+        MENU_OPEN => show_main_window(app),
+        MENU_TOGGLE_ONLINE => {
+            let connected = app.state::<TrayState>().status.lock().unwrap().connected;
+            events::emit(app, AppEvent::OnlineRequested { online: !connected });
+        }
+        MENU_PANIC_WIPE => events::emit(app, AppEvent::PanicWipeRequested),
+        MENU_QUIT => app.exit(0),
+        _ => {}
+    }
+}
+
+/// Apply `f` to the current status and refresh the tray, menu and unread
+/// badge to match.
+pub fn update_status(app: &AppHandle, f: impl FnOnce(&mut TrayStatus)) -> AppResult<()> {
+    // Messages may arrive before the tray is set up.
+    let Some(state) = app.try_state::<TrayState>() else {
+        return Ok(());
+    };
+    let status = {
+        let mut status = state.status.lock().unwrap();
+        f(&mut status);
+        *status
+    };
+
+    state
+        .toggle_item
+        .set_text(if status.connected {
+            "Go offline"
+        } else {
+            "Go online"
+        })
+        .map_err(AppError::platform)?;
+
+    if let Some(tray) = app.tray_by_id(TRAY_ID) {
+        tray.set_tooltip(Some(tooltip(status)))
+            .map_err(AppError::platform)?;
+        // Only shown next to the icon on macOS.
+        let title = (status.unread > 0).then(|| status.unread.to_string());
+        tray.set_title(title).map_err(AppError::platform)?;
+    }
+
+    notifications::set_badge(app, status.unread);
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn tray_update(app: AppHandle, connected: bool) -> AppResult<()> {
+    update_status(&app, |s| s.connected = connected)
+}
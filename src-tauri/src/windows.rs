@@ -0,0 +1,112 @@
+//! Conversations popped out into their own windows.
+//!
+//! Every window runs its own copy of the frontend, so the backend is the
+//! only place that knows which window shows which conversation. Events that
+//! must be handled exactly once for a conversation (e.g. a message to send)
+//! are routed through [`label_for_conversation`] instead of broadcast.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use tauri::{AppHandle, Manager, State, WebviewUrl, WebviewWindowBuilder, WindowEvent};
+
+use crate::error::{AppError, AppResult, ErrorCode};
+use crate::favorites;
+use crate::transport::router::Recipient;
+
+pub const MAIN_WINDOW: &str = "main";
+
+#[derive(Default)]
+pub struct WindowRegistry {
+    /// Conversation ID -> window label.
+    conversations: Mutex<HashMap<String, String>>,
+    next_id: AtomicU64,
+}
+
+/// Label of the window showing `conversation_id`, falling back to the main
+/// window when it isn't popped out.
+pub fn label_for_conversation(app: &AppHandle, conversation_id: &str) -> String {
+    app.state::<WindowRegistry>()
+        .conversations
+        .lock()
+        .unwrap()
+        .get(conversation_id)
+        .cloned()
+        .unwrap_or_else(|| MAIN_WINDOW.to_string())
+}
+
+/// Open `conversation_id` (the peer's ID, Noise key or npub) in a window of
+/// its own, or focus the one already showing it. Returns the window label.
+/// Async because building a window from a synchronous command deadlocks on
+/// Windows.
+#[tauri::command]
+#[specta::specta]
+pub async fn window_open_conversation(
+    app: AppHandle,
+    conversation_id: String,
+    title: Option<String>,
+    registry: State<'_, WindowRegistry>,
+) -> AppResult<String> {
+    // It ends up in the window's route, so nothing else gets through.
+    if !favorites::is_npub(&conversation_id) && conversation_id.parse::<Recipient>().is_err() {
+        return Err(AppError::new(ErrorCode::InvalidArgument).with("field", "conversation_id"));
+    }
+    // Claim the conversation before building, so a second call focuses
+    // this window instead of building another.
+    let label = {
+        let mut conversations = registry.conversations.lock().unwrap();
+        if let Some(label) = conversations.get(&conversation_id) {
+            // Not there yet if another call is still building it.
+            if let Some(window) = app.get_webview_window(label) {
+                window.set_focus().map_err(AppError::platform)?;
+            }
+            return Ok(label.clone());
+        }
+        let label = format!(
+            "conversation-{}",
+            registry.next_id.fetch_add(1, Ordering::Relaxed)
+        );
+        conversations.insert(conversation_id.clone(), label.clone());
+        label
+    };
+
+    let url = WebviewUrl::App(format!("private/{}", conversation_id).into());
+    let built = WebviewWindowBuilder::new(&app, &label, url)
+        .title(title.unwrap_or_else(|| "BitChat".to_string()))
+        .inner_size(480.0, 640.0)
+        .min_inner_size(400.0, 600.0)
+        .build();
+    let window = match built {
+        Ok(window) => window,
+        Err(e) => {
+            registry
+                .conversations
+                .lock()
+                .unwrap()
+                .remove(&conversation_id);
+            return Err(AppError::platform(e));
+        }
+    };
+
+    let handle = app.clone();
+    let window_label = label.clone();
+    window.on_window_event(move |event| {
+        if let WindowEvent::Destroyed = event {
+            let registry = handle.state::<WindowRegistry>();
+            let mut conversations = registry.conversations.lock().unwrap();
+            if conversations.get(&conversation_id) == Some(&window_label) {
+                conversations.remove(&conversation_id);
+            }
+        }
+    });
+
+    Ok(label)
+}
+
+/// Conversation ID -> window label for every popped-out conversation.
+#[tauri::command]
+#[specta::specta]
+pub fn window_list_conversations(registry: State<'_, WindowRegistry>) -> HashMap<String, String> {
+    registry.conversations.lock().unwrap().clone()
+}